@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+
+use rand::Rng;
+
+use crate::hidden_markov::HiddenMarkov;
+use crate::utils::{format_observed, split_tokens, START_TOKEN};
+
+/// Absolute discount subtracted from every seen transition count
+pub(crate) const DEFAULT_DISCOUNT: f64 = 0.75;
+
+/// Hidden markov model over token-level hidden contexts that backs off from
+/// order-N contexts to shorter ones when a context or transition is unseen
+///
+/// Seen transitions are discounted by `discount` and the reserved mass is
+/// redistributed over the next lower order (Katz backoff), ending at the
+/// unigram distribution of hidden states
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackoffHiddenMarkov {
+    pub markov_order: u32,
+    pub discount: f64,
+    /// Transition counts per context length; `hidden_counts[k - 1]` maps the
+    /// previous k hidden states (space separated, oldest first) to next state counts
+    pub hidden_counts: Vec<HashMap<String, HashMap<String, f64>>>,
+    pub unigram_probs: HashMap<String, f64>,
+    pub observed_probs: HashMap<String, HashMap<String, f64>>,
+}
+
+impl BackoffHiddenMarkov {
+    /// Trains transition counts for every order from `markov_order` down to 1
+    /// on line separated `observed:hidden` tokens
+    pub fn new(markov_order: u32, data: String) -> BackoffHiddenMarkov {
+        assert!(markov_order >= 1, "markov_order must be >= 1");
+        let mut model = BackoffHiddenMarkov {
+            markov_order,
+            discount: DEFAULT_DISCOUNT,
+            hidden_counts: vec![HashMap::new(); markov_order as usize],
+            unigram_probs: HashMap::new(),
+            observed_probs: HashMap::new(),
+        };
+
+        for line in data.split('\n') {
+            model.process_line(line);
+        }
+        model.normalize();
+
+        model
+    }
+
+    /// Use `discount` (between 0 and 1) instead of `DEFAULT_DISCOUNT`
+    pub fn with_discount(mut self, discount: f64) -> BackoffHiddenMarkov {
+        assert!(discount > 0.0 && discount < 1.0, "discount must be between 0 and 1");
+        self.discount = discount;
+        self
+    }
+
+    /// True when no transitions were learned, e.g. from empty training data
+    pub fn is_empty(&self) -> bool {
+        self.unigram_probs.is_empty()
+    }
+
+    /// Lines with an unclosed quote are skipped; a token without a colon is skipped
+    /// and ends the history chain like a line break
+    fn process_line(&mut self, line: &str) {
+        let mut history = vec![START_TOKEN.to_owned(); self.markov_order as usize];
+        for token in split_tokens(line).unwrap_or_default() {
+            let (observed, hidden) = match HiddenMarkov::split_token(&token) {
+                Some(parts) => parts,
+                None => {
+                    history = vec![START_TOKEN.to_owned(); self.markov_order as usize];
+                    continue
+                },
+            };
+            for order in 1..=self.markov_order as usize {
+                let context = history[history.len() - order..].join(" ");
+                *self.hidden_counts[order - 1].entry(context).or_default()
+                    .entry(hidden.to_owned()).or_insert(0.0) += 1.0;
+            }
+            *self.unigram_probs.entry(hidden.to_owned()).or_insert(0.0) += 1.0;
+            *self.observed_probs.entry(hidden.to_owned()).or_default()
+                .entry(observed).or_insert(0.0) += 1.0;
+
+            history.remove(0);
+            history.push(hidden);
+        }
+    }
+
+    fn normalize(&mut self) {
+        let total: f64 = self.unigram_probs.values().sum();
+        for prob in self.unigram_probs.values_mut() {
+            *prob /= total;
+        }
+        for emissions in self.observed_probs.values_mut() {
+            let sum: f64 = emissions.values().sum();
+            for prob in emissions.values_mut() {
+                *prob /= sum;
+            }
+        }
+    }
+
+    /// Probability of `next` following `context` (previous hidden states, oldest first),
+    /// backing off to shorter contexts when the full context or transition is unseen
+    ///
+    /// Only the last `markov_order` states of `context` are used
+    pub fn transition_probability(&self, context: &[&str], next: &str) -> f64 {
+        let context = &context[context.len().saturating_sub(self.markov_order as usize)..];
+        if context.is_empty() {
+            return *self.unigram_probs.get(next).unwrap_or(&0.0);
+        }
+        let lower_context = &context[1..];
+        let counts = match self.hidden_counts[context.len() - 1].get(&context.join(" ")) {
+            Some(counts) => counts,
+            None => return self.transition_probability(lower_context, next),
+        };
+
+        // Nothing is left to back off to when every hidden state was seen in this context
+        let discount = if counts.len() >= self.unigram_probs.len() { 0.0 } else { self.discount };
+        let total: f64 = counts.values().sum();
+        match counts.get(next) {
+            Some(count) => (count - discount) / total,
+            None => {
+                let seen_lower_mass: f64 = counts.keys()
+                    .map(|seen| self.transition_probability(lower_context, seen))
+                    .sum();
+                if seen_lower_mass >= 1.0 { return 0.0 }
+                let reserved_mass = discount * counts.len() as f64 / total;
+                reserved_mass * self.transition_probability(lower_context, next) / (1.0 - seen_lower_mass)
+            }
+        }
+    }
+
+    /// Calculate the probability to generate a given sequence of `observed:hidden` tokens, or
+    /// `None` if the model is empty or the sequence has an unclosed quote or a token without a colon
+    pub fn get_sequence_probability(&self, sequence: &str) -> Option<f64> {
+        if self.is_empty() { return None }
+        let mut history = vec![START_TOKEN.to_owned(); self.markov_order as usize];
+        let mut product = 1.0;
+        for token in split_tokens(sequence).ok()? {
+            let (observed, hidden) = HiddenMarkov::split_token(&token)?;
+            let context: Vec<&str> = history.iter().map(|state| state.as_str()).collect();
+            product *= self.transition_probability(&context, &hidden);
+            product *= self.observed_probs.get(&hidden).and_then(|emissions| emissions.get(&observed)).unwrap_or(&0.0);
+
+            history.remove(0);
+            history.push(hidden);
+        }
+        Some(product)
+    }
+
+    /// Samples a sequence of `length` `observed:hidden` tokens
+    pub fn sample_sequence(&self, length: usize) -> String {
+        let mut rng = rand::thread_rng();
+        let mut sequence = vec![];
+        if self.is_empty() { return String::new() }
+        let mut history = vec![START_TOKEN.to_owned(); self.markov_order as usize];
+        for _ in 0..length {
+            let hidden = self.next_token(&history, &mut rng).to_owned();
+            let observed = BackoffHiddenMarkov::sample(&self.observed_probs[&hidden], &mut rng);
+            sequence.push(format!("{}:{}", format_observed(observed, true), hidden));
+
+            history.remove(0);
+            history.push(hidden);
+        }
+        sequence.join(" ")
+    }
+
+    /// Draws the next hidden state following `history` from the backed-off distribution
+    fn next_token<R: Rng>(&self, history: &[String], rng: &mut R) -> &str {
+        let context: Vec<&str> = history.iter().map(|state| state.as_str()).collect();
+        let probs: HashMap<String, f64> = self.unigram_probs.keys()
+            .map(|hidden| (hidden.to_owned(), self.transition_probability(&context, hidden)))
+            .collect();
+        let hidden = BackoffHiddenMarkov::sample(&probs, rng);
+        self.unigram_probs.get_key_value(hidden).map(|(key, _)| key.as_str()).unwrap_or("")
+    }
+
+    fn sample<'a, R: Rng>(probs: &'a HashMap<String, f64>, rng: &mut R) -> &'a str {
+        let total: f64 = probs.values().sum();
+        let rand_value: f64 = rng.gen::<f64>() * total;
+        let mut sum = 0.0;
+        for (token, prob) in probs {
+            sum += prob;
+            if sum > rand_value {
+                return token;
+            }
+        }
+        probs.keys().next().map(|token| token.as_str()).unwrap_or("")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_data() -> String {
+        String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        )
+    }
+
+    #[test]
+    fn train_backoff_hidden_markov() {
+        let model = BackoffHiddenMarkov::new(2, test_data());
+        assert_eq!(2, model.hidden_counts.len());
+        assert_eq!(3.0, model.hidden_counts[0]["VBZ"]["NN"]);
+        assert_eq!(2.0, model.hidden_counts[1]["RB VBZ"]["NN"]);
+        assert_eq!(4.0, model.hidden_counts[1][format!("{} {}", START_TOKEN, START_TOKEN).as_str()]["NNP"]);
+        assert_eq!(0.6, model.observed_probs["NNP"]["Mary"]);
+        assert_eq!(5.0 / 15.0, model.unigram_probs["NNP"]);
+    }
+
+    #[test]
+    fn malformed_token_backoff_hidden_markov() {
+        let model = BackoffHiddenMarkov::new(2, String::from("Ted:NNP hello now:RB likes:VBZ"));
+        // The chain restarts after "hello", so NNP is never followed by RB
+        assert_eq!(None, model.hidden_counts[0].get("NNP"));
+        assert_eq!(1.0, model.hidden_counts[0][START_TOKEN]["RB"]);
+        assert_eq!(2.0, model.hidden_counts[1][format!("{} {}", START_TOKEN, START_TOKEN).as_str()].values().sum::<f64>());
+        assert_eq!(1.0, model.hidden_counts[1][format!("{} RB", START_TOKEN).as_str()]["VBZ"]);
+        assert_eq!(None, model.get_sequence_probability("Ted:NNP hello now:RB"));
+    }
+
+    #[test]
+    fn unseen_context_backs_off() {
+        let model = BackoffHiddenMarkov::new(2, test_data());
+        // "NN VBZ" never occurs, so the order-1 "VBZ" distribution is used
+        let backed_off = model.transition_probability(&["NN", "VBZ"], "NN");
+        assert!(backed_off > 0.0);
+        assert_eq!(model.transition_probability(&["VBZ"], "NN"), backed_off);
+    }
+
+    #[test]
+    fn unseen_transition_backs_off() {
+        let model = BackoffHiddenMarkov::new(2, test_data());
+        // "RB VBZ" was only ever followed by NN
+        assert!(model.transition_probability(&["RB", "VBZ"], "NNP") > 0.0);
+        assert!(model.transition_probability(&["RB", "VBZ"], "NN") < 1.0);
+    }
+
+    #[test]
+    fn transition_probabilities_sum_to_one() {
+        let model = BackoffHiddenMarkov::new(2, test_data());
+        for context in [["RB", "VBZ"], ["NNP", "RB"], ["NN", "VBZ"], [START_TOKEN, "NNP"]] {
+            let sum: f64 = model.unigram_probs.keys()
+                .map(|hidden| model.transition_probability(&context, hidden))
+                .sum();
+            assert!((sum - 1.0).abs() < 1e-9, "{:?} sums to {}", context, sum);
+        }
+    }
+
+    #[test]
+    fn backoff_sequence_probability() {
+        let model = BackoffHiddenMarkov::new(2, test_data());
+        assert!(model.get_sequence_probability("Mary:NNP likes:VBZ red:NN").unwrap() > 0.0);
+        // Never seen at order 2, but every step can back off
+        assert!(model.get_sequence_probability("Mary:NNP red:NN likes:VBZ red:NN").unwrap() > 0.0);
+        assert_eq!(Some(0.0), model.get_sequence_probability("Bob:NNP likes:VBZ red:NN"));
+        assert_eq!(None, BackoffHiddenMarkov::new(2, String::new()).get_sequence_probability("Mary:NNP"));
+    }
+
+    #[test]
+    fn backoff_sample_sequence() {
+        let model = BackoffHiddenMarkov::new(2, test_data());
+        let sequence = model.sample_sequence(6);
+        assert_eq!(6, sequence.split_whitespace().count());
+        assert!(model.get_sequence_probability(&sequence).unwrap() > 0.0);
+    }
+}
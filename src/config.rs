@@ -1,16 +1,103 @@
 use std::fs;
+use serde::{Deserialize, Deserializer};
+use crate::error::ChmmError;
 
 #[derive(Debug, serde::Deserialize, PartialEq)]
 pub struct Config {
-    training_file: String,
-    markov_order: String,
-    constraints: String,
+    pub training_file: String,
+    #[serde(deserialize_with = "string_or_number")]
+    pub markov_order: String,
+    pub constraints: String,
+    /// Length of generated sequences when no constraints are given
+    #[serde(default)]
+    pub sequence_length: Option<usize>,
+    /// Number of sequences to generate
+    #[serde(default)]
+    pub num_of_sequences: Option<u32>,
+    /// File to write generated sequences to instead of printing them
+    #[serde(default)]
+    pub output_file: Option<String>,
 }
 
 impl Config {
-    pub fn parse(config_file: &String) -> (String, String, String){
-        let config_str = fs::read_to_string(config_file).expect("Unable to read config file");
-        let yaml: Config = serde_yaml::from_str(&config_str).unwrap();
-        return (yaml.training_file, yaml.constraints, yaml.markov_order)
+    /// Reads a YAML config, or a TOML config when the file ends in `.toml`
+    pub fn parse(config_file: &String) -> Result<Config, ChmmError> {
+        let config_str = fs::read_to_string(config_file)
+            .map_err(|e| ChmmError::InvalidConfig(format!("unable to read config file {}: {}", config_file, e)))?;
+        if config_file.ends_with(".toml") {
+            toml::from_str(&config_str)
+                .map_err(|e| ChmmError::InvalidConfig(format!("malformed TOML in {}: {}", config_file, e)))
+        } else {
+            serde_yaml::from_str(&config_str)
+                .map_err(|e| ChmmError::InvalidConfig(format!("malformed YAML in {}: {}", config_file, e)))
+        }
     }
-}
\ No newline at end of file
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum StringOrNumber {
+    String(String),
+    Number(i64),
+}
+
+// TOML keeps integers and strings apart, while YAML reads `markov_order : 1` as a string
+fn string_or_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Ok(match StringOrNumber::deserialize(deserializer)? {
+        StringOrNumber::String(value) => value,
+        StringOrNumber::Number(value) => value.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_yaml_config() {
+        let config = Config::parse(&String::from("config.yaml")).unwrap();
+        assert_eq!("data/small_test.txt", config.training_file);
+        assert_eq!("1", config.markov_order);
+        assert_eq!("SW(t):NC\nNC*2\nred:NC\n", config.constraints);
+        assert_eq!(None, config.sequence_length);
+        assert_eq!(None, config.num_of_sequences);
+        assert_eq!(None, config.output_file);
+    }
+
+    #[test]
+    fn parse_full_config() {
+        let config_file = std::env::temp_dir().join(format!("chmm_full_config_{}.yaml", std::process::id()));
+        fs::write(&config_file, "training_file : \"data/small_test.txt\"\nmarkov_order : 2\nconstraints : \"\"\n\
+                                 sequence_length : 6\nnum_of_sequences : 3\noutput_file : \"out.txt\"\n").unwrap();
+        let config = Config::parse(&config_file.to_str().unwrap().to_string());
+        fs::remove_file(&config_file).unwrap();
+        let config = config.unwrap();
+        assert_eq!(Some(6), config.sequence_length);
+        assert_eq!(Some(3), config.num_of_sequences);
+        assert_eq!(Some(String::from("out.txt")), config.output_file);
+    }
+
+    #[test]
+    fn parse_toml_config() {
+        let toml_config = Config::parse(&String::from("data/test_config.toml")).unwrap();
+        let yaml_config = Config::parse(&String::from("config.yaml")).unwrap();
+        assert_eq!(yaml_config, toml_config);
+    }
+
+    #[test]
+    fn parse_malformed_toml_config() {
+        let config_file = std::env::temp_dir().join(format!("chmm_config_{}.toml", std::process::id()));
+        fs::write(&config_file, "training_file = \"data/small_test.txt\"\nmarkov_order = ").unwrap();
+        let result = Config::parse(&config_file.to_str().unwrap().to_string());
+        fs::remove_file(&config_file).unwrap();
+        match result {
+            Err(ChmmError::InvalidConfig(message)) => assert!(message.starts_with("malformed TOML")),
+            other => panic!("expected a TOML error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_missing_config() {
+        assert!(Config::parse(&String::from("data/does_not_exist.yaml")).is_err());
+    }
+}
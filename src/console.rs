@@ -1,57 +1,400 @@
-use clap::{Arg, App};
+use std::process;
+use clap::{Arg, App, ArgMatches};
 use crate::config::Config;
+use crate::error::ChmmError;
+
+/// How much diagnostic output is written to stderr
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+/// Which part of the pipeline to run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Train and generate in a single run
+    Run,
+    /// Train a model and write it to the model file
+    Train,
+    /// Load the model file and generate constrained sequences from it
+    Generate,
+    /// Print statistics about the training data
+    Stats,
+}
 
 pub struct Args {
+    pub command: Command,
     pub config_file: String,
+    pub model_file: String,
     pub training_file: String,
+    pub training_dir: String,
     pub constraint_string: String,
+    pub sequence_length: Option<usize>,
     pub markov_order: u32,
     pub num_of_sequences: u32,
     pub output_file: String,
+    pub output_format: String,
+    /// Write the output file in chunks of this many sequences, recording progress after each
+    pub checkpoint_every: Option<usize>,
+    /// Continue a checkpointed run from its last recorded progress
+    pub resume: bool,
+    /// Check the config, training data and constraints instead of running the command
+    pub validate: bool,
+    pub verbosity: Verbosity,
 }
 
 impl Args {
     pub fn new() -> Args {
-        let matches = App::new("Constrained Hidden Markov Model")
+        Args::from_matches(Args::app().get_matches()).unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            process::exit(2)
+        })
+    }
+
+    pub fn parse_from<I, T>(args: I) -> Result<Args, ChmmError>
+        where I: IntoIterator<Item = T>, T: Into<std::ffi::OsString> + Clone {
+        Args::from_matches(Args::app().get_matches_from(args))
+    }
+
+    fn app() -> App<'static> {
+        App::new("Constrained Hidden Markov Model")
             .about("Generates constrained sequences")
-            .arg(Arg::with_name("training_file")
-                .short('f')
-                .long("file")
-                .takes_value(true)
-                .help("Training file path"))
+            .args(Args::training_args())
+            .args(Args::generation_args())
             .arg(Arg::with_name("config_file")
                 .short('c')
                 .long("config")
                 .takes_value(true)
-                .help("YAML config file path"))
-            .arg(Arg::with_name("markov_order")
-                .short('m')
-                .long("order")
+                .global(true)
+                .help("YAML or TOML config file path"))
+            .arg(Arg::with_name("validate")
+                .long("validate")
+                .global(true)
+                .help("Check that the config, training data and constraints can be used, then exit"))
+            .arg(Arg::with_name("quiet")
+                .short('q')
+                .long("quiet")
+                .global(true)
+                .conflicts_with("verbose")
+                .help("Suppress diagnostic output"))
+            .arg(Arg::with_name("verbose")
+                .short('v')
+                .long("verbose")
+                .global(true)
+                .help("Print additional diagnostic output"))
+            .subcommand(App::new("train")
+                .about("Trains a model and writes it to a model file")
+                .arg(Args::model_arg())
+                .args(Args::training_args()))
+            .subcommand(App::new("generate")
+                .about("Generates constrained sequences from a trained model file")
+                .arg(Args::model_arg())
+                .args(Args::generation_args()))
+            .subcommand(App::new("stats")
+                .about("Prints statistics about the training data")
+                .args(Args::data_args()))
+    }
+
+    fn training_args() -> Vec<Arg<'static>> {
+        let mut args = Args::data_args();
+        args.push(Arg::with_name("markov_order")
+            .short('m')
+            .long("order")
+            .takes_value(true)
+            .help("Markov order"));
+        args
+    }
+
+    fn data_args() -> Vec<Arg<'static>> {
+        vec![
+            Arg::with_name("training_file")
+                .short('f')
+                .long("file")
+                .takes_value(true)
+                .help("Training file path"),
+            Arg::with_name("training_dir")
+                .short('d')
+                .long("dir")
                 .takes_value(true)
-                .help("Markov order"))
-            .arg(Arg::with_name("sequences")
+                .help("Directory of .txt training files (overrides the training file)"),
+        ]
+    }
+
+    fn generation_args() -> Vec<Arg<'static>> {
+        vec![
+            Arg::with_name("sequences")
                 .short('n')
                 .long("sequences")
                 .takes_value(true)
-                .help("The number of sequences to generate"))
-            .arg(Arg::with_name("output_file")
+                .help("The number of sequences to generate"),
+            Arg::with_name("sequence_length")
+                .short('l')
+                .long("length")
+                .takes_value(true)
+                .help("Length of the generated sequences when no constraints are given; otherwise it must equal the number of constraints"),
+            Arg::with_name("output_file")
                 .short('o')
                 .long("out")
                 .takes_value(true)
-                .help("Output file to write sequences to"))
-            .get_matches();
+                .help("Output file to write sequences to"),
+            Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .possible_values(["text", "json"])
+                .help("Output format of the generated sequences"),
+            Arg::with_name("checkpoint")
+                .long("checkpoint")
+                .takes_value(true)
+                .requires("output_file")
+                .help("Write the output file every N sequences so an interrupted run can be resumed"),
+            Arg::with_name("resume")
+                .long("resume")
+                .requires("checkpoint")
+                .help("Continue an interrupted checkpointed run from its last checkpoint"),
+        ]
+    }
+
+    fn model_arg() -> Arg<'static> {
+        Arg::with_name("model_file")
+            .long("model")
+            .takes_value(true)
+            .required(true)
+            .help("Serialized model file path")
+    }
+
+    fn from_matches(matches: ArgMatches) -> Result<Args, ChmmError> {
+        let (command, matches) = match matches.subcommand() {
+            Some(("train", sub_matches)) => (Command::Train, sub_matches),
+            Some(("generate", sub_matches)) => (Command::Generate, sub_matches),
+            Some(("stats", sub_matches)) => (Command::Stats, sub_matches),
+            _ => (Command::Run, &matches),
+        };
 
         let config_file = matches.value_of("config_file").unwrap_or("config.yaml").to_string();
-        let (training_file, constraint_string, markov_order) = Config::parse(&config_file);
+        let config = Config::parse(&config_file)?;
 
         let args = Args {
+            command,
             config_file,
-            training_file: matches.value_of("training_file").unwrap_or(&training_file).to_string(),
-            constraint_string,
-            markov_order: matches.value_of("markov_order").unwrap_or(&markov_order).parse::<u32>().unwrap(),
-            num_of_sequences: matches.value_of("sequences").unwrap_or("10").parse::<u32>().unwrap(),
-            output_file: matches.value_of("output_file").unwrap_or("").to_string(),
+            model_file: value_of(matches, "model_file").unwrap_or("").to_string(),
+            training_file: value_of(matches, "training_file").unwrap_or(&config.training_file).to_string(),
+            training_dir: value_of(matches, "training_dir").unwrap_or("").to_string(),
+            constraint_string: config.constraints,
+            sequence_length: match value_of(matches, "sequence_length") {
+                Some(length) => Some(parse_positive(length, "sequence_length")?),
+                None => config.sequence_length.map(|length| parse_positive(&length.to_string(), "sequence_length")).transpose()?,
+            },
+            markov_order: parse_positive(value_of(matches, "markov_order").unwrap_or(&config.markov_order), "markov_order")?,
+            num_of_sequences: match value_of(matches, "sequences") {
+                Some(sequences) => parse_positive(sequences, "sequences")?,
+                None => config.num_of_sequences.map(|sequences| parse_positive(&sequences.to_string(), "num_of_sequences")).transpose()?.unwrap_or(10),
+            },
+            output_file: value_of(matches, "output_file").map(String::from).or(config.output_file).unwrap_or_default(),
+            output_format: value_of(matches, "format").unwrap_or("text").to_string(),
+            checkpoint_every: match value_of(matches, "checkpoint") {
+                Some(every) => Some(parse_positive(every, "checkpoint")?),
+                None => None,
+            },
+            resume: matches.try_contains_id("resume").unwrap_or(false) && matches.is_present("resume"),
+            validate: matches.is_present("validate"),
+            verbosity: if matches.is_present("quiet") {
+                Verbosity::Quiet
+            } else if matches.is_present("verbose") {
+                Verbosity::Verbose
+            } else {
+                Verbosity::Normal
+            },
         };
-        return args
+        if args.checkpoint_every.is_some() && args.output_format == "json" {
+            return Err(ChmmError::InvalidArgument(String::from("checkpoint only supports text output")));
+        }
+        Ok(args)
+    }
+}
+
+fn parse_positive<T: std::str::FromStr + Default + PartialEq>(value: &str, name: &str) -> Result<T, ChmmError> {
+    match value.trim().parse::<T>() {
+        Ok(parsed) if parsed != T::default() => Ok(parsed),
+        _ => Err(ChmmError::InvalidArgument(format!("{} must be a positive integer, got \"{}\"", name, value))),
+    }
+}
+
+fn value_of<'a>(matches: &'a ArgMatches, name: &str) -> Option<&'a str> {
+    // Subcommands only define a subset of the arguments
+    match matches.try_contains_id(name) {
+        Ok(true) => matches.value_of(name),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn default_verbosity() {
+        let args = Args::parse_from(["constrained_hmm"]).unwrap();
+        assert_eq!(Verbosity::Normal, args.verbosity);
+    }
+
+    #[test]
+    fn quiet_flag() {
+        let args = Args::parse_from(["constrained_hmm", "--quiet"]).unwrap();
+        assert_eq!(Verbosity::Quiet, args.verbosity);
+        let args = Args::parse_from(["constrained_hmm", "-q"]).unwrap();
+        assert_eq!(Verbosity::Quiet, args.verbosity);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn verbose_flag() {
+        let args = Args::parse_from(["constrained_hmm", "--verbose"]).unwrap();
+        assert_eq!(Verbosity::Verbose, args.verbosity);
+    }
+
+    #[test]
+    fn no_subcommand_runs_everything() {
+        let args = Args::parse_from(["constrained_hmm", "-f", "data/small_test.txt", "-n", "3"]).unwrap();
+        assert_eq!(Command::Run, args.command);
+        assert_eq!("data/small_test.txt", args.training_file);
+        assert_eq!(3, args.num_of_sequences);
+    }
+
+    #[test]
+    fn train_subcommand() {
+        let args = Args::parse_from(["constrained_hmm", "train", "--model", "model.json", "-m", "2", "-q"]).unwrap();
+        assert_eq!(Command::Train, args.command);
+        assert_eq!("model.json", args.model_file);
+        assert_eq!(2, args.markov_order);
+        assert_eq!(Verbosity::Quiet, args.verbosity);
+    }
+
+    #[test]
+    fn generate_subcommand() {
+        let args = Args::parse_from(["constrained_hmm", "generate", "--model", "model.json", "-n", "5", "--format", "json"]).unwrap();
+        assert_eq!(Command::Generate, args.command);
+        assert_eq!("model.json", args.model_file);
+        assert_eq!(5, args.num_of_sequences);
+        assert_eq!("json", args.output_format);
+    }
+
+    #[test]
+    fn stats_subcommand() {
+        let args = Args::parse_from(["constrained_hmm", "stats", "-f", "data/small_test.txt"]).unwrap();
+        assert_eq!(Command::Stats, args.command);
+        assert_eq!("data/small_test.txt", args.training_file);
+        assert!(Args::app().try_get_matches_from(["constrained_hmm", "stats", "-m", "2"]).is_err());
+    }
+
+    #[test]
+    fn subcommands_require_model() {
+        assert!(Args::app().try_get_matches_from(["constrained_hmm", "train"]).is_err());
+        assert!(Args::app().try_get_matches_from(["constrained_hmm", "generate"]).is_err());
+    }
+
+    #[test]
+    fn generate_rejects_training_args() {
+        let result = Args::app().try_get_matches_from(["constrained_hmm", "generate", "--model", "model.json", "-m", "2"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn sequence_length_override() {
+        let args = Args::parse_from(["constrained_hmm"]).unwrap();
+        assert_eq!(None, args.sequence_length);
+        let args = Args::parse_from(["constrained_hmm", "--length", "6"]).unwrap();
+        assert_eq!(Some(6), args.sequence_length);
+        let args = Args::parse_from(["constrained_hmm", "generate", "--model", "model.json", "-l", "3"]).unwrap();
+        assert_eq!(Some(3), args.sequence_length);
+    }
+
+    #[test]
+    fn invalid_markov_order() {
+        let result = Args::parse_from(["constrained_hmm", "--order", "abc"]);
+        assert_eq!(Some(ChmmError::InvalidArgument(String::from("markov_order must be a positive integer, got \"abc\""))), result.err());
+        assert!(Args::parse_from(["constrained_hmm", "--order=-1"]).is_err());
+    }
+
+    #[test]
+    fn zero_markov_order() {
+        let result = Args::parse_from(["constrained_hmm", "--order", "0"]);
+        assert_eq!(Some(ChmmError::InvalidArgument(String::from("markov_order must be a positive integer, got \"0\""))), result.err());
+    }
+
+    #[test]
+    fn invalid_sequences_and_length() {
+        assert!(Args::parse_from(["constrained_hmm", "-n", "ten"]).is_err());
+        assert!(Args::parse_from(["constrained_hmm", "-l", "0"]).is_err());
+        assert_eq!(2, Args::parse_from(["constrained_hmm", "--order", "2"]).unwrap().markov_order);
+    }
+
+    #[test]
+    fn validate_flag() {
+        assert!(!Args::parse_from(["constrained_hmm"]).unwrap().validate);
+        assert!(Args::parse_from(["constrained_hmm", "--validate"]).unwrap().validate);
+        assert!(Args::parse_from(["constrained_hmm", "stats", "--validate"]).unwrap().validate);
+    }
+
+    #[test]
+    fn quiet_conflicts_with_verbose() {
+        let result = Args::app().try_get_matches_from(["constrained_hmm", "--quiet", "--verbose"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn config_fields_with_overrides() {
+        let config_file = std::env::temp_dir().join(format!("chmm_args_config_{}.yaml", std::process::id()));
+        fs::write(&config_file, "training_file : \"data/small_test.txt\"\nmarkov_order : 2\nconstraints : \"\"\n\
+                                 sequence_length : 6\nnum_of_sequences : 3\noutput_file : \"out.txt\"\n").unwrap();
+        let config_file = config_file.to_str().unwrap();
+
+        let args = Args::parse_from(["constrained_hmm", "-c", config_file]);
+        let overridden = Args::parse_from(["constrained_hmm", "-c", config_file, "-m", "1", "-l", "4", "-n", "7", "-o", "cli.txt"]);
+        fs::remove_file(config_file).unwrap();
+
+        let args = args.unwrap();
+        assert_eq!(2, args.markov_order);
+        assert_eq!(Some(6), args.sequence_length);
+        assert_eq!(3, args.num_of_sequences);
+        assert_eq!("out.txt", args.output_file);
+
+        let overridden = overridden.unwrap();
+        assert_eq!(1, overridden.markov_order);
+        assert_eq!(Some(4), overridden.sequence_length);
+        assert_eq!(7, overridden.num_of_sequences);
+        assert_eq!("cli.txt", overridden.output_file);
+    }
+
+    #[test]
+    fn zero_config_values() {
+        let config_file = std::env::temp_dir().join(format!("chmm_args_zero_config_{}.yaml", std::process::id()));
+        fs::write(&config_file, "training_file : \"data/small_test.txt\"\nmarkov_order : 1\nconstraints : \"\"\n\
+                                 sequence_length : 0\nnum_of_sequences : 0\n").unwrap();
+        let config_file = config_file.to_str().unwrap();
+
+        let args = Args::parse_from(["constrained_hmm", "-c", config_file]);
+        let length_given = Args::parse_from(["constrained_hmm", "-c", config_file, "-l", "4"]);
+        let both_given = Args::parse_from(["constrained_hmm", "-c", config_file, "-l", "4", "-n", "2"]);
+        fs::remove_file(config_file).unwrap();
+
+        assert_eq!(Some(ChmmError::InvalidArgument(String::from("sequence_length must be a positive integer, got \"0\""))), args.err());
+        assert_eq!(Some(ChmmError::InvalidArgument(String::from("num_of_sequences must be a positive integer, got \"0\""))), length_given.err());
+        assert_eq!(2, both_given.unwrap().num_of_sequences);
+    }
+
+    #[test]
+    fn checkpoint_flags() {
+        let args = Args::parse_from(["constrained_hmm", "-o", "out.txt", "--checkpoint", "100", "--resume"]).unwrap();
+        assert_eq!(Some(100), args.checkpoint_every);
+        assert!(args.resume);
+        let args = Args::parse_from(["constrained_hmm"]).unwrap();
+        assert_eq!(None, args.checkpoint_every);
+        assert!(!args.resume);
+
+        assert!(Args::app().try_get_matches_from(["constrained_hmm", "--checkpoint", "100"]).is_err());
+        assert!(Args::app().try_get_matches_from(["constrained_hmm", "-o", "out.txt", "--resume"]).is_err());
+        assert!(Args::parse_from(["constrained_hmm", "-o", "out.json", "--checkpoint", "100", "--format", "json"]).is_err());
+    }
+}
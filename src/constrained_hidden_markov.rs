@@ -1,18 +1,108 @@
-use std::collections::{HashMap, HashSet};
-use rand::Rng;
-use crate::hidden_markov::HiddenMarkov;
-use crate::constraints::Constraint;
+use std::collections::HashSet;
+use std::mem::size_of;
+use std::ops::Range;
+use std::time::{Duration, Instant};
+use rand::{Rng, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use rand::seq::SliceRandom;
+use rayon::prelude::*;
+use crate::hidden_markov::{HiddenMarkov, COMPARE_TOLERANCE};
+use crate::constraints::{describe, Constraint, JointConstraint, SequenceConstraint};
 use crate::constraints::empty_constraint::EmptyConstraint;
-use crate::utils::START_TOKEN;
+use crate::error::ChmmError;
+use crate::mixed_order::MixedOrderModel;
+use crate::utils::{format_observed, split_tokens, ProbMap, END_TOKEN, START_TOKEN};
+
+/// Probabilities of each next (or emitted) state, keyed by the previous (or emitting) state
+type ProbMatrix = ProbMap<String, ProbMap<String, f64>>;
+
+/// Upper bound on the number of paths walked when enumerating a model's support
+const ENUMERATION_LIMIT: usize = 100_000;
+
+/// What to do when a sampled path reaches a state with no surviving successor,
+/// which training normally prevents but e.g. partial pruning can leave behind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeadPathPolicy {
+    /// Return the partial sequence sampled so far
+    Truncate,
+    /// Sample again up to the given number of times before returning an error
+    Retry(usize),
+    /// Return an error
+    Error,
+}
+
+/// Sizes of the per-position hidden and observed matrices
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChmmStats {
+    pub outer_keys: usize,
+    pub inner_entries: usize,
+    /// Inner entries that were not zeroed out by pruning
+    pub nonzero_entries: usize,
+    /// Rough heap estimate of keys, values and map entries
+    pub estimated_bytes: usize,
+}
+
+/// Delimiters used when formatting generated sequences
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SequenceFormat {
+    /// Placed between tokens
+    pub token_sep: String,
+    /// Placed between the observed word and hidden state of a token
+    pub field_sep: String,
+    /// Emit only the observed words when false
+    pub include_hidden: bool,
+}
+
+impl Default for SequenceFormat {
+    /// The `observed:hidden` tokens separated by spaces produced by `sample_sequence`
+    fn default() -> SequenceFormat {
+        SequenceFormat {
+            token_sep: String::from(" "),
+            field_sep: String::from(":"),
+            include_hidden: true,
+        }
+    }
+}
+
+/// Entries removed from one position's matrices during training
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PositionReport {
+    pub transitions_removed_by_constraints: usize,
+    pub emissions_removed_by_constraints: usize,
+    pub transitions_removed_by_arc_consistency: usize,
+    pub emissions_removed_by_arc_consistency: usize,
+    /// Fraction of the position's emission probability mass that survived pruning
+    pub surviving_mass: f64,
+}
+
+/// Pruning impact of training, one entry per markov order token position
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrainReport {
+    pub positions: Vec<PositionReport>,
+}
 
 #[derive(Debug)]
 pub struct ConstrainedHiddenMarkov {
     pub hidden_markov_model: HiddenMarkov,
     pub sequence_length: usize,
-    pub hidden_probs: Vec<HashMap<String, HashMap<String, f64>>>,
-    pub observed_probs: Vec<HashMap<String, HashMap<String, f64>>>,
+    pub hidden_probs: Vec<ProbMap<String, ProbMap<String, f64>>>,
+    pub observed_probs: Vec<ProbMap<String, ProbMap<String, f64>>>,
     pub hidden_constraints: Vec<Box<dyn Constraint + Send>>,
     pub observed_constraints: Vec<Box<dyn Constraint + Send>>,
+    /// Fewest tokens before `END_TOKEN` may be drawn, for models trained with `HiddenMarkov::new_with_end`
+    pub min_length: usize,
+    /// Per-position constraints on observed and hidden states together, or empty for none
+    pub joint_constraints: Vec<Box<dyn JointConstraint + Send>>,
+    /// Evaluate hidden and observed constraints across threads during training
+    pub parallel_constraints: bool,
+    /// Propagate dead states from the most constrained positions first during arc-consistency
+    pub selective_pruning: bool,
+    /// Per-position markov orders for `sample_sequence_mixed_order`, see `with_position_orders`
+    pub mixed_order: Option<MixedOrderModel>,
+    /// Constraints on each observed word given the previous ones, checked while sampling
+    pub sequence_constraints: Vec<Box<dyn SequenceConstraint + Send>>,
+    /// Deterministic positions found by `train`, each with its one surviving hidden state
+    pub cut_states: Vec<(usize, String)>,
 }
 
 impl ConstrainedHiddenMarkov {
@@ -23,9 +113,16 @@ impl ConstrainedHiddenMarkov {
             hidden_probs: Default::default(),
             observed_probs: Default::default(),
             hidden_constraints: Default::default(),
-            observed_constraints: Default::default()
+            observed_constraints: Default::default(),
+            min_length: 0,
+            joint_constraints: vec![],
+            parallel_constraints: false,
+            selective_pruning: false,
+            mixed_order: None,
+            sequence_constraints: vec![],
+            cut_states: vec![],
         };
-        assert!(sequence_length > 1);
+        assert!(sequence_length >= 1);
 
 
         chmm.hidden_constraints = hidden_constraints.unwrap_or(
@@ -39,273 +136,1368 @@ impl ConstrainedHiddenMarkov {
         return chmm;
     }
 
+    /// Sequences sampled from a model trained with `HiddenMarkov::new_with_end` end
+    /// once `END_TOKEN` is drawn, but never before `min_length` tokens
+    pub fn with_min_length(mut self, min_length: usize) -> ConstrainedHiddenMarkov {
+        self.min_length = min_length;
+        self
+    }
+
+    /// Replaces the constraints with `f(i)`'s (observed, hidden) constraints for each position `i`
+    pub fn with_position_constraints<F>(mut self, f: F) -> ConstrainedHiddenMarkov
+        where F: Fn(usize) -> (Box<dyn Constraint + Send>, Box<dyn Constraint + Send>) {
+        let (observed_constraints, hidden_constraints) = (0..self.sequence_length).map(f).unzip();
+        self.observed_constraints = observed_constraints;
+        self.hidden_constraints = hidden_constraints;
+        self
+    }
+
+    /// Also prune emissions whose observed word and hidden state together violate
+    /// the joint constraint at their position
+    ///
+    /// Panics if there is not exactly one joint constraint per position
+    pub fn with_joint_constraints(mut self, joint_constraints: Vec<Box<dyn JointConstraint + Send>>) -> ConstrainedHiddenMarkov {
+        assert_eq!(self.sequence_length, joint_constraints.len());
+        self.joint_constraints = joint_constraints;
+        self
+    }
+
+    /// Also require every sampled observed word to satisfy each sequence constraint
+    /// given the words sampled before it
+    ///
+    /// Each hidden state is drawn in proportion to its transition probability times the
+    /// emission mass the sequence constraints still allow, but there is no further
+    /// lookahead, so a path dies when no allowed word is left; see `DeadPathPolicy`
+    ///
+    /// Every random and draw based sampler honors them except `sample_sequence_beam`,
+    /// `sample_sequence_with_suffix` and the exact methods over the trained trellis
+    /// (e.g. `enumerate_sequences` and `distinct_top_sequences`), which ignore them
+    pub fn with_sequence_constraints(mut self, sequence_constraints: Vec<Box<dyn SequenceConstraint + Send>>) -> ConstrainedHiddenMarkov {
+        self.sequence_constraints = sequence_constraints;
+        self
+    }
+
+    /// Evaluate constraints in parallel during training, which pays off for
+    /// expensive constraints (e.g. regex or phonetic) over a large vocabulary
+    pub fn with_parallel_constraints(mut self, parallel_constraints: bool) -> ConstrainedHiddenMarkov {
+        self.parallel_constraints = parallel_constraints;
+        self
+    }
+
+    /// Replace the back-to-front arc-consistency sweep with a worklist that starts from
+    /// the positions with the fewest surviving hidden states and only revisits a position
+    /// when pruning the one after it removed transitions into it
+    ///
+    /// Both reach the same fixpoint, so the trained model is the same either way. The sweep
+    /// already visits each position once, so expect similar training times; compare them
+    /// with `benchmark_selective_pruning` on a long, tightly constrained sequence
+    pub fn with_selective_pruning(mut self, selective_pruning: bool) -> ConstrainedHiddenMarkov {
+        self.selective_pruning = selective_pruning;
+        self
+    }
+
+    /// Uses markov order `orders[i]` at each sequence position `i` when sampling with
+    /// `sample_sequence_mixed_order` and scoring with `get_mixed_order_sequence_probability`
+    ///
+    /// The hidden markov model must be trained at the maximum order used, since the
+    /// lower order transitions are derived from its counts (see `MixedOrderModel`)
+    pub fn with_position_orders(mut self, orders: Vec<usize>) -> Result<ConstrainedHiddenMarkov, ChmmError> {
+        if orders.len() != self.sequence_length {
+            return Err(ChmmError::InvalidArgument(
+                format!("{} position orders given for a sequence length of {}", orders.len(), self.sequence_length)));
+        }
+        self.mixed_order = Some(MixedOrderModel::new(&self.hidden_markov_model, orders)?);
+        Ok(self)
+    }
+
     pub fn train(&mut self) {
+        self.train_with_report();
+    }
+
+    /// Trains like `train`, reporting per position how many entries were removed
+    /// by the constraints themselves and how many by arc-consistency afterwards
+    pub fn train_with_report(&mut self) -> TrainReport {
         self.clear_probs();
 
         // Copy matrices for each sequence position
         self.duplicate_matrices();
+        let unconstrained = self.nonzero_entries();
+        let unconstrained_mass: Vec<f64> = (0..self.observed_probs.len()).map(|i| self.total_emission_mass(i)).collect();
 
         // Remove states violating the constraints
         self.remove_constrain_violating_states();
 
+        // Remove emissions violating the joint observed and hidden constraints
+        self.remove_joint_constraint_violating_states();
+
+        // Keep sequences from ending before the minimum length
+        self.remove_early_end_states();
+        let constrained = self.nonzero_entries();
+
         // Enforce arc-consistency
         self.remove_dead_states();
+        let consistent = self.nonzero_entries();
+
+        let positions = (0..self.hidden_probs.len()).map(|i| PositionReport {
+            transitions_removed_by_constraints: unconstrained[i].0 - constrained[i].0,
+            emissions_removed_by_constraints: unconstrained[i].1 - constrained[i].1,
+            transitions_removed_by_arc_consistency: constrained[i].0 - consistent[i].0,
+            emissions_removed_by_arc_consistency: constrained[i].1 - consistent[i].1,
+            surviving_mass: if unconstrained_mass[i] == 0.0 { 0.0 } else { self.total_emission_mass(i) / unconstrained_mass[i] },
+        }).collect();
 
         // Re-normalize
         self.renormalize();
+        self.cut_states = self.find_cut_states();
+
+        TrainReport { positions }
     }
 
-    /// Generate a sequence
-    pub fn sample_sequence(&self, include_hidden: bool) -> String { // TODO: update for higher markov orders
-        let mut sequence = String::from("");
-        let markov_order = self.hidden_markov_model.markov_order as usize;
-        let mut start_string = "".to_owned();
-        for _ in 0..markov_order {
-            start_string.push_str(START_TOKEN);
-            start_string.push(' ');
-        }
-        start_string.pop();
-        let mut hidden = start_string.as_str();
-        let mut sequence_count = 0;
-        for i in 0..self.get_markov_order_token_length() {
-            if self.hidden_probs[i].contains_key(hidden) {
-                hidden = ConstrainedHiddenMarkov::next_token(&self.hidden_probs[i][hidden])
-            } else {
-                return sequence;
+    /// Hidden transition matrix of markov order token `position`, or `None` if out of range
+    pub fn hidden_at(&self, position: usize) -> Option<&ProbMap<String, ProbMap<String, f64>>> {
+        self.hidden_probs.get(position)
+    }
+
+    /// Observed emission matrix of markov order token `position`, or `None` if out of range
+    pub fn observed_at(&self, position: usize) -> Option<&ProbMap<String, ProbMap<String, f64>>> {
+        self.observed_probs.get(position)
+    }
+
+    /// Nonzero (transitions, emissions) at each position
+    fn nonzero_entries(&self) -> Vec<(usize, usize)> {
+        let count_nonzero = |matrix: &ProbMap<String, ProbMap<String, f64>>| {
+            matrix.values().flat_map(|inner_map| inner_map.values()).filter(|value| **value != 0.0).count()
+        };
+        self.hidden_probs.iter().zip(self.observed_probs.iter())
+            .map(|(hidden, observed)| (count_nonzero(hidden), count_nonzero(observed)))
+            .collect()
+    }
+
+    fn total_emission_mass(&self, position: usize) -> f64 {
+        self.observed_probs[position].values().flat_map(|emissions| emissions.values()).sum()
+    }
+
+    /// Replaces the constraints and retrains from the stored base model
+    ///
+    /// Fails without changing the model if either list does not have `sequence_length` constraints
+    pub fn set_constraints(&mut self, hidden_constraints: Vec<Box<dyn Constraint + Send>>,
+                           observed_constraints: Vec<Box<dyn Constraint + Send>>) -> Result<(), ChmmError> {
+        for constraints in [&hidden_constraints, &observed_constraints] {
+            if constraints.len() != self.sequence_length {
+                return Err(ChmmError::SequenceLengthMismatch { length: self.sequence_length, constraints: constraints.len() });
             }
+        }
+        self.hidden_constraints = hidden_constraints;
+        self.observed_constraints = observed_constraints;
+        self.train();
+        Ok(())
+    }
 
-            if self.observed_probs[i].contains_key(hidden) {
-                let observed = ConstrainedHiddenMarkov::next_token(&self.observed_probs[i][hidden]);
-                if include_hidden {
-                    for (observed, hidden) in observed.split_whitespace().zip(hidden.split_whitespace()) {
-                        if sequence_count == self.sequence_length { break };
-                        sequence += format!("{}:{} ", observed, hidden).as_str();
-                        sequence_count += 1;
+    /// Adds an observed constraint at `position` of a trained model (combined with any
+    /// existing constraint there), pruning only that position's emissions before
+    /// re-running arc-consistency and renormalization instead of retraining from scratch
+    pub fn add_constraint_at(&mut self, position: usize, observed: Box<dyn Constraint + Send>) -> Result<(), ChmmError> {
+        if position >= self.sequence_length {
+            return Err(ChmmError::InvalidArgument(format!("position {} is outside the sequence length {}", position, self.sequence_length)));
+        }
+        if self.observed_probs.is_empty() {
+            return Err(ChmmError::InvalidArgument(String::from("the model must be trained before adding constraints")));
+        }
+        let markov_order = self.hidden_markov_model.markov_order as usize;
+        let (i, j) = (position / markov_order, position % markov_order);
+        for outer_map in self.observed_probs[i].values_mut() {
+            for (inner_map_key, inner_map_val) in outer_map.iter_mut() {
+                if let Some(state) = inner_map_key.split_whitespace().nth(j) {
+                    if !observed.is_satisfied_by_state(format_observed(state, false)) {
+                        *inner_map_val = 0.0;
                     }
-                    sequence.pop();
-                } else {
-                    if sequence_count == self.sequence_length { break };
-                    sequence += observed;
-                    sequence_count += 1;
                 }
-                if i != self.sequence_length/markov_order - 1 { sequence += " " }
             }
         }
-        return sequence;
+        self.observed_constraints[position] = self.observed_constraints[position].clone() & observed;
+
+        self.remove_dead_states();
+        self.renormalize();
+        self.cut_states = self.find_cut_states();
+        Ok(())
     }
 
-    fn next_token(prev_token_map: &HashMap<String, f64>) -> &str {
-        let mut sum = 0.0;
-        let rand_value: f64 = rand::thread_rng().gen();
-        for potential_token in prev_token_map {
-            sum += potential_token.1;
-            if sum > rand_value {
-                return potential_token.0;
+    /// Generate a sequence
+    pub fn sample_sequence(&self, include_hidden: bool) -> String {
+        self.sample_sequence_with_rng(include_hidden, &mut rand::thread_rng())
+    }
+
+    /// Generate a sequence drawing from the given random number generator
+    pub fn sample_sequence_with_rng<R: Rng>(&self, include_hidden: bool, rng: &mut R) -> String {
+        let path = self.sample_path(rng);
+        self.format_path(&path, include_hidden)
+    }
+
+    /// Generate a sequence using the delimiters of `fmt`
+    pub fn sample_sequence_formatted(&self, fmt: &SequenceFormat) -> String {
+        let path = self.sample_path(&mut rand::thread_rng());
+        self.format_path_with(&path, fmt)
+    }
+
+    /// Generate a sequence of space separated observed words without their hidden states
+    pub fn sample_observed_only(&self) -> String {
+        self.sample_sequence_formatted(&SequenceFormat { include_hidden: false, ..Default::default() })
+    }
+
+    /// Endless stream of observed-only sequences, sampled lazily as in
+    /// `sample_observed_only`, e.g. to chain `.filter` and `.take`
+    pub fn observed_sequences(&self) -> impl Iterator<Item = String> + '_ {
+        std::iter::repeat_with(move || self.sample_observed_only())
+    }
+
+    /// Generate a sequence (including hidden states) using `draws` in place of random
+    /// values, e.g. for tests or for draws from a low-discrepancy sequence
+    ///
+    /// Each markov order token consumes two draws in [0, 1), first for the hidden state
+    /// then for the observed state, each picking from the cumulative probabilities of
+    /// the candidates in sorted order. Sampling stops early when the draws run out
+    pub fn sample_sequence_from_draws(&self, draws: &[f64]) -> String {
+        let mut draws = draws.iter();
+        let path = self.sample_path_by(|candidates| {
+            draws.next().map(|draw| ConstrainedHiddenMarkov::next_token_from_draw(candidates, *draw))
+        });
+        self.format_path(&path, true)
+    }
+
+    /// Generate a sequence that is the same for the same trained probabilities and `seed`
+    /// on every platform and Rust version
+    ///
+    /// Training sums floats in hash map order, so training again on the same data can change
+    /// the probabilities in their last bits and with them the rare draw near a boundary
+    ///
+    /// Draws come from `ChaCha20Rng::seed_from_u64(seed)`: each state is picked by taking
+    /// the next `u64` x, mapping it to [0, 1) as (x >> 11) * 2^-53, and walking the
+    /// cumulative probabilities of the candidates in sorted order, as in `sample_sequence_from_draws`
+    pub fn sample_sequence_portable(&self, seed: u64, include_hidden: bool) -> String {
+        let mut rng = ChaCha20Rng::seed_from_u64(seed);
+        let path = self.sample_path_by(|candidates| {
+            let draw = (rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+            Some(ConstrainedHiddenMarkov::next_token_from_draw(candidates, draw))
+        });
+        self.format_path(&path, include_hidden)
+    }
+
+    /// Generate a sequence, handling paths that die before the sequence length as `policy` says
+    pub fn sample_sequence_with_policy(&self, include_hidden: bool, policy: DeadPathPolicy) -> Result<String, ChmmError> {
+        self.sample_with_policy(include_hidden, policy, &mut rand::thread_rng())
+    }
+
+    fn sample_with_policy<R: Rng>(&self, include_hidden: bool, policy: DeadPathPolicy, rng: &mut R) -> Result<String, ChmmError> {
+        let attempts = match policy {
+            DeadPathPolicy::Truncate | DeadPathPolicy::Error => 1,
+            DeadPathPolicy::Retry(retries) => retries + 1,
+        };
+        let token_length = self.get_markov_order_token_length();
+        let mut position = 0;
+        for _ in 0..attempts {
+            let path = self.sample_path(rng);
+            if path.len() == token_length || policy == DeadPathPolicy::Truncate {
+                return Ok(self.format_path(&path, include_hidden))
             }
+            position = path.len();
         }
-        return ""
+        Err(ChmmError::DeadPath { position, attempts })
     }
 
-    fn clear_probs(&mut self) {
-        self.hidden_probs.clear();
-        self.observed_probs.clear();
+    /// Generate the rest of a sequence (including hidden states) from markov order token
+    /// `position` onwards, walking on from `hidden_state` in place of the start state,
+    /// e.g. to continue a partial sequence whose last hidden state is known
+    ///
+    /// Returns `None` if `hidden_state` has no surviving transitions into `position`.
+    /// Sequence constraints only see the words sampled from `position` on
+    pub fn sample_sequence_starting_at(&self, position: usize, hidden_state: &str) -> Option<String> {
+        let transitions = self.hidden_probs.get(position)?.get(hidden_state)?;
+        if !transitions.values().any(|prob| *prob > 0.0) { return None }
+        let mut rng = rand::thread_rng();
+        let path = self.sample_segment_by(position..self.get_markov_order_token_length(), hidden_state.to_owned(), vec![],
+                                          |candidates| Some(ConstrainedHiddenMarkov::next_token(candidates, &mut rng)));
+        Some(self.format_path(&path, true))
     }
 
-    fn check_sequence_and_constraint_length(&self) {
-        assert_eq!(self.sequence_length, self.hidden_constraints.len());
-        assert_eq!(self.sequence_length, self.observed_constraints.len());
-        assert!(self.sequence_length >= self.hidden_markov_model.markov_order as usize);
+    /// Generate a sequence one token at a time with the per-position orders of
+    /// `with_position_orders`, failing if none were given
+    ///
+    /// Candidates violating a position's constraints are skipped, but without the
+    /// lookahead of `train`, so a path can reach a position with no candidate left;
+    /// `policy` decides whether that path is returned truncated, re-sampled or an error
+    pub fn sample_sequence_mixed_order(&self, include_hidden: bool, policy: DeadPathPolicy) -> Result<String, ChmmError> {
+        let mixed_order = self.mixed_order.as_ref()
+            .ok_or_else(|| ChmmError::InvalidArgument(String::from("no per-position orders were given")))?;
+        let attempts = match policy {
+            DeadPathPolicy::Truncate | DeadPathPolicy::Error => 1,
+            DeadPathPolicy::Retry(retries) => retries + 1,
+        };
+        let mut rng = rand::thread_rng();
+        let mut position = 0;
+        for _ in 0..attempts {
+            let tokens = self.sample_mixed_order_tokens(mixed_order, include_hidden, &mut rng);
+            if tokens.len() == self.sequence_length || policy == DeadPathPolicy::Truncate {
+                return Ok(tokens.join(" "))
+            }
+            position = tokens.len();
+        }
+        Err(ChmmError::DeadPath { position, attempts })
     }
 
-    fn get_markov_order_token_length(&self) -> usize {
-        let markov_order = self.hidden_markov_model.markov_order as usize;
-        return if self.sequence_length % markov_order == 0 {
-            self.sequence_length / markov_order
-        } else {
-            self.sequence_length / markov_order + 1
+    /// Samples formatted tokens for `sample_sequence_mixed_order`, stopping early at a dead end
+    fn sample_mixed_order_tokens<R: Rng>(&self, mixed_order: &MixedOrderModel, include_hidden: bool, rng: &mut R) -> Vec<String> {
+        let mut previous_hidden: Vec<String> = vec![];
+        let mut previous_observed: Vec<String> = vec![];
+        let mut tokens = vec![];
+        for position in 0..self.sequence_length {
+            let hidden_candidates: Vec<(&str, f64)> = mixed_order.transitions_at(position, &previous_hidden)
+                .map(|next| next.iter()
+                    .filter(|(hidden, _)| self.hidden_constraints[position].is_satisfied_by_state(hidden.to_string()))
+                    .map(|(hidden, prob)| (hidden.as_str(), *prob))
+                    .collect())
+                .unwrap_or_default();
+            let hidden = match ConstrainedHiddenMarkov::weighted_choice(&hidden_candidates, rng) {
+                Some(hidden) => hidden,
+                None => break,
+            };
+            let observed_candidates: Vec<(&str, f64)> = mixed_order.emissions.get(hidden)
+                .map(|emissions| emissions.iter()
+                    .filter(|(observed, _)| self.observed_constraints[position].is_satisfied_by_state(format_observed(observed, false))
+                        && self.is_allowed_by_sequence_constraints(&previous_observed, observed))
+                    .map(|(observed, prob)| (observed.as_str(), *prob))
+                    .collect())
+                .unwrap_or_default();
+            let observed = match ConstrainedHiddenMarkov::weighted_choice(&observed_candidates, rng) {
+                Some(observed) => observed,
+                None => break,
+            };
+            tokens.push(if include_hidden {
+                format!("{}:{}", format_observed(observed, true), hidden)
+            } else {
+                format_observed(observed, false)
+            });
+            previous_hidden.push(hidden.to_owned());
+            previous_observed.push(format_observed(observed, false));
         }
+        tokens
     }
 
-    /// Performs deep copy of non-constrained hidden markov model
-    /// probabilities for each sequence position
-    fn duplicate_matrices(&mut self) {
-        let markov_order = self.hidden_markov_model.markov_order as usize;
-        let end_matrix = if self.sequence_length % markov_order > 0 { 1 } else { 0 };
-        for i in 0..(self.sequence_length / markov_order + end_matrix) {
-            self.hidden_probs.insert(i,self.hidden_markov_model.hidden_probs.clone());
-            self.observed_probs.insert(i,self.hidden_markov_model.observed_probs.clone());
-        }
+    /// Generate a sequence (including hidden states) whose probability, as given by
+    /// `get_sequence_probability`, is at least `floor`, re-sampling up to `max_attempts`
+    /// times before giving up with `None`
+    pub fn sample_sequence_min_probability(&self, floor: f64, max_attempts: usize) -> Option<String> {
+        let mut rng = rand::thread_rng();
+        let token_length = self.get_markov_order_token_length();
+        (0..max_attempts)
+            .map(|_| self.sample_path(&mut rng))
+            .find(|path| path.len() == token_length && self.path_probability(path) >= floor)
+            .map(|path| self.format_path(&path, true))
     }
 
-    /// Removes states that violate constraints on hidden
-    /// or observed sequence positions
-    fn remove_constrain_violating_states(&mut self) {
-        self.remove_constrain_violating_hidden_states();
-        self.remove_constrain_violating_observed_states()
+    /// Samples (hidden, observed) states per markov order token, stopping early at a dead end
+    fn sample_path<R: Rng>(&self, rng: &mut R) -> Vec<(String, String)> {
+        self.sample_path_by(|candidates| Some(ConstrainedHiddenMarkov::next_token(candidates, rng)))
     }
 
-    fn remove_constrain_violating_hidden_states(&mut self) {
-        let markov_order = self.hidden_markov_model.markov_order as usize;
-        for i in 0..self.get_markov_order_token_length() {
-            for (_, outer_map) in self.hidden_probs[i].iter_mut() {
-                for (inner_map_key, inner_map_val) in outer_map.iter_mut() {
-                    // Check for constraint satisfaction for each token
-                    // (split for markov orders higher than 1)
-                    let mut state_markov_split = inner_map_key.split_whitespace();
-                    for j in 0..(markov_order) {
-                        match state_markov_split.next() {
-                            None => {}
-                            Some(state) => {
-                                // continue if inside last markov order token but past constraints
-                                if (i*markov_order)+j >= self.hidden_constraints.len() { continue };
-                                if !self.hidden_constraints[(i*markov_order)+j].is_satisfied_by_state(String::from(state)) {
-                                    *inner_map_val = 0.0;
-                                }
-                            }
-                        }
-                    }
-                }
+    /// Emissions of `hidden` at markov order token `i` whose words the sequence constraints
+    /// allow after `previous_observed`, with their probabilities (not renormalized)
+    fn allowed_emissions(&self, i: usize, hidden: &str, previous_observed: &[String]) -> ProbMap<String, f64> {
+        self.observed_probs[i].get(hidden).map(|emissions| emissions.iter()
+            .filter(|(observed, prob)| **prob > 0.0 && self.is_allowed_by_sequence_constraints(previous_observed, observed))
+            .map(|(observed, prob)| (observed.to_owned(), *prob))
+            .collect())
+            .unwrap_or_default()
+    }
+
+    /// `transitions` at markov order token `i` weighted by the emission mass the
+    /// sequence constraints still allow after `previous_observed`
+    fn allowed_transitions(&self, i: usize, transitions: &ProbMap<String, f64>, previous_observed: &[String]) -> ProbMap<String, f64> {
+        transitions.iter()
+            .map(|(hidden, prob)| (hidden.to_owned(), prob * self.allowed_emissions(i, hidden, previous_observed).values().sum::<f64>()))
+            .filter(|(_, prob)| *prob > 0.0)
+            .collect()
+    }
+
+    fn renormalized(mut candidates: ProbMap<String, f64>) -> ProbMap<String, f64> {
+        let total: f64 = candidates.values().sum();
+        candidates.values_mut().for_each(|prob| *prob /= total);
+        candidates
+    }
+
+    /// Whether each word of the observed state satisfies every sequence constraint,
+    /// following `previous_observed` and the words of the state before it
+    fn is_allowed_by_sequence_constraints(&self, previous_observed: &[String], observed: &str) -> bool {
+        let mut history = previous_observed.to_vec();
+        for word in observed.split_whitespace().map(|word| format_observed(word, false)) {
+            if !self.sequence_constraints.iter().all(|constraint| constraint.is_satisfied_by(history.len(), &history, &word)) {
+                return false
             }
+            history.push(word);
         }
+        true
     }
 
-    fn remove_constrain_violating_observed_states(&mut self) {
-        let markov_order = self.hidden_markov_model.markov_order as usize;
-        for i in 0..self.get_markov_order_token_length() {
-            for (_, outer_map) in self.observed_probs[i].iter_mut() {
-                for (inner_map_key, inner_map_val) in outer_map.iter_mut() {
-                    // Check for constraint satisfaction for each token
-                    // (split for markov orders higher than 1)
-                    let mut state_markov_split = inner_map_key.split_whitespace();
-                    for j in 0..(markov_order) {
-                        match state_markov_split.next() {
-                            None => {}
-                            Some(state) => {
-                                // continue if inside last markov order token but past constraints
-                                if (i*markov_order)+j >= self.observed_constraints.len() { continue };
-                                if !self.observed_constraints[(i*markov_order)+j].is_satisfied_by_state(String::from(state)) {
-                                    *inner_map_val = 0.0;
-                                }
-                            }
-                        }
-                    }
-                }
+    /// Samples a path picking each state with `choose`, stopping early at a dead end
+    /// or when `choose` returns `None`
+    fn sample_path_by<F: FnMut(&ProbMap<String, f64>) -> Option<&str>>(&self, choose: F) -> Vec<(String, String)> {
+        self.sample_segment_by(0..self.get_markov_order_token_length(), self.start_state(), vec![], choose)
+    }
+
+    /// Samples the markov order token positions in `positions` following `prev_hidden`
+    /// and the observed words `previous_observed`, stopping early at a dead end or when
+    /// `choose` returns `None`
+    ///
+    /// With sequence constraints, `choose` picks from the candidates they allow,
+    /// renormalized, with hidden states weighted as in `with_sequence_constraints`
+    fn sample_segment_by<F: FnMut(&ProbMap<String, f64>) -> Option<&str>>(&self, positions: Range<usize>, mut prev_hidden: String,
+                                                                        mut previous_observed: Vec<String>, mut choose: F) -> Vec<(String, String)> {
+        let has_sequence_constraints = !self.sequence_constraints.is_empty();
+        let mut path = vec![];
+        for i in positions {
+            let (allowed_transitions, allowed_emissions);
+            let transitions = match self.hidden_probs[i].get(&prev_hidden) {
+                Some(transitions) if has_sequence_constraints => {
+                    allowed_transitions = ConstrainedHiddenMarkov::renormalized(self.allowed_transitions(i, transitions, &previous_observed));
+                    &allowed_transitions
+                },
+                Some(transitions) => transitions,
+                None => break,
+            };
+            // An empty choice means no candidate had probability left
+            let hidden = match choose(transitions) {
+                Some(hidden) if !hidden.is_empty() => hidden.to_owned(),
+                _ => break,
+            };
+            let emissions = match self.observed_probs[i].get(&hidden) {
+                Some(_) if has_sequence_constraints => {
+                    allowed_emissions = ConstrainedHiddenMarkov::renormalized(self.allowed_emissions(i, &hidden, &previous_observed));
+                    &allowed_emissions
+                },
+                Some(emissions) => emissions,
+                None => break,
+            };
+            let observed = match choose(emissions) {
+                Some(observed) if !observed.is_empty() => observed.to_owned(),
+                _ => break,
+            };
+            if has_sequence_constraints {
+                previous_observed.extend(observed.split_whitespace().map(|word| format_observed(word, false)));
             }
+            path.push((hidden.clone(), observed));
+            prev_hidden = hidden;
         }
+        path
     }
 
-    /// Removes state transitions that lead to a zero probability solution
-    /// i.e. enforces arc-consistency
+    /// Generate a sequence sampling each segment between cut points on its own
     ///
-    /// this is a tree-structured CSP, so can be done in a single pass
-    fn remove_dead_states(&mut self) {
-        // Working backwards through the sequence positions
-        // Remove hidden states whose observed state sums to 0.0
-        for i in (0..self.hidden_probs.len()).rev() {
-            let current_hidden = &mut self.hidden_probs[i];
-            let current_observed = &mut self.observed_probs[i];
+    /// The trellis factorizes at every deterministic position, so the segments are
+    /// independent given the forced state before them: each is sampled in parallel
+    /// from its own generator seeded from `rng`. With sequence constraints the segments
+    /// depend on each other's words, so the sequence is sampled in one walk instead
+    pub fn sample_sequence_segmented_with_rng<R: Rng>(&self, include_hidden: bool, rng: &mut R) -> String {
+        if !self.sequence_constraints.is_empty() { return self.sample_sequence_with_rng(include_hidden, rng) }
+        let segments: Vec<(Range<usize>, String, u64)> = self.segment_starts().into_iter()
+            .map(|(segment, prev_hidden)| (segment, prev_hidden, rng.gen()))
+            .collect();
+        let segment_paths: Vec<(usize, Vec<(String, String)>)> = segments.into_par_iter()
+            .map(|(segment, prev_hidden, seed)| {
+                let mut segment_rng = ChaCha20Rng::seed_from_u64(seed);
+                let expected_len = segment.len();
+                (expected_len, self.sample_segment_by(segment, prev_hidden, vec![],
+                                                      |candidates| Some(ConstrainedHiddenMarkov::next_token(candidates, &mut segment_rng))))
+            })
+            .collect();
+
+        let mut path = vec![];
+        for (expected_len, segment_path) in segment_paths {
+            let is_dead_end = segment_path.len() < expected_len;
+            path.extend(segment_path);
+            if is_dead_end { break }
+        }
+        self.format_path(&path, include_hidden)
+    }
 
-            // Remove hidden states whose observed state sums to 0.0
-            // from the current sequence position
-            let states_to_remove_in_curr: HashSet<String> =
-                ConstrainedHiddenMarkov::get_zero_sum_outer_keys(current_observed);
-            for (_, outer_value) in current_hidden.iter_mut() {
-                for (inner_key, inner_value) in outer_value.iter_mut() {
-                    if states_to_remove_in_curr.contains(inner_key) {
-                        *inner_value = 0.0;
+    /// Markov order token positions where exactly one state survives, where the
+    /// trained model can be split into independent segments
+    pub fn cut_points(&self) -> Vec<usize> {
+        self.cut_states.iter().map(|(cut, _)| *cut).collect()
+    }
+
+    /// Markov order token ranges that are independent of each other,
+    /// each ending just after a cut point (or at the end of the sequence)
+    pub fn segments(&self) -> Vec<Range<usize>> {
+        self.segment_starts().into_iter().map(|(segment, _)| segment).collect()
+    }
+
+    /// `segments` paired with the hidden state each one follows
+    fn segment_starts(&self) -> Vec<(Range<usize>, String)> {
+        let length = self.get_markov_order_token_length();
+        let mut segments = vec![];
+        let (mut start, mut prev_hidden) = (0, self.start_state());
+        for (cut, hidden) in &self.cut_states {
+            segments.push((start..cut + 1, prev_hidden));
+            start = cut + 1;
+            prev_hidden = hidden.to_owned();
+        }
+        if start < length { segments.push((start..length, prev_hidden)) }
+        segments
+    }
+
+    /// Each deterministic position with the one hidden state surviving there
+    fn find_cut_states(&self) -> Vec<(usize, String)> {
+        let reachable = self.reachable_hidden_states();
+        self.deterministic_positions().iter().enumerate()
+            .filter(|(_, is_deterministic)| **is_deterministic)
+            .filter_map(|(i, _)| reachable[i].iter().next().map(|hidden| (i, hidden.to_owned())))
+            .collect()
+    }
+
+    /// Generate a sequence (including hidden states) following the given hidden
+    /// tags, or `None` if the tag path has zero probability under the constraints
+    ///
+    /// Only observed words are sampled; `tags` must have `sequence_length` entries
+    /// and is matched one markov order token at a time
+    pub fn sample_for_hidden_sequence(&self, tags: &[&str]) -> Option<String> {
+        if tags.len() != self.sequence_length { return None }
+        let mut rng = rand::thread_rng();
+        let mut path: Vec<(String, String)> = vec![];
+        let mut prev_hidden = self.start_state();
+        for (i, chunk) in tags.chunks(self.hidden_markov_model.markov_order as usize).enumerate() {
+            let hidden = chunk.join(" ");
+            let transition_prob = self.hidden_probs[i].get(&prev_hidden).and_then(|probs| probs.get(&hidden))?;
+            if *transition_prob == 0.0 || self.emission_mass(i, &hidden) == 0.0 { return None }
+
+            let observed = ConstrainedHiddenMarkov::next_token(&self.observed_probs[i][&hidden], &mut rng).to_owned();
+            path.push((hidden.clone(), observed));
+            prev_hidden = hidden;
+        }
+        Some(self.format_path(&path, true))
+    }
+
+    /// The `n` most probable observed words at each position of the given hidden tag path,
+    /// with their emission probabilities under the constraints, most probable first
+    ///
+    /// Fails if `tags` does not have `sequence_length` entries or has zero probability
+    pub fn nbest_observed(&self, tags: &[&str], n: usize) -> Result<Vec<Vec<(String, f64)>>, ChmmError> {
+        if tags.len() != self.sequence_length {
+            return Err(ChmmError::SequenceLengthMismatch { length: self.sequence_length, constraints: tags.len() });
+        }
+        let mut nbest = vec![];
+        let mut prev_hidden = self.start_state();
+        for (i, chunk) in tags.chunks(self.hidden_markov_model.markov_order as usize).enumerate() {
+            let hidden = chunk.join(" ");
+            let transition_prob = self.hidden_probs.get(i).and_then(|probs| probs.get(&prev_hidden)).and_then(|probs| probs.get(&hidden));
+            if transition_prob.is_none_or(|prob| *prob == 0.0) || self.emission_mass(i, &hidden) == 0.0 {
+                return Err(ChmmError::InvalidArgument(format!("tag path has zero probability at \"{}\"", hidden)));
+            }
+
+            // Marginal probability of each word at each sequence position of the markov order token
+            for j in 0..chunk.len() {
+                let mut words: ProbMap<String, f64> = ProbMap::new();
+                for (observed, prob) in &self.observed_probs[i][&hidden] {
+                    if let Some(word) = observed.split_whitespace().nth(j) {
+                        if *prob > 0.0 { *words.entry(format_observed(word, false)).or_insert(0.0) += prob }
                     }
                 }
+                let mut words: Vec<(String, f64)> = words.into_iter().collect();
+                words.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+                words.truncate(n);
+                nbest.push(words);
             }
+            prev_hidden = hidden;
         }
+        Ok(nbest)
+    }
 
-        // Remove dead states based on transitions
-        for i in (1..self.hidden_probs.len()).rev() {
-            let current_hidden = &mut self.hidden_probs[i].to_owned();
+    /// Generate a sequence (including hidden states) whose last tokens are
+    /// the given `observed:hidden` suffix, or `None` if no valid sequence ends with it
+    /// or a suffix token has no colon
+    ///
+    /// Earlier positions are sampled conditioned on reaching the suffix
+    /// using the backward completion masses of the trellis, which ignore sequence constraints
+    pub fn sample_sequence_with_suffix(&self, suffix: &[&str]) -> Option<String> {
+        let suffix_tokens: Vec<(String, String)> = split_tokens(&suffix.join(" ")).ok()?.iter()
+            .map(|token| HiddenMarkov::split_token(token))
+            .collect::<Option<Vec<(String, String)>>>()?;
+        if suffix_tokens.len() > self.sequence_length { return None }
+        let suffix_start = self.sequence_length - suffix_tokens.len();
+        let markov_order = self.hidden_markov_model.markov_order as usize;
+        let matches_suffix = |position: usize, hidden: &str, observed: &str| {
+            observed.split_whitespace().zip(hidden.split_whitespace()).enumerate().all(|(j, (observed, hidden))| {
+                let sequence_position = position * markov_order + j;
+                if sequence_position < suffix_start || sequence_position >= self.sequence_length { return true }
+                let (suffix_observed, suffix_hidden) = &suffix_tokens[sequence_position - suffix_start];
+                suffix_observed == observed && suffix_hidden == hidden
+            })
+        };
 
-            // Add states from current sequence position whose transitions sum to 0.0
-            // to an array to be removed
-            let states_to_remove_in_prev: HashSet<String> =
-                ConstrainedHiddenMarkov::get_zero_sum_outer_keys(current_hidden);
+        let completion_masses = self.completion_masses(&matches_suffix);
+        let mut rng = rand::thread_rng();
+        let mut path: Vec<(String, String)> = vec![];
+        let mut prev_hidden = self.start_state();
+        for (i, masses) in completion_masses.iter().enumerate() {
+            let transitions = self.hidden_probs[i].get(&prev_hidden)?;
+            let hidden_candidates: Vec<(&str, f64)> = transitions.keys()
+                .map(|hidden| (hidden.as_str(), self.surviving_transition(i, &prev_hidden, hidden)
+                    * masses.get(hidden).unwrap_or(&0.0)))
+                .collect();
+            let hidden = ConstrainedHiddenMarkov::weighted_choice(&hidden_candidates, &mut rng)?.to_owned();
+
+            let observed_candidates: Vec<(&str, f64)> = self.observed_probs[i].get(&hidden)?.iter()
+                .filter(|(observed, _)| matches_suffix(i, &hidden, observed))
+                .map(|(observed, prob)| (observed.as_str(), *prob))
+                .collect();
+            let observed = ConstrainedHiddenMarkov::weighted_choice(&observed_candidates, &mut rng)?.to_owned();
+
+            path.push((hidden.to_owned(), observed));
+            prev_hidden = hidden;
+        }
+        Some(self.format_path(&path, true))
+    }
 
-            // Remove transitions to removed states in the previous sequence position
-            let previous_hidden = &mut self.hidden_probs[i-1];
-            for (_, outer_value) in previous_hidden.iter_mut() {
-                for (inner_key, inner_value) in outer_value.iter_mut() {
-                    if states_to_remove_in_prev.contains(inner_key) {
-                        *inner_value = 0.0;
-                    }
-                    // Also remove transitions to states that do not exist in the next sequence position
-                    if current_hidden.get(inner_key).is_none() {
-                        *inner_value = 0.0;
-                    }
+    /// Generate a sequence (including hidden states) with a beam search keeping the
+    /// `beam_width` most probable partial paths at each position, then sampling one
+    /// of the surviving full paths in proportion to its probability
+    ///
+    /// Ties are broken randomly, so a width of 1 follows the greedy path.
+    /// Sequence constraints are ignored
+    pub fn sample_sequence_beam(&self, beam_width: usize) -> String {
+        let mut rng = rand::thread_rng();
+        let sequences: Vec<(String, f64)> = self.beam_paths(beam_width, &mut rng).iter()
+            .map(|(path, score)| (self.format_path(path, true), *score))
+            .collect();
+        let weighted: Vec<(&str, f64)> = sequences.iter().map(|(sequence, score)| (sequence.as_str(), *score)).collect();
+        ConstrainedHiddenMarkov::weighted_choice(&weighted, &mut rng).unwrap_or("").to_owned()
+    }
+
+    /// Up to `k` distinct sequences (including hidden states) with the highest
+    /// probabilities, paired with their probabilities and sorted descending
+    ///
+    /// Exact when the support can be enumerated within `ENUMERATION_LIMIT` paths,
+    /// otherwise the candidates are the survivors of a beam search of width `k`
+    pub fn distinct_top_sequences(&self, k: usize) -> Vec<(String, f64)> {
+        let mut paths = self.enumerate_paths(ENUMERATION_LIMIT);
+        if paths.len() >= ENUMERATION_LIMIT {
+            paths = self.beam_paths(k, &mut rand::thread_rng()).into_iter().map(|(path, _)| path).collect();
+        }
+
+        let mut sequence_probs: ProbMap<String, f64> = ProbMap::new();
+        for path in paths.iter() {
+            *sequence_probs.entry(self.format_path(path, true)).or_insert(0.0) += self.path_probability(path);
+        }
+        let mut sequences: Vec<(String, f64)> = sequence_probs.into_iter().collect();
+        sequences.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        sequences.truncate(k);
+        sequences
+    }
+
+    /// Full paths kept by a beam search of width `beam_width`, with their probabilities
+    fn beam_paths<R: Rng>(&self, beam_width: usize, rng: &mut R) -> Vec<(Vec<(String, String)>, f64)> {
+        let mut beam: Vec<(Vec<(String, String)>, f64)> = vec![(vec![], 1.0)];
+        for i in 0..self.hidden_probs.len() {
+            let mut candidates = vec![];
+            for (path, score) in &beam {
+                let prev_hidden = path.last().map_or_else(|| self.start_state(), |(hidden, _)| hidden.to_owned());
+                for (_, hidden, observed) in self.path_extensions(i, &prev_hidden) {
+                    let candidate_score = score * self.hidden_probs[i][&prev_hidden][&hidden]
+                        * self.observed_probs[i][&hidden][&observed];
+                    let mut candidate = path.clone();
+                    candidate.push((hidden, observed));
+                    candidates.push((candidate, candidate_score));
                 }
             }
+            candidates.shuffle(rng);
+            candidates.sort_by(|a, b| b.1.total_cmp(&a.1));
+            candidates.truncate(beam_width.max(1));
+            beam = candidates;
         }
+        beam
     }
 
-    fn get_zero_sum_outer_keys(probability_matrix: &mut HashMap<String, HashMap<String, f64>>) -> HashSet<String> {
-        let mut zero_sum_keys: HashSet<String> = HashSet::new();
-        for (outer_key, outer_value) in probability_matrix.iter() {
-            if outer_value.values().sum::<f64>() == 0.0 {
-                zero_sum_keys.insert(String::from(outer_key));
+    /// Generate `n` sequences (including hidden states)
+    pub fn sample_n(&self, n: usize) -> Vec<String> {
+        self.sample_n_with_rng(n, &mut rand::thread_rng())
+    }
+
+    /// Generate `n` sequences (including hidden states) drawing
+    /// from the given random number generator
+    pub fn sample_n_with_rng<R: Rng>(&self, n: usize, rng: &mut R) -> Vec<String> {
+        (0..n).map(|_| self.sample_sequence_with_rng(true, rng)).collect()
+    }
+
+    /// Generate `n` sequences (including hidden states), calling `progress` with the
+    /// number of sequences generated so far and the elapsed time after every `every` sequences
+    pub fn sample_n_with_progress<F: FnMut(usize, Duration)>(&self, n: usize, every: usize, mut progress: F) -> Vec<String> {
+        let start = Instant::now();
+        let mut rng = rand::thread_rng();
+        let mut sequences = Vec::with_capacity(n);
+        for count in 1..=n {
+            sequences.push(self.sample_sequence_with_rng(true, &mut rng));
+            if every > 0 && count % every == 0 {
+                progress(count, start.elapsed());
             }
         }
-        return zero_sum_keys;
+        sequences
     }
 
-    /// Re-normalize probabilities such that they have the same
-    /// probability distribution as the original HMM
-    fn renormalize(&mut self) {
+    /// Generate `n` sequences (including hidden states) in parallel,
+    /// each worker drawing from its own thread-local random number generator
+    pub fn par_sample_n(&self, n: usize) -> Vec<String> {
+        (0..n).into_par_iter().map(|_| self.sample_sequence(true)).collect()
+    }
 
-        let mut betas: Vec<HashMap<String, f64>> = vec![HashMap::new(); self.hidden_probs.len()];
-        let mut alphas: Vec<HashMap<String, f64>> = vec![HashMap::new(); self.hidden_probs.len()];
+    /// Every valid sequence (including hidden states) in sorted order,
+    /// truncated after `ENUMERATION_LIMIT` paths
+    pub fn enumerate_sequences(&self) -> Vec<String> {
+        self.enumerate_sequences_up_to(ENUMERATION_LIMIT)
+    }
 
-        for i in (0..self.hidden_probs.len()).rev() {
+    /// Every valid sequence (including hidden states) in sorted order,
+    /// walking at most `limit` paths through the trellis
+    pub fn enumerate_sequences_up_to(&self, limit: usize) -> Vec<String> {
+        let mut sequences: Vec<String> = self.enumerate_paths(limit).iter()
+            .map(|path| self.format_path(path, true))
+            .collect();
+        sequences.sort();
+        sequences.dedup();
+        sequences
+    }
 
-            // Renormalize observed values
-            for (outer_key, outer_value) in &mut self.observed_probs[i].iter_mut() {
-                let sum: f64 = outer_value.values().sum::<f64>();  // beta_j = sum of e_jk
-                betas[i].insert(String::from(outer_key), sum);
-                if sum != 0.0 {
-                    for (_, inner_value) in outer_value.iter_mut() {
-                        *inner_value = *inner_value / sum;  // e'_jk = e_jk / beta_j
-                    }
-                }
-            }
+    /// Expected number of distinct sequences among `samples` independent draws,
+    /// i.e. the sum over the support of 1 - (1 - p_s)^samples
+    ///
+    /// The support is enumerated up to `ENUMERATION_LIMIT` paths; if it is larger,
+    /// the remaining probability mass is assumed to be spread thinly enough
+    /// that every draw from it is unique
+    pub fn expected_unique(&self, samples: usize) -> f64 {
+        let paths = self.enumerate_paths(ENUMERATION_LIMIT);
+        let is_truncated = paths.len() >= ENUMERATION_LIMIT;
+
+        // Distinct paths can format to the same sequence when the last
+        // markov order token is cut off at the sequence length
+        let mut sequence_probs: ProbMap<String, f64> = ProbMap::new();
+        for path in paths.iter() {
+            *sequence_probs.entry(self.format_path(path, true)).or_insert(0.0) += self.path_probability(path);
+        }
 
-            if i == self.hidden_probs.len()-1 {
-                for (outer_key, outer_value) in &mut self.hidden_probs[i].iter_mut() {
-                    let mut sum: f64 = 0.0;
-                    for (inner_key, inner_value) in outer_value.iter() {
-                        sum += betas[i][inner_key] * inner_value;  // alpha_j = sum of beta_k * z_jk
+        let mut expected: f64 = sequence_probs.values()
+            .map(|prob| 1.0 - (1.0 - prob).powf(samples as f64))
+            .sum();
+        if is_truncated {
+            let remaining_mass = (1.0 - sequence_probs.values().sum::<f64>()).max(0.0);
+            expected += remaining_mass * samples as f64;
+        }
+        expected
+    }
+
+    /// Marks each position where exactly one hidden state and one observed state
+    /// survive, i.e. where the output is forced by the constraints
+    ///
+    /// Positions are per markov order token (one per sequence position for first order models)
+    pub fn deterministic_positions(&self) -> Vec<bool> {
+        self.reachable_hidden_states().iter().enumerate().map(|(i, states)| {
+            if states.len() != 1 { return false }
+            let hidden = states.iter().next().unwrap();
+            match self.observed_probs[i].get(hidden) {
+                Some(emissions) => emissions.values().filter(|prob| **prob > 0.0).count() == 1,
+                None => false,
+            }
+        }).collect()
+    }
+
+    /// Whether any nonzero path through the trained model places `observed_word`
+    /// at sequence `position`, e.g. to find out why a word is never generated there
+    pub fn reachable_with(&self, position: usize, observed_word: &str) -> bool {
+        let markov_order = self.hidden_markov_model.markov_order as usize;
+        let (token, word_index) = (position / markov_order, position % markov_order);
+        if position >= self.sequence_length || token >= self.observed_probs.len() { return false }
+
+        let forward = self.forward();
+        let backward = self.backward();
+        self.observed_probs[token].iter().any(|(hidden, emissions)| {
+            forward[token].get(hidden).is_some_and(|alpha| *alpha > 0.0)
+                && backward[token].get(hidden).is_some_and(|beta| *beta > 0.0)
+                && emissions.iter().any(|(observed, prob)| {
+                    *prob > 0.0 && observed.split_whitespace().nth(word_index).is_some_and(|word| format_observed(word, false) == observed_word)
+                })
+        })
+    }
+
+    /// Up to `n` observed words starting with `prefix` (ignoring case) that `current_hidden`
+    /// can emit at sequence `position` while leaving a valid path to the end, most probable first
+    pub fn suggest(&self, position: usize, current_hidden: &str, prefix: &str, n: usize) -> Vec<String> {
+        let markov_order = self.hidden_markov_model.markov_order as usize;
+        let (token, word_index) = (position / markov_order, position % markov_order);
+        if position >= self.sequence_length || token >= self.observed_probs.len() { return vec![] }
+
+        let prefix = prefix.to_lowercase();
+        let forward = self.forward();
+        let backward = self.backward();
+        let mut words: ProbMap<String, f64> = ProbMap::new();
+        for (hidden, emissions) in &self.observed_probs[token] {
+            let live = forward[token].get(hidden).is_some_and(|alpha| *alpha > 0.0)
+                && backward[token].get(hidden).is_some_and(|beta| *beta > 0.0);
+            if !live || hidden.split_whitespace().nth(word_index) != Some(current_hidden) { continue }
+            for (observed, prob) in emissions.iter().filter(|(_, prob)| **prob > 0.0) {
+                if let Some(word) = observed.split_whitespace().nth(word_index) {
+                    if word.to_lowercase().starts_with(&prefix) {
+                        *words.entry(format_observed(word, false)).or_insert(0.0) += prob;
                     }
-                    alphas[i].insert(String::from(outer_key), sum);
-                    if sum != 0.0 {
-                        for (inner_key, inner_value) in outer_value.iter_mut() {
-                            *inner_value = (betas[i][inner_key] * *inner_value) / sum;  // z'_jk = (beta_j * z_jk) / alpha_j
+                }
+            }
+        }
+        let mut words: Vec<(String, f64)> = words.into_iter().collect();
+        words.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        words.into_iter().take(n).map(|(word, _)| word).collect()
+    }
+
+    /// Number of distinct observed words that can still be generated at each
+    /// sequence position, i.e. how tightly the constraints restrict the word choice
+    pub fn effective_vocabulary(&self) -> Vec<usize> {
+        let markov_order = self.hidden_markov_model.markov_order as usize;
+        let mut vocabularies: Vec<HashSet<&str>> = vec![HashSet::new(); self.sequence_length];
+        for (i, states) in self.reachable_hidden_states().iter().enumerate() {
+            for hidden in states {
+                let emissions = match self.observed_probs[i].get(hidden) {
+                    Some(emissions) => emissions,
+                    None => continue,
+                };
+                for (observed, _) in emissions.iter().filter(|(_, prob)| **prob > 0.0) {
+                    for (j, word) in observed.split_whitespace().enumerate() {
+                        if let Some(vocabulary) = vocabularies.get_mut(i * markov_order + j) {
+                            vocabulary.insert(word);
                         }
                     }
                 }
+            }
+        }
+        vocabularies.iter().map(|vocabulary| vocabulary.len()).collect()
+    }
+
+    /// Marks each markov order token position whose trained matrices still equal the
+    /// unconstrained model's (within `COMPARE_TOLERANCE`), e.g. to check that positions
+    /// without constraints were not perturbed by pruning or renormalization
+    pub fn positions_equal_unconstrained(&self) -> Vec<bool> {
+        self.hidden_probs.iter().zip(self.observed_probs.iter())
+            .map(|(hidden_probs, observed_probs)| {
+                ConstrainedHiddenMarkov::matrices_close(hidden_probs, &self.hidden_markov_model.hidden_probs)
+                    && ConstrainedHiddenMarkov::matrices_close(observed_probs, &self.hidden_markov_model.observed_probs)
+            })
+            .collect()
+    }
+
+    /// Whether every entry of either matrix is within `COMPARE_TOLERANCE` of the other's (absent entries count as 0)
+    fn matrices_close(matrix: &ProbMap<String, ProbMap<String, f64>>, other: &ProbMap<String, ProbMap<String, f64>>) -> bool {
+        let covered = |a: &ProbMap<String, ProbMap<String, f64>>, b: &ProbMap<String, ProbMap<String, f64>>| {
+            a.iter().all(|(outer_key, inner_map)| inner_map.iter().all(|(inner_key, value)| {
+                let other_value = b.get(outer_key).and_then(|probs| probs.get(inner_key)).unwrap_or(&0.0);
+                (value - other_value).abs() <= COMPARE_TOLERANCE
+            }))
+        };
+        covered(matrix, other) && covered(other, matrix)
+    }
+
+    /// Transition and emission probabilities averaged over the positions where their
+    /// source state is live, i.e. reachable with a nonzero row, as a position-independent
+    /// "typical" constrained distribution to compare against the unconstrained model
+    pub fn averaged_distribution(&self) -> (ProbMatrix, ProbMatrix) {
+        let reachable = self.reachable_hidden_states();
+        let mut start = HashSet::new();
+        start.insert(self.start_state());
+        let transition_sources = std::iter::once(&start).chain(reachable.iter());
+        (ConstrainedHiddenMarkov::average_live_rows(&self.hidden_probs, transition_sources),
+         ConstrainedHiddenMarkov::average_live_rows(&self.observed_probs, reachable.iter()))
+    }
+
+    fn average_live_rows<'a>(matrices: &[ProbMatrix], live_sources: impl Iterator<Item = &'a HashSet<String>>) -> ProbMatrix {
+        let mut sums: ProbMatrix = ProbMap::new();
+        let mut counts: ProbMap<String, f64> = ProbMap::new();
+        for (matrix, live) in matrices.iter().zip(live_sources) {
+            for (source, row) in matrix {
+                if !live.contains(source) || row.values().sum::<f64>() == 0.0 { continue }
+                *counts.entry(source.to_owned()).or_insert(0.0) += 1.0;
+                let summed_row = sums.entry(source.to_owned()).or_default();
+                for (state, prob) in row {
+                    *summed_row.entry(state.to_owned()).or_insert(0.0) += prob;
+                }
+            }
+        }
+        for (source, row) in sums.iter_mut() {
+            row.values_mut().for_each(|prob| *prob /= counts[source]);
+        }
+        sums
+    }
+
+    /// Hidden states reachable from the start through nonzero transitions
+    /// at each markov order token position
+    fn reachable_hidden_states(&self) -> Vec<HashSet<String>> {
+        let mut reachable: Vec<HashSet<String>> = vec![];
+        let mut previous: HashSet<String> = HashSet::new();
+        previous.insert(self.start_state());
+        for i in 0..self.hidden_probs.len() {
+            let mut current: HashSet<String> = HashSet::new();
+            for prev_hidden in previous.iter() {
+                if let Some(transitions) = self.hidden_probs[i].get(prev_hidden) {
+                    for (hidden, prob) in transitions {
+                        if *prob > 0.0 { current.insert(hidden.to_owned()); }
+                    }
+                }
+            }
+            reachable.push(current.clone());
+            previous = current;
+        }
+        reachable
+    }
+
+    /// KL divergence of the constrained distribution from the unconstrained
+    /// model's distribution renormalized over the same support
+    ///
+    /// Renormalization should preserve the relative probabilities of the
+    /// surviving sequences, so this is ~0 when the theory holds.
+    /// Only the first `ENUMERATION_LIMIT` paths of the support are compared
+    pub fn kl_divergence_from_unconstrained(&self) -> f64 {
+        let paths = self.enumerate_paths(ENUMERATION_LIMIT);
+        let unconstrained_probs: Vec<f64> = paths.iter().map(|path| self.unconstrained_path_probability(path)).collect();
+        let unconstrained_sum: f64 = unconstrained_probs.iter().sum();
+        if unconstrained_sum == 0.0 { return 0.0 }
+
+        let mut divergence = 0.0;
+        for (path, unconstrained_prob) in paths.iter().zip(unconstrained_probs.iter()) {
+            let prob = self.path_probability(path);
+            if prob > 0.0 {
+                divergence += prob * (prob / (unconstrained_prob / unconstrained_sum)).ln();
+            }
+        }
+        divergence
+    }
+
+    /// Total probability under the unconstrained model of the sequences that satisfy
+    /// the constraints; near 0 when the constraints are extremely restrictive
+    pub fn surviving_probability_mass(&self) -> f64 {
+        if self.hidden_probs.is_empty() { return 0.0 }
+        let masses = self.completion_masses(&|_, _, _| true);
+        let start_state = self.start_state();
+        self.hidden_probs[0].get(&start_state).map_or(0.0, |transitions| transitions.keys()
+            .map(|hidden| self.surviving_transition(0, &start_state, hidden) * masses[0].get(hidden).unwrap_or(&0.0))
+            .sum())
+    }
+
+    /// Probability of a path under the original, unconstrained hidden markov model
+    fn unconstrained_path_probability(&self, path: &[(String, String)]) -> f64 {
+        let hidden_probs = &self.hidden_markov_model.hidden_probs;
+        let observed_probs = &self.hidden_markov_model.observed_probs;
+        let mut product = 1.0;
+        let mut prev_hidden = self.start_state();
+        for (hidden, observed) in path.iter() {
+            product *= hidden_probs.get(&prev_hidden).and_then(|probs| probs.get(hidden)).unwrap_or(&0.0);
+            product *= observed_probs.get(hidden).and_then(|probs| probs.get(observed)).unwrap_or(&0.0);
+            prev_hidden = hidden.to_owned();
+        }
+        product
+    }
+
+    /// Transition probability under the unconstrained model if the
+    /// transition survived training, otherwise 0.0
+    fn surviving_transition(&self, position: usize, prev_hidden: &str, hidden: &str) -> f64 {
+        let survived = self.hidden_probs[position].get(prev_hidden)
+            .and_then(|probs| probs.get(hidden))
+            .is_some_and(|prob| *prob > 0.0);
+        if !survived { return 0.0 }
+        *self.hidden_markov_model.hidden_probs.get(prev_hidden).and_then(|probs| probs.get(hidden)).unwrap_or(&0.0)
+    }
+
+    /// Emission mass of `hidden` at `position` under the unconstrained model,
+    /// counting only observed states that survived training and pass `allowed`
+    fn surviving_emission_mass(&self, position: usize, hidden: &str, allowed: &dyn Fn(usize, &str, &str) -> bool) -> f64 {
+        let emissions = match self.observed_probs[position].get(hidden) {
+            Some(emissions) => emissions,
+            None => return 0.0,
+        };
+        let unconstrained_emissions = self.hidden_markov_model.observed_probs.get(hidden);
+        emissions.iter()
+            .filter(|(observed, prob)| **prob > 0.0 && allowed(position, hidden, observed))
+            .map(|(observed, _)| *unconstrained_emissions.and_then(|probs| probs.get(observed)).unwrap_or(&0.0))
+            .sum()
+    }
+
+    /// Probability mass under the unconstrained model of completing a valid sequence
+    /// from each hidden state at each position, including that state's own emission
+    ///
+    /// Only transitions and emissions that survived training and pass `allowed` count
+    fn completion_masses(&self, allowed: &dyn Fn(usize, &str, &str) -> bool) -> Vec<ProbMap<String, f64>> {
+        let length = self.hidden_probs.len();
+        let mut masses: Vec<ProbMap<String, f64>> = vec![ProbMap::new(); length];
+        for i in (0..length).rev() {
+            for hidden in self.observed_probs[i].keys() {
+                let emission_mass = self.surviving_emission_mass(i, hidden, allowed);
+                let mass = if i == length - 1 || emission_mass == 0.0 {
+                    emission_mass
+                } else {
+                    let continuation: f64 = match self.hidden_probs[i + 1].get(hidden) {
+                        Some(transitions) => transitions.keys()
+                            .map(|next_hidden| self.surviving_transition(i + 1, hidden, next_hidden)
+                                * masses[i + 1].get(next_hidden).unwrap_or(&0.0))
+                            .sum(),
+                        None => 0.0,
+                    };
+                    emission_mass * continuation
+                };
+                masses[i].insert(hidden.to_owned(), mass);
+            }
+        }
+        masses
+    }
+
+    /// Picks a candidate with probability proportional to its weight,
+    /// or `None` if all weights are zero
+    fn weighted_choice<'a, R: Rng>(candidates: &[(&'a str, f64)], rng: &mut R) -> Option<&'a str> {
+        let total: f64 = candidates.iter().map(|(_, weight)| weight).sum();
+        if total <= 0.0 { return None }
+        let rand_value = rng.gen::<f64>() * total;
+        let mut sum = 0.0;
+        let mut last_nonzero = None;
+        for (candidate, weight) in candidates {
+            if *weight <= 0.0 { continue }
+            sum += weight;
+            last_nonzero = Some(*candidate);
+            if sum > rand_value { return last_nonzero }
+        }
+        last_nonzero
+    }
+
+    /// The hidden state preceding the first sequence position
+    fn start_state(&self) -> String {
+        vec![START_TOKEN; self.hidden_markov_model.markov_order as usize].join(" ")
+    }
+
+    /// Depth-first search over the nonzero trellis, returning up to `limit`
+    /// complete paths as (hidden, observed) states per markov order token
+    fn enumerate_paths(&self, limit: usize) -> Vec<Vec<(String, String)>> {
+        let mut paths = vec![];
+        let mut path: Vec<(String, String)> = vec![];
+        let mut stack = self.path_extensions(0, &self.start_state());
+        while let Some((i, hidden, observed)) = stack.pop() {
+            if paths.len() >= limit { break }
+            path.truncate(i);
+            path.push((hidden, observed));
+            if i + 1 == self.hidden_probs.len() {
+                paths.push(path.clone());
+            } else {
+                stack.extend(self.path_extensions(i + 1, &path[i].0));
+            }
+        }
+        paths
+    }
+
+    /// Nonzero (hidden, observed) states at `position` reachable from `hidden`
+    fn path_extensions(&self, position: usize, hidden: &str) -> Vec<(usize, String, String)> {
+        let mut extensions = vec![];
+        let transitions = match self.hidden_probs.get(position).and_then(|probs| probs.get(hidden)) {
+            Some(transitions) => transitions,
+            None => return extensions,
+        };
+        for (next_hidden, transition_prob) in transitions {
+            if *transition_prob == 0.0 { continue }
+            if let Some(emissions) = self.observed_probs[position].get(next_hidden) {
+                for (observed, observed_prob) in emissions {
+                    if *observed_prob > 0.0 {
+                        extensions.push((position, next_hidden.to_owned(), observed.to_owned()));
+                    }
+                }
+            }
+        }
+        extensions
+    }
+
+    /// Probability of a path of (hidden, observed) states per markov order token
+    fn path_probability(&self, path: &[(String, String)]) -> f64 {
+        let mut product = 1.0;
+        let mut prev_hidden = self.start_state();
+        for (i, (hidden, observed)) in path.iter().enumerate() {
+            product *= self.hidden_probs[i].get(&prev_hidden).and_then(|probs| probs.get(hidden)).unwrap_or(&0.0);
+            product *= self.observed_probs[i].get(hidden).and_then(|probs| probs.get(observed)).unwrap_or(&0.0);
+            prev_hidden = hidden.to_owned();
+        }
+        product
+    }
+
+    /// Formats a path the same way as `sample_sequence`
+    fn format_path(&self, path: &[(String, String)], include_hidden: bool) -> String {
+        self.format_path_with(path, &SequenceFormat { include_hidden, ..Default::default() })
+    }
+
+    fn format_path_with(&self, path: &[(String, String)], fmt: &SequenceFormat) -> String {
+        let tokens: Vec<String> = path.iter()
+            .flat_map(|(hidden, observed)| observed.split_whitespace().zip(hidden.split_whitespace()))
+            .take(self.sequence_length)
+            .take_while(|(_, hidden)| *hidden != END_TOKEN)
+            .map(|(observed, hidden)| if fmt.include_hidden {
+                format!("{}{}{}", format_observed(observed, true), fmt.field_sep, hidden)
             } else {
-                for (outer_key, outer_value) in &mut self.hidden_probs[i].iter_mut() {
-                    let mut sum: f64 = 0.0;
-                    for (inner_key, inner_value) in outer_value.iter() {
-                        let alpha: f64;
-                        match alphas[i + 1].get(inner_key) {
-                            Some(value) => alpha = *value,
-                            None => alpha = 0.0
+                format_observed(observed, false)
+            })
+            .collect();
+        tokens.join(&fmt.token_sep)
+    }
+
+    fn next_token<'a, R: Rng>(prev_token_map: &'a ProbMap<String, f64>, rng: &mut R) -> &'a str {
+        let mut sum = 0.0;
+        let rand_value: f64 = rng.gen();
+        for potential_token in prev_token_map {
+            sum += potential_token.1;
+            if sum > rand_value {
+                return potential_token.0;
+            }
+        }
+        return ""
+    }
+
+    /// Like `next_token`, but walks the candidates in sorted order so that
+    /// the same draw always picks the same token
+    fn next_token_from_draw(prev_token_map: &ProbMap<String, f64>, draw: f64) -> &str {
+        let mut candidates: Vec<(&String, &f64)> = prev_token_map.iter().collect();
+        candidates.sort_by(|a, b| a.0.cmp(b.0));
+        let mut sum = 0.0;
+        for (token, prob) in candidates {
+            sum += prob;
+            if sum > draw {
+                return token;
+            }
+        }
+        ""
+    }
+
+    /// Forward probabilities: per position, the probability of reaching each hidden
+    /// state from the start while emitting valid observed states along the way
+    pub fn forward(&self) -> Vec<ProbMap<String, f64>> {
+        let mut alphas: Vec<ProbMap<String, f64>> = Vec::with_capacity(self.hidden_probs.len());
+        let mut previous: ProbMap<String, f64> = ProbMap::from([(self.start_state(), 1.0)]);
+        for i in 0..self.hidden_probs.len() {
+            let mut current: ProbMap<String, f64> = ProbMap::new();
+            for (prev_hidden, prev_alpha) in &previous {
+                if let Some(transitions) = self.hidden_probs[i].get(prev_hidden) {
+                    for (hidden, transition_prob) in transitions {
+                        *current.entry(hidden.to_owned()).or_insert(0.0) +=
+                            prev_alpha * transition_prob * self.emission_mass(i, hidden);
+                    }
+                }
+            }
+            alphas.push(current.clone());
+            previous = current;
+        }
+        alphas
+    }
+
+    /// Backward probabilities: per position, the probability of completing a valid
+    /// sequence from each hidden state to the end (excluding that state's own emission)
+    pub fn backward(&self) -> Vec<ProbMap<String, f64>> {
+        let length = self.hidden_probs.len();
+        let mut betas: Vec<ProbMap<String, f64>> = vec![ProbMap::new(); length];
+        for i in (0..length).rev() {
+            let states: HashSet<&String> = self.hidden_probs[i].values().flat_map(|transitions| transitions.keys()).collect();
+            let values: Vec<(String, f64)> = states.into_iter().map(|hidden| {
+                let beta = if i == length - 1 {
+                    1.0
+                } else {
+                    self.hidden_probs[i + 1].get(hidden).map_or(0.0, |transitions| transitions.iter()
+                        .map(|(next_hidden, transition_prob)| transition_prob * self.emission_mass(i + 1, next_hidden)
+                            * betas[i + 1].get(next_hidden).unwrap_or(&0.0))
+                        .sum())
+                };
+                (hidden.to_owned(), beta)
+            }).collect();
+            betas[i].extend(values);
+        }
+        betas
+    }
+
+    /// Total emission probability of `hidden` at `position`
+    fn emission_mass(&self, position: usize, hidden: &str) -> f64 {
+        self.observed_probs[position].get(hidden).map_or(0.0, |emissions| emissions.values().sum())
+    }
+
+    /// Nonzero transition probabilities as CSV rows of `position,from_state,to_state,probability`,
+    /// sorted by position then state, e.g. for loading into pandas
+    pub fn to_csv(&self) -> String {
+        ConstrainedHiddenMarkov::matrices_to_csv(&self.hidden_probs)
+    }
+
+    /// Nonzero emission probabilities in the same layout as `to_csv`,
+    /// with the hidden state as `from_state` and the observed state as `to_state`
+    pub fn emissions_to_csv(&self) -> String {
+        ConstrainedHiddenMarkov::matrices_to_csv(&self.observed_probs)
+    }
+
+    fn matrices_to_csv(matrices: &[ProbMap<String, ProbMap<String, f64>>]) -> String {
+        let mut csv = String::from("position,from_state,to_state,probability\n");
+        for (position, matrix) in matrices.iter().enumerate() {
+            let mut rows: Vec<(&String, &String, &f64)> = matrix.iter()
+                .flat_map(|(from, transitions)| transitions.iter().map(move |(to, prob)| (from, to, prob)))
+                .filter(|(_, _, prob)| **prob > 0.0)
+                .collect();
+            rows.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+            for (from, to, prob) in rows {
+                csv.push_str(&format!("{},{},{},{}\n", position, ConstrainedHiddenMarkov::csv_field(from),
+                                      ConstrainedHiddenMarkov::csv_field(to), prob));
+            }
+        }
+        csv
+    }
+
+    /// Quotes `field` if it contains a comma, quote or newline, doubling any quotes
+    fn csv_field(field: &str) -> String {
+        if field.contains([',', '"', '\n']) {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_owned()
+        }
+    }
+
+    /// Totals across all per-position matrices; pruning zeroes entries
+    /// in place, so compare `nonzero_entries` before and after training
+    pub fn stats(&self) -> ChmmStats {
+        let mut stats = ChmmStats::default();
+        let entry_bytes = size_of::<String>() + size_of::<f64>();
+        for matrix in self.hidden_probs.iter().chain(self.observed_probs.iter()) {
+            for (outer_key, inner_map) in matrix {
+                stats.outer_keys += 1;
+                stats.estimated_bytes += size_of::<String>() + outer_key.len() + size_of::<ProbMap<String, f64>>();
+                for (inner_key, inner_value) in inner_map {
+                    stats.inner_entries += 1;
+                    stats.estimated_bytes += entry_bytes + inner_key.len();
+                    if *inner_value != 0.0 {
+                        stats.nonzero_entries += 1;
+                    }
+                }
+            }
+        }
+        stats
+    }
+
+    fn clear_probs(&mut self) {
+        self.hidden_probs.clear();
+        self.observed_probs.clear();
+        self.cut_states.clear();
+    }
+
+    fn check_sequence_and_constraint_length(&self) {
+        assert_eq!(self.sequence_length, self.hidden_constraints.len());
+        assert_eq!(self.sequence_length, self.observed_constraints.len());
+        assert!(self.sequence_length >= self.hidden_markov_model.markov_order as usize);
+    }
+
+    fn get_markov_order_token_length(&self) -> usize {
+        let markov_order = self.hidden_markov_model.markov_order as usize;
+        return if self.sequence_length % markov_order == 0 {
+            self.sequence_length / markov_order
+        } else {
+            self.sequence_length / markov_order + 1
+        }
+    }
+
+    /// Performs deep copy of non-constrained hidden markov model
+    /// probabilities for each sequence position
+    fn duplicate_matrices(&mut self) {
+        let markov_order = self.hidden_markov_model.markov_order as usize;
+        let end_matrix = if self.sequence_length % markov_order > 0 { 1 } else { 0 };
+        for i in 0..(self.sequence_length / markov_order + end_matrix) {
+            self.hidden_probs.insert(i,self.hidden_markov_model.hidden_probs.clone());
+            self.observed_probs.insert(i,self.hidden_markov_model.observed_probs.clone());
+        }
+    }
+
+    /// Removes states that violate constraints on hidden
+    /// or observed sequence positions
+    fn remove_constrain_violating_states(&mut self) {
+        if self.parallel_constraints {
+            return self.par_remove_constrain_violating_states();
+        }
+        self.remove_constrain_violating_hidden_states();
+        self.remove_constrain_violating_observed_states()
+    }
+
+    /// Same as `remove_constrain_violating_states`, but evaluates the constraints across threads
+    /// and zeroes the violating entries afterwards
+    fn par_remove_constrain_violating_states(&mut self) {
+        let markov_order = self.hidden_markov_model.markov_order as usize;
+        for i in 0..self.get_markov_order_token_length() {
+            let hidden_violations = ConstrainedHiddenMarkov::par_violating_entries(&self.hidden_probs[i], &self.hidden_constraints, markov_order, i);
+            let observed_violations = ConstrainedHiddenMarkov::par_violating_entries(&self.observed_probs[i], &self.observed_constraints, markov_order, i);
+            for (matrix, violations) in [(&mut self.hidden_probs[i], hidden_violations), (&mut self.observed_probs[i], observed_violations)] {
+                for (outer_key, inner_key) in violations {
+                    if let Some(value) = matrix.get_mut(&outer_key).and_then(|outer_map| outer_map.get_mut(&inner_key)) {
+                        *value = 0.0;
+                    }
+                }
+            }
+        }
+    }
+
+    /// (outer, inner) keys of the entries at markov order token position `i`
+    /// whose inner state violates a constraint
+    fn par_violating_entries(matrix: &ProbMap<String, ProbMap<String, f64>>, constraints: &[Box<dyn Constraint + Send>],
+                             markov_order: usize, i: usize) -> Vec<(String, String)> {
+        matrix.par_iter().flat_map_iter(|(outer_key, outer_map)| {
+            outer_map.keys().filter(move |inner_map_key| {
+                inner_map_key.split_whitespace().enumerate().any(|(j, state)| {
+                    let position = (i*markov_order)+j;
+                    j < markov_order && position < constraints.len()
+                        && !constraints[position].is_satisfied_by_state(format_observed(state, false))
+                })
+            }).map(move |inner_map_key| (outer_key.to_owned(), inner_map_key.to_owned()))
+        }).collect()
+    }
+
+    fn remove_constrain_violating_hidden_states(&mut self) {
+        let markov_order = self.hidden_markov_model.markov_order as usize;
+        let (cache_ids, mut caches) = ConstrainedHiddenMarkov::constraint_caches(&self.hidden_constraints);
+        for i in 0..self.get_markov_order_token_length() {
+            for (_, outer_map) in self.hidden_probs[i].iter_mut() {
+                for (inner_map_key, inner_map_val) in outer_map.iter_mut() {
+                    // Check for constraint satisfaction for each token
+                    // (split for markov orders higher than 1)
+                    let mut state_markov_split = inner_map_key.split_whitespace();
+                    for j in 0..(markov_order) {
+                        match state_markov_split.next() {
+                            None => {}
+                            Some(state) => {
+                                // continue if inside last markov order token but past constraints
+                                let position = (i*markov_order)+j;
+                                if position >= self.hidden_constraints.len() { continue };
+                                let cache = &mut caches[cache_ids[position]];
+                                if !ConstrainedHiddenMarkov::is_satisfied_cached(&*self.hidden_constraints[position], cache, state) {
+                                    *inner_map_val = 0.0;
+                                }
+                            }
                         }
-                        sum += betas[i][inner_key] * alpha * inner_value;  // alpha_j = sum of beta_k * alpha^(i+1)_k * z_jk
                     }
-                    alphas[i].insert(String::from(outer_key), sum);
-                    if sum != 0.0 {
-                        for (inner_key, inner_value) in outer_value.iter_mut() {
-                            let alpha: f64;
-                            match alphas[i + 1].get(inner_key) {
-                                Some(value) => alpha = *value,
-                                None => alpha = 0.0
+                }
+            }
+        }
+    }
+
+    fn remove_constrain_violating_observed_states(&mut self) {
+        let markov_order = self.hidden_markov_model.markov_order as usize;
+        let (cache_ids, mut caches) = ConstrainedHiddenMarkov::constraint_caches(&self.observed_constraints);
+        for i in 0..self.get_markov_order_token_length() {
+            for (_, outer_map) in self.observed_probs[i].iter_mut() {
+                for (inner_map_key, inner_map_val) in outer_map.iter_mut() {
+                    // Check for constraint satisfaction for each token
+                    // (split for markov orders higher than 1)
+                    let mut state_markov_split = inner_map_key.split_whitespace();
+                    for j in 0..(markov_order) {
+                        match state_markov_split.next() {
+                            None => {}
+                            Some(state) => {
+                                // continue if inside last markov order token but past constraints
+                                let position = (i*markov_order)+j;
+                                if position >= self.observed_constraints.len() { continue };
+                                let cache = &mut caches[cache_ids[position]];
+                                if !ConstrainedHiddenMarkov::is_satisfied_cached(&*self.observed_constraints[position], cache, state) {
+                                    *inner_map_val = 0.0;
+                                }
                             }
-                            *inner_value = (betas[i][inner_key] * alpha * *inner_value) / sum;  // z'_jk = (beta_j * alpha^(i+1)_k * z_jk) / alpha_j
                         }
                     }
                 }
@@ -313,236 +1505,1652 @@ impl ConstrainedHiddenMarkov {
         }
     }
 
-    /// Calculate the probability to generate a given sequence
-    pub fn get_sequence_probability(&self, sequence: &str) -> f64 {
-        let tokens = sequence.split_whitespace();
-        let mut product: f64 = 1.0;
-        let mut curr_hidden = String::from(START_TOKEN);
+    fn remove_joint_constraint_violating_states(&mut self) {
+        if self.joint_constraints.is_empty() { return }
+        let markov_order = self.hidden_markov_model.markov_order as usize;
+        for i in 0..self.get_markov_order_token_length() {
+            for (outer_key, outer_map) in self.observed_probs[i].iter_mut() {
+                for (inner_map_key, inner_map_val) in outer_map.iter_mut() {
+                    // Pair up each observed and hidden token of the markov order token
+                    let pairs = inner_map_key.split_whitespace().zip(outer_key.split_whitespace());
+                    for (j, (observed, hidden)) in pairs.enumerate() {
+                        let position = (i*markov_order)+j;
+                        if position >= self.joint_constraints.len() { continue };
+                        if !self.joint_constraints[position].is_satisfied_by(&format_observed(observed, false), hidden) {
+                            *inner_map_val = 0.0;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Groups equal constraints so they share a cache of satisfaction results per word,
+    /// returning the cache index of every position and the empty caches
+    fn constraint_caches(constraints: &[Box<dyn Constraint + Send>]) -> (Vec<usize>, Vec<ProbMap<String, bool>>) {
+        let mut distinct: Vec<&Box<dyn Constraint + Send>> = vec![];
+        let cache_ids = constraints.iter().map(|constraint| {
+            match distinct.iter().position(|seen| *seen == constraint) {
+                Some(id) => id,
+                None => {
+                    distinct.push(constraint);
+                    distinct.len() - 1
+                }
+            }
+        }).collect();
+        (cache_ids, vec![ProbMap::new(); distinct.len()])
+    }
+
+    fn is_satisfied_cached(constraint: &dyn Constraint, cache: &mut ProbMap<String, bool>, state: &str) -> bool {
+        if let Some(satisfied) = cache.get(state) { return *satisfied }
+        // Multi-word chunks are checked as space separated words
+        let satisfied = constraint.is_satisfied_by_state(format_observed(state, false));
+        cache.insert(String::from(state), satisfied);
+        satisfied
+    }
+
+    /// Removes transitions into `END_TOKEN` that would end a sequence before `min_length` tokens
+    fn remove_early_end_states(&mut self) {
+        let markov_order = self.hidden_markov_model.markov_order as usize;
+        let min_length = self.min_length;
+        for (i, matrix) in self.hidden_probs.iter_mut().enumerate() {
+            for outer_map in matrix.values_mut() {
+                for (inner_key, inner_value) in outer_map.iter_mut() {
+                    let end_index = inner_key.split_whitespace().position(|state| state == END_TOKEN);
+                    if end_index.is_some_and(|j| i * markov_order + j < min_length) {
+                        *inner_value = 0.0;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Removes state transitions that lead to a zero probability solution
+    /// i.e. enforces arc-consistency
+    ///
+    /// this is a tree-structured CSP, so can be done in a single pass
+    fn remove_dead_states(&mut self) {
+        // Working backwards through the sequence positions
+        // Remove hidden states whose observed state sums to 0.0
+        for i in (0..self.hidden_probs.len()).rev() {
+            let current_hidden = &mut self.hidden_probs[i];
+            let current_observed = &mut self.observed_probs[i];
+
+            // Remove hidden states whose observed state sums to 0.0
+            // from the current sequence position
+            let states_to_remove_in_curr: HashSet<String> =
+                ConstrainedHiddenMarkov::get_zero_sum_outer_keys(current_observed);
+            for (_, outer_value) in current_hidden.iter_mut() {
+                for (inner_key, inner_value) in outer_value.iter_mut() {
+                    if states_to_remove_in_curr.contains(inner_key) {
+                        *inner_value = 0.0;
+                    }
+                }
+            }
+        }
+
+        // Remove dead states based on transitions
+        if self.selective_pruning {
+            self.remove_dead_states_most_constrained_first();
+        } else {
+            for i in (1..self.hidden_probs.len()).rev() {
+                self.remove_transitions_to_dead_states(i);
+            }
+        }
+
+        // Hidden constraints can strand states whose emissions are still nonzero
+        let has_hidden_constraints = self.hidden_constraints.iter()
+            .any(|constraint| constraint.as_any().downcast_ref::<EmptyConstraint>().is_none());
+        if has_hidden_constraints {
+            self.remove_unreachable_emissions();
+        }
+    }
+
+    /// Removes transitions at position `i - 1` into states whose transitions at position `i`
+    /// sum to 0.0 or that have none there, returning whether any transition was removed
+    fn remove_transitions_to_dead_states(&mut self, i: usize) -> bool {
+        let (previous, current) = self.hidden_probs.split_at_mut(i);
+        let current_hidden = &current[0];
+        let dead_states: HashSet<&str> = current_hidden.iter()
+            .filter(|(_, transitions)| transitions.values().sum::<f64>() == 0.0)
+            .map(|(state, _)| state.as_str())
+            .collect();
+
+        let mut removed = false;
+        for (_, outer_value) in previous[i - 1].iter_mut() {
+            for (inner_key, inner_value) in outer_value.iter_mut() {
+                if *inner_value != 0.0 && (dead_states.contains(inner_key.as_str()) || !current_hidden.contains_key(inner_key)) {
+                    *inner_value = 0.0;
+                    removed = true;
+                }
+            }
+        }
+        removed
+    }
+
+    /// Arc-consistency run as a worklist ordered by the number of surviving hidden states,
+    /// so a position is revisited only after transitions into it were removed
+    fn remove_dead_states_most_constrained_first(&mut self) {
+        let surviving_states = |transitions: &ProbMap<String, ProbMap<String, f64>>| transitions.values()
+            .filter(|next| next.values().sum::<f64>() != 0.0)
+            .count();
+        let mut positions: Vec<(usize, usize)> = (1..self.hidden_probs.len())
+            .map(|i| (surviving_states(&self.hidden_probs[i]), i))
+            .collect();
+        positions.sort_unstable_by(|a, b| b.cmp(a));
+
+        // The most constrained position is on top of the stack
+        let mut pending: Vec<usize> = positions.into_iter().map(|(_, i)| i).collect();
+        let mut is_pending = vec![true; self.hidden_probs.len()];
+        while let Some(i) = pending.pop() {
+            is_pending[i] = false;
+            if self.remove_transitions_to_dead_states(i) && i > 1 && !is_pending[i - 1] {
+                is_pending[i - 1] = true;
+                pending.push(i - 1);
+            }
+        }
+    }
+
+    /// Zeroes the emissions of hidden states without a surviving incoming
+    /// transition from a reachable state at the previous position
+    fn remove_unreachable_emissions(&mut self) {
+        let mut reachable: HashSet<String> = HashSet::from([self.start_state()]);
+        for i in 0..self.hidden_probs.len() {
+            reachable = self.hidden_probs[i].iter()
+                .filter(|(outer_key, _)| reachable.contains(*outer_key))
+                .flat_map(|(_, outer_value)| outer_value.iter())
+                .filter(|(_, inner_value)| **inner_value != 0.0)
+                .map(|(inner_key, _)| inner_key.to_owned())
+                .collect();
+            for (outer_key, outer_value) in self.observed_probs[i].iter_mut() {
+                if !reachable.contains(outer_key) {
+                    outer_value.values_mut().for_each(|inner_value| *inner_value = 0.0);
+                }
+            }
+        }
+    }
+
+    fn get_zero_sum_outer_keys(probability_matrix: &mut ProbMap<String, ProbMap<String, f64>>) -> HashSet<String> {
+        let mut zero_sum_keys: HashSet<String> = HashSet::new();
+        for (outer_key, outer_value) in probability_matrix.iter() {
+            if outer_value.values().sum::<f64>() == 0.0 {
+                zero_sum_keys.insert(String::from(outer_key));
+            }
+        }
+        return zero_sum_keys;
+    }
+
+    /// Re-normalize probabilities such that they have the same
+    /// probability distribution as the original HMM
+    fn renormalize(&mut self) {
+
+        let mut betas: Vec<ProbMap<String, f64>> = vec![ProbMap::new(); self.hidden_probs.len()];
+        let mut alphas: Vec<ProbMap<String, f64>> = vec![ProbMap::new(); self.hidden_probs.len()];
+
+        for i in (0..self.hidden_probs.len()).rev() {
+
+            // Renormalize observed values
+            for (outer_key, outer_value) in &mut self.observed_probs[i].iter_mut() {
+                let sum: f64 = outer_value.values().sum::<f64>();  // beta_j = sum of e_jk
+                betas[i].insert(String::from(outer_key), sum);
+                if sum != 0.0 {
+                    for (_, inner_value) in outer_value.iter_mut() {
+                        *inner_value = *inner_value / sum;  // e'_jk = e_jk / beta_j
+                    }
+                }
+            }
+
+            if i == self.hidden_probs.len()-1 {
+                for (outer_key, outer_value) in &mut self.hidden_probs[i].iter_mut() {
+                    let mut sum: f64 = 0.0;
+                    for (inner_key, inner_value) in outer_value.iter() {
+                        sum += betas[i][inner_key] * inner_value;  // alpha_j = sum of beta_k * z_jk
+                    }
+                    alphas[i].insert(String::from(outer_key), sum);
+                    if sum != 0.0 {
+                        for (inner_key, inner_value) in outer_value.iter_mut() {
+                            *inner_value = (betas[i][inner_key] * *inner_value) / sum;  // z'_jk = (beta_j * z_jk) / alpha_j
+                        }
+                    }
+                }
+            } else {
+                for (outer_key, outer_value) in &mut self.hidden_probs[i].iter_mut() {
+                    let mut sum: f64 = 0.0;
+                    for (inner_key, inner_value) in outer_value.iter() {
+                        let alpha: f64;
+                        match alphas[i + 1].get(inner_key) {
+                            Some(value) => alpha = *value,
+                            None => alpha = 0.0
+                        }
+                        sum += betas[i][inner_key] * alpha * inner_value;  // alpha_j = sum of beta_k * alpha^(i+1)_k * z_jk
+                    }
+                    alphas[i].insert(String::from(outer_key), sum);
+                    if sum != 0.0 {
+                        for (inner_key, inner_value) in outer_value.iter_mut() {
+                            let alpha: f64;
+                            match alphas[i + 1].get(inner_key) {
+                                Some(value) => alpha = *value,
+                                None => alpha = 0.0
+                            }
+                            *inner_value = (betas[i][inner_key] * alpha * *inner_value) / sum;  // z'_jk = (beta_j * alpha^(i+1)_k * z_jk) / alpha_j
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Per position, human-readable descriptions of the (observed, hidden) constraints,
+    /// e.g. for rendering the active constraints in a UI; see `constraints::describe`
+    pub fn constraint_summary(&self) -> Vec<(usize, String, String)> {
+        self.observed_constraints.iter().zip(self.hidden_constraints.iter()).enumerate()
+            .map(|(position, (observed, hidden))| (position, describe(observed.as_ref()), describe(hidden.as_ref())))
+            .collect()
+    }
+
+    /// Calculate the probability to generate a given sequence, or `None` if it does not
+    /// have exactly `sequence_length` tokens, has an unclosed quote or a token without a colon
+    ///
+    /// Tokens are scored in markov order chunks; unseen entries give a probability of 0
+    pub fn get_sequence_probability(&self, sequence: &str) -> Option<f64> {
+        let tokens = split_tokens(sequence).ok()?.iter()
+            .map(|token| HiddenMarkov::split_token(token))
+            .collect::<Option<Vec<(String, String)>>>()?;
+        if tokens.len() != self.sequence_length { return None }
+        let mut product: f64 = 1.0;
+        let mut prev_hidden = self.start_state();
+
+        for (i, chunk) in tokens.chunks(self.hidden_markov_model.markov_order as usize).enumerate() {
+            let hidden = chunk.iter().map(|(_, hidden)| hidden.as_str()).collect::<Vec<&str>>().join(" ");
+            let observed = chunk.iter().map(|(observed, _)| observed.as_str()).collect::<Vec<&str>>().join(" ");
+            product *= HiddenMarkov::get_prob(&self.hidden_probs[i], &prev_hidden, &hidden);
+            product *= HiddenMarkov::get_prob(&self.observed_probs[i], &hidden, &observed);
+            prev_hidden = hidden;
+        }
+        Some(product)
+    }
+
+    /// Unconstrained probability of `sequence` under the per-position orders of
+    /// `with_position_orders`, or `None` if none were given, the length is wrong
+    /// or a token is not an `observed:hidden` pair
+    pub fn get_mixed_order_sequence_probability(&self, sequence: &str) -> Option<f64> {
+        let mixed_order = self.mixed_order.as_ref()?;
+        let tokens: Vec<(String, String)> = split_tokens(sequence).ok()?.iter()
+            .map(|token| token.split_once(':').map(|(observed, hidden)| (observed.to_owned(), hidden.to_owned())))
+            .collect::<Option<Vec<(String, String)>>>()?;
+        if tokens.len() != self.sequence_length { return None }
+        Some(mixed_order.sequence_probability(&tokens))
+    }
+
+    /// Human-readable reasons why `sequence` (space separated `observed:hidden` tokens)
+    /// has zero probability, one per zero transition or emission, or none if it is possible
+    ///
+    /// Each reason names the sequence positions of the markov order token (or the single
+    /// position whose constraint rules it out) and whether the entry was never seen in
+    /// training, is ruled out by a constraint, or was pruned afterwards because it could
+    /// not be part of a complete path
+    pub fn explain_zero_probability(&self, sequence: &str) -> Vec<String> {
+        let tokens = match split_tokens(sequence) {
+            Ok(tokens) => tokens,
+            Err(e) => return vec![e.to_string()],
+        };
+        let tokens: Vec<(String, String)> = match tokens.iter().map(|token| HiddenMarkov::split_token(token).ok_or(token)).collect() {
+            Ok(tokens) => tokens,
+            Err(token) => return vec![format!("token '{}' has no hidden state", format_observed(token, true))],
+        };
+        if tokens.len() != self.sequence_length {
+            return vec![format!("sequence has {} tokens but the model generates {}", tokens.len(), self.sequence_length)]
+        }
+        let pruned = if self.joint_constraints.is_empty() { "pruned by arc-consistency" } else { "pruned by arc-consistency or a joint constraint" };
+        let markov_order = self.hidden_markov_model.markov_order as usize;
+        let mut reasons = vec![];
+        let mut prev_hidden = self.start_state();
+        for (i, chunk) in tokens.chunks(markov_order).enumerate() {
+            let hidden = chunk.iter().map(|(_, hidden)| hidden.as_str()).collect::<Vec<&str>>().join(" ");
+            let observed = chunk.iter().map(|(observed, _)| observed.as_str()).collect::<Vec<&str>>().join(" ");
+            let positions = i * markov_order..i * markov_order + chunk.len();
+            let at = if chunk.len() == 1 {
+                format!("position {}", positions.start)
+            } else {
+                format!("positions {}-{}", positions.start, positions.end - 1)
+            };
+
+            if HiddenMarkov::get_prob(&self.hidden_probs[i], &prev_hidden, &hidden) == 0.0 {
+                let violated = positions.clone().zip(chunk)
+                    .find(|(j, (_, tag))| !self.hidden_constraints[*j].is_satisfied_by_state(tag.to_owned()));
+                reasons.push(match violated {
+                    _ if HiddenMarkov::get_prob(&self.hidden_markov_model.hidden_probs, &prev_hidden, &hidden) == 0.0 =>
+                        format!("{}: transition {}->{} never seen in training", at, prev_hidden, hidden),
+                    Some((j, (_, tag))) => format!("position {}: hidden '{}' not allowed by constraint", j, tag),
+                    None => format!("{}: transition {}->{} has probability 0 ({})", at, prev_hidden, hidden, pruned),
+                });
+            }
+            if HiddenMarkov::get_prob(&self.observed_probs[i], &hidden, &observed) == 0.0 {
+                let violated = positions.zip(chunk)
+                    .find(|(j, (word, _))| !self.observed_constraints[*j].is_satisfied_by_state(format_observed(word, false)));
+                reasons.push(match violated {
+                    _ if HiddenMarkov::get_prob(&self.hidden_markov_model.observed_probs, &hidden, &observed) == 0.0 =>
+                        format!("{}: observed '{}' never emitted by {} in training", at, format_observed(&observed, false), hidden),
+                    Some((j, (word, _))) => format!("position {}: observed '{}' not allowed by constraint", j, format_observed(word, false)),
+                    None => format!("{}: emission {}->'{}' has probability 0 ({})", at, hidden, format_observed(&observed, false), pruned),
+                });
+            }
+            prev_hidden = hidden;
+        }
+        reasons
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constraints::empty_constraint::EmptyConstraint;
+    use crate::constraints::starts_with_letter_constraint::StartsWithLetterConstraint;
+    use crate::constraints::matches_constraint::MatchesConstraint;
+    use crate::utils::{get_test_constraints, START_TOKEN};
+    use crate::constraints::multi_constraint::MultiConstraint;
+    use crate::constraints::distinct_word_budget_constraint::DistinctWordBudgetConstraint;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::any::Any;
+    use std::fmt::{Error, Formatter};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn create_constrained_hidden_markov() {
+        let model = ConstrainedHiddenMarkov {
+            hidden_markov_model: HiddenMarkov::new(1, Default::default()),
+            sequence_length: 4,
+            hidden_probs: Default::default(),
+            observed_probs: Default::default(),
+            hidden_constraints: Default::default(),
+            observed_constraints: Default::default(),
+            min_length: 0,
+            joint_constraints: vec![],
+            parallel_constraints: false,
+            selective_pruning: false,
+            mixed_order: None,
+            sequence_constraints: vec![],
+            cut_states: vec![],
+        };
+        assert_eq!(1, model.hidden_markov_model.markov_order);
+        assert_eq!(4, model.sequence_length);
+    }
+
+    #[test]
+    fn clear_probabilities_chmm() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, None);
+
+        constrained_model.hidden_probs.push(model.hidden_probs.clone());
+        constrained_model.hidden_probs.push(model.hidden_probs.clone());
+        constrained_model.hidden_probs.push(model.hidden_probs.clone());
+        constrained_model.observed_probs.push(model.observed_probs.clone());
+        constrained_model.observed_probs.push(model.observed_probs.clone());
+
+        assert_eq!(3, constrained_model.hidden_probs.len());
+        assert_eq!(2, constrained_model.observed_probs.len());
+
+        constrained_model.clear_probs();
+        assert_eq!(0, constrained_model.hidden_probs.len());
+        assert_eq!(0, constrained_model.observed_probs.len());
+    }
+    
+    #[test]
+    fn constraints_chmm() {
+        let constraints: Vec<Box<dyn Constraint + Send>> = vec![
+            Box::new(StartsWithLetterConstraint::new('f')),
+            Box::new(EmptyConstraint::new()),
+            Box::new(EmptyConstraint::new()),
+            Box::new(MatchesConstraint::new(String::from("red"))),
+        ];
+        let model = ConstrainedHiddenMarkov {
+            hidden_markov_model: HiddenMarkov::new(1, Default::default()),
+            sequence_length: 4,
+            hidden_probs: Default::default(),
+            observed_probs: Default::default(),
+            hidden_constraints: Default::default(),
+            observed_constraints: constraints,
+            min_length: 0,
+            joint_constraints: vec![],
+            parallel_constraints: false,
+            selective_pruning: false,
+            mixed_order: None,
+            sequence_constraints: vec![],
+            cut_states: vec![],
+        };
+        assert_eq!(4, model.observed_constraints.len());
+        assert_eq!(true, model.observed_constraints[0].is_satisfied_by_state(String::from("Fred")));
+        assert_eq!(true, model.observed_constraints[1].is_satisfied_by_state(String::from("anything")));
+        assert_eq!(true, model.observed_constraints[2].is_satisfied_by_state(String::from("")));
+        assert_eq!(false, model.observed_constraints[3].is_satisfied_by_state(String::from("reds")));
+    }
+
+    #[test]
+    fn remove_constraint_violating_states_chmm() {
+        let observed_constraints: Vec<Box<dyn Constraint + Send>> = vec![
+            Box::new(StartsWithLetterConstraint::new('t')),
+            Box::new(EmptyConstraint::new()),
+            Box::new(EmptyConstraint::new()),
+            Box::new(MatchesConstraint::new(String::from("red"))),
+        ];
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, Some(observed_constraints));
+
+        constrained_model.duplicate_matrices();
+        constrained_model.remove_constrain_violating_states();
+        assert_eq!(0.0, constrained_model.observed_probs[0]["VBZ"]["likes"]);
+        assert_eq!(0.2, constrained_model.observed_probs[0]["NNP"]["Ted"]);
+
+        assert_eq!(0.5, constrained_model.observed_probs[1]["VBZ"]["likes"]);
+        assert_eq!(0.2, constrained_model.observed_probs[1]["NNP"]["Ted"]);
+
+        assert_eq!(2.0/3.0, constrained_model.observed_probs[3]["NN"]["red"]);
+        assert_eq!(0.0, constrained_model.observed_probs[3]["NN"]["green"]);
+        assert_eq!(0.0, constrained_model.observed_probs[3]["RB"]["now"]);
+    }
+
+    #[test]
+    fn stats_chmm() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, Some(get_test_constraints()));
+        assert_eq!(ChmmStats::default(), constrained_model.stats());
+
+        constrained_model.duplicate_matrices();
+        let before = constrained_model.stats();
+        assert_eq!(4 * (model.hidden_probs.len() + model.observed_probs.len()), before.outer_keys);
+        assert_eq!(before.inner_entries, before.nonzero_entries);
+        assert!(before.estimated_bytes > 0);
+
+        constrained_model.remove_constrain_violating_states();
+        constrained_model.remove_dead_states();
+        let after = constrained_model.stats();
+        assert_eq!(before.inner_entries, after.inner_entries);
+        assert!(after.nonzero_entries < before.nonzero_entries);
+    }
+
+    #[test]
+    fn to_csv_chmm() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 4, None, Some(get_test_constraints()));
+        constrained_model.train();
+
+        let csv = constrained_model.to_csv();
+        let mut lines = csv.lines();
+        assert_eq!(Some("position,from_state,to_state,probability"), lines.next());
+        assert!(lines.clone().any(|line| line == format!("0,{},NNP,1", START_TOKEN)));
+        assert!(lines.all(|line| !line.ends_with(",0")));
+
+        let emissions = constrained_model.emissions_to_csv();
+        assert!(emissions.starts_with("position,from_state,to_state,probability\n"));
+        assert!(emissions.lines().any(|line| line == "3,NN,red,1"));
+        assert!(!emissions.lines().any(|line| line.starts_with("3,NN,green")));
+        assert_eq!("\"Red,\"", ConstrainedHiddenMarkov::csv_field("Red,"));
+    }
+
+    #[test]
+    fn remove_dead_nodes_from_hidden_constraints() {
+        let hidden_constraints: Vec<Box<dyn Constraint + Send>> = vec![
+            Box::new(EmptyConstraint::new()),
+            Box::new(EmptyConstraint::new()),
+            Box::new(EmptyConstraint::new()),
+            Box::new(MatchesConstraint::new(String::from("NNP"))),
+        ];
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, Some(hidden_constraints), None);
+        constrained_model.duplicate_matrices();
+        constrained_model.remove_constrain_violating_states();
+
+        constrained_model.remove_dead_states();
+
+        assert_eq!(0.25, constrained_model.hidden_probs[3]["VBZ"]["NNP"]);
+        assert_eq!(0.0, constrained_model.hidden_probs[3]["VBZ"]["NN"]);
+
+        assert_eq!(0.4, constrained_model.hidden_probs[2]["NNP"]["VBZ"]);
+        assert_eq!(1.0, constrained_model.hidden_probs[2]["RB"]["VBZ"]);
+        assert_eq!(0.0, constrained_model.hidden_probs[2]["NNP"]["RB"]);
+        assert_eq!(0.0, constrained_model.hidden_probs[2]["VBZ"]["NN"]);
+        assert_eq!(0.0, constrained_model.hidden_probs[2]["VBZ"]["NNP"]);
+
+        assert_eq!(0.6, constrained_model.hidden_probs[1]["NNP"]["RB"]);
+        assert_eq!(0.25, constrained_model.hidden_probs[1]["VBZ"]["NNP"]);
+        assert_eq!(0.0, constrained_model.hidden_probs[1]["RB"]["VBZ"]);
+
+        // Emissions of hidden states that can no longer be reached are zeroed
+        assert_eq!(0.6, constrained_model.observed_probs[3]["NNP"]["Mary"]);
+        assert_eq!(0.0, constrained_model.observed_probs[3]["VBZ"]["likes"]);
+        assert_eq!(0.0, constrained_model.observed_probs[3]["NN"]["red"]);
+        assert_eq!(0.0, constrained_model.observed_probs[0]["VBZ"]["likes"]);
+        assert_eq!(0.5, constrained_model.observed_probs[2]["VBZ"]["likes"]);
+    }
+
+    #[test]
+    fn remove_dead_nodes_from_observed_constraints() {
+        let observed_constraints: Vec<Box<dyn Constraint + Send>> = vec![
+            Box::new(StartsWithLetterConstraint::new('t')),
+            Box::new(EmptyConstraint::new()),
+            Box::new(EmptyConstraint::new()),
+            Box::new(MatchesConstraint::new(String::from("red"))),
+        ];
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, Some(observed_constraints));
+        constrained_model.duplicate_matrices();
+        constrained_model.remove_constrain_violating_states();
+
+        constrained_model.remove_dead_states();
+
+        assert_eq!(1.0, constrained_model.hidden_probs[0][START_TOKEN]["NNP"]);
+        assert_eq!(0.25, constrained_model.hidden_probs[0]["VBZ"]["NNP"]);
+        assert_eq!(0.0, constrained_model.hidden_probs[0]["NNP"]["RB"]);
+        assert_eq!(0.0, constrained_model.hidden_probs[0]["NNP"]["VBZ"]);
+        assert_eq!(0.0, constrained_model.hidden_probs[0]["RB"]["VBZ"]);
+        assert_eq!(0.0, constrained_model.hidden_probs[0]["VBZ"]["NN"]);
+
+        assert_eq!(0.4, constrained_model.hidden_probs[2]["NNP"]["VBZ"]);
+        assert_eq!(0.0, constrained_model.hidden_probs[2]["NNP"]["RB"]);
+
+        assert_eq!(0.75, constrained_model.hidden_probs[3]["VBZ"]["NN"]);
+        assert_eq!(0.0, constrained_model.hidden_probs[3]["NNP"]["RB"]);
+        assert_eq!(0.0, constrained_model.hidden_probs[3]["RB"]["VBZ"]);
+        assert_eq!(0.0, constrained_model.hidden_probs[3]["NNP"]["VBZ"]);
+        assert_eq!(0.0, constrained_model.hidden_probs[3]["VBZ"]["NNP"]);
+        assert_eq!(0.0, constrained_model.hidden_probs[3][START_TOKEN]["NNP"]);
+    }
+
+    #[test]
+    fn renormalize_chmm() {
+        let observed_constraints = get_test_constraints();
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, Some(observed_constraints));
+        constrained_model.duplicate_matrices();
+        constrained_model.remove_constrain_violating_states();
+        constrained_model.remove_dead_states();
+
+        constrained_model.renormalize();
+
+        assert_eq!(1.0, constrained_model.hidden_probs[0]["VBZ"]["NNP"]);
+        assert_eq!(1.0, constrained_model.hidden_probs[1]["NNP"]["RB"]);
+        assert_eq!(1.0, constrained_model.hidden_probs[1]["VBZ"]["NNP"]);
+        assert_eq!(0.0, constrained_model.hidden_probs[1]["VBZ"]["NN"]);
+        assert_eq!(1.0, constrained_model.hidden_probs[2]["NNP"]["VBZ"]);
+        assert_eq!(0.0, constrained_model.hidden_probs[2]["NNP"]["RB"]);
+        assert_eq!(1.0, constrained_model.hidden_probs[2]["RB"]["VBZ"]);
+        assert_eq!(1.0, constrained_model.hidden_probs[3]["VBZ"]["NN"]);
+        assert_eq!(0.0, constrained_model.hidden_probs[3]["VBZ"]["NNP"]);
+        assert_eq!(0.0, constrained_model.hidden_probs[3]["RB"]["VBZ"]);
+
+        assert_eq!(0.5, constrained_model.observed_probs[0]["NNP"]["Fred"]);
+        assert_eq!(0.5, constrained_model.observed_probs[0]["NNP"]["Ted"]);
+        assert_eq!(0.0, constrained_model.observed_probs[0]["NNP"]["Mary"]);
+        assert_eq!(0.0, constrained_model.observed_probs[0]["VBZ"]["likes"]);
+        assert_eq!(0.25, constrained_model.observed_probs[1]["VBZ"]["sees"]);
+        assert_eq!(0.25, constrained_model.observed_probs[1]["VBZ"]["loves"]);
+        assert_eq!(0.5, constrained_model.observed_probs[1]["VBZ"]["likes"]);
+        assert_eq!(1.0/3.0, constrained_model.observed_probs[1]["NN"]["green"]);
+        assert_eq!(2.0/3.0, constrained_model.observed_probs[1]["NN"]["red"]);
+        assert_eq!(0.2, constrained_model.observed_probs[1]["NNP"]["Fred"]);
+        assert_eq!(0.6, constrained_model.observed_probs[1]["NNP"]["Mary"]);
+        assert_eq!(0.2, constrained_model.observed_probs[1]["NNP"]["Ted"]);
+        assert_eq!(0.2, constrained_model.observed_probs[2]["NNP"]["Fred"]);
+        assert_eq!(0.6, constrained_model.observed_probs[2]["NNP"]["Mary"]);
+        assert_eq!(0.2, constrained_model.observed_probs[2]["NNP"]["Ted"]);
+        assert_eq!(1.0, constrained_model.observed_probs[3]["NN"]["red"]);
+        assert_eq!(0.0, constrained_model.observed_probs[3]["NN"]["green"]);
+        assert_eq!(0.0, constrained_model.observed_probs[3]["NNP"]["Fred"]);
+        assert_eq!(0.0, constrained_model.observed_probs[3]["NNP"]["Mary"]);
+        assert_eq!(0.0, constrained_model.observed_probs[3]["NNP"]["Ted"]);
+    }
+
+    #[test]
+    fn train_chmm() {
+        let observed_constraints = get_test_constraints();
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, Some(observed_constraints));
+        constrained_model.train();
+
+        assert_eq!(1.0, constrained_model.hidden_probs[0]["VBZ"]["NNP"]);
+        assert_eq!(1.0, constrained_model.hidden_probs[1]["NNP"]["RB"]);
+        assert_eq!(1.0, constrained_model.hidden_probs[1]["VBZ"]["NNP"]);
+        assert_eq!(0.0, constrained_model.hidden_probs[1]["VBZ"]["NN"]);
+        assert_eq!(1.0, constrained_model.hidden_probs[2]["NNP"]["VBZ"]);
+        assert_eq!(0.0, constrained_model.hidden_probs[2]["NNP"]["RB"]);
+        assert_eq!(1.0, constrained_model.hidden_probs[2]["RB"]["VBZ"]);
+        assert_eq!(1.0, constrained_model.hidden_probs[3]["VBZ"]["NN"]);
+        assert_eq!(0.0, constrained_model.hidden_probs[3]["VBZ"]["NNP"]);
+        assert_eq!(0.0, constrained_model.hidden_probs[3]["RB"]["VBZ"]);
+
+        assert_eq!(0.5, constrained_model.observed_probs[0]["NNP"]["Fred"]);
+        assert_eq!(0.5, constrained_model.observed_probs[0]["NNP"]["Ted"]);
+        assert_eq!(0.0, constrained_model.observed_probs[0]["NNP"]["Mary"]);
+        assert_eq!(0.0, constrained_model.observed_probs[0]["VBZ"]["likes"]);
+        assert_eq!(0.25, constrained_model.observed_probs[1]["VBZ"]["sees"]);
+        assert_eq!(0.25, constrained_model.observed_probs[1]["VBZ"]["loves"]);
+        assert_eq!(0.5, constrained_model.observed_probs[1]["VBZ"]["likes"]);
+        assert_eq!(1.0/3.0, constrained_model.observed_probs[1]["NN"]["green"]);
+        assert_eq!(2.0/3.0, constrained_model.observed_probs[1]["NN"]["red"]);
+        assert_eq!(0.2, constrained_model.observed_probs[1]["NNP"]["Fred"]);
+        assert_eq!(0.6, constrained_model.observed_probs[1]["NNP"]["Mary"]);
+        assert_eq!(0.2, constrained_model.observed_probs[1]["NNP"]["Ted"]);
+        assert_eq!(0.2, constrained_model.observed_probs[2]["NNP"]["Fred"]);
+        assert_eq!(0.6, constrained_model.observed_probs[2]["NNP"]["Mary"]);
+        assert_eq!(0.2, constrained_model.observed_probs[2]["NNP"]["Ted"]);
+        assert_eq!(1.0, constrained_model.observed_probs[3]["NN"]["red"]);
+        assert_eq!(0.0, constrained_model.observed_probs[3]["NN"]["green"]);
+        assert_eq!(0.0, constrained_model.observed_probs[3]["NNP"]["Fred"]);
+        assert_eq!(0.0, constrained_model.observed_probs[3]["NNP"]["Mary"]);
+        assert_eq!(0.0, constrained_model.observed_probs[3]["NNP"]["Ted"]);
+    }
+
+    #[test]
+    fn generate_sequence_chmm() {
+        let observed_constraints: Vec<Box<dyn Constraint + Send>> = vec![
+            Box::new(MultiConstraint::new(vec![
+                Box::new(StartsWithLetterConstraint::new('t')),
+                Box::new(StartsWithLetterConstraint::new('f')),
+            ], false)),
+            Box::new(EmptyConstraint::new()),
+            Box::new(EmptyConstraint::new()),
+            Box::new(MatchesConstraint::new(String::from("green"))),
+        ];
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nTed:NNP now:RB likes:VBZ green:NN"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, Some(observed_constraints));
+        constrained_model.train();
+        assert_eq!("Ted:NNP now:RB likes:VBZ green:NN", constrained_model.sample_sequence(true));
+    }
+
+    #[test]
+    fn generate_random_sequence_chmm() {
+        let observed_constraints = get_test_constraints();
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, Some(observed_constraints));
+        constrained_model.train();
+        assert_eq!(true, constrained_model.sample_sequence(true).ends_with("red:NN"));
+    }
+
+    #[test]
+    fn set_constraints_chmm() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 4, None, None);
+        constrained_model.train();
+        assert!(constrained_model.enumerate_sequences().len() > 12);
+
+        let empty: Vec<Box<dyn Constraint + Send>> = vec![Box::new(EmptyConstraint::new()); 4];
+        constrained_model.set_constraints(empty, get_test_constraints()).unwrap();
+        assert_eq!(12, constrained_model.enumerate_sequences().len());
+        assert_eq!(Some(1.0/6.0), constrained_model.get_sequence_probability("Ted:NNP now:RB likes:VBZ red:NN"));
+
+        let result = constrained_model.set_constraints(vec![Box::new(EmptyConstraint::new()); 3], get_test_constraints());
+        assert_eq!(Some(ChmmError::SequenceLengthMismatch { length: 4, constraints: 3 }), result.err());
+        assert_eq!(12, constrained_model.enumerate_sequences().len());
+    }
+
+    #[test]
+    fn single_token_chmm() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let observed_constraints: Vec<Box<dyn Constraint + Send>> = vec![Box::new(StartsWithLetterConstraint::new('f'))];
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 1, None, Some(observed_constraints));
+        constrained_model.train();
+
+        for _ in 0..10 {
+            assert_eq!("Fred:NNP", constrained_model.sample_sequence(true));
+        }
+        assert_eq!(Some(1.0), constrained_model.get_sequence_probability("Fred:NNP"));
+        assert_eq!(vec![String::from("Fred:NNP")], constrained_model.enumerate_sequences());
+    }
+
+    #[test]
+    fn sequence_probability_chmm() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, None);
+        constrained_model.train();
+        assert_eq!(Some(0.0007142857142857144), constrained_model.get_sequence_probability("Ted:NNP sometimes:RB loves:VBZ Fred:NNP"))
+    }
+
+    #[test]
+    fn sequence_probability_two_chmm() {
+        let observed_constraints = get_test_constraints();
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, Some(observed_constraints));
+        constrained_model.train();
+        assert_eq!(Some(1.0/6.0), constrained_model.get_sequence_probability("Ted:NNP now:RB likes:VBZ red:NN"));
+        assert_eq!(Some(1.0/12.0), constrained_model.get_sequence_probability("Ted:NNP now:RB loves:VBZ red:NN"));
+        assert_eq!(Some(1.0/12.0), constrained_model.get_sequence_probability("Ted:NNP now:RB sees:VBZ red:NN"));
+        assert_eq!(Some(1.0/12.0), constrained_model.get_sequence_probability("Ted:NNP sometimes:RB likes:VBZ red:NN"));
+        assert_eq!(Some(1.0/24.0), constrained_model.get_sequence_probability("Ted:NNP sometimes:RB loves:VBZ red:NN"));
+        assert_eq!(Some(1.0/24.0), constrained_model.get_sequence_probability("Ted:NNP sometimes:RB sees:VBZ red:NN"));
+        assert_eq!(Some(1.0/6.0), constrained_model.get_sequence_probability("Fred:NNP now:RB likes:VBZ red:NN"));
+        assert_eq!(Some(1.0/12.0), constrained_model.get_sequence_probability("Fred:NNP now:RB loves:VBZ red:NN"));
+        assert_eq!(Some(1.0/12.0), constrained_model.get_sequence_probability("Fred:NNP now:RB sees:VBZ red:NN"));
+        assert_eq!(Some(1.0/12.0), constrained_model.get_sequence_probability("Fred:NNP sometimes:RB likes:VBZ red:NN"));
+        assert_eq!(Some(1.0/24.0), constrained_model.get_sequence_probability("Fred:NNP sometimes:RB loves:VBZ red:NN"));
+        assert_eq!(Some(1.0/24.0), constrained_model.get_sequence_probability("Fred:NNP sometimes:RB sees:VBZ red:NN"));
+    }
+
+    #[test]
+    fn sequence_probability_wrong_length_chmm() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 4, None, None);
+        constrained_model.train();
+        assert_eq!(None, constrained_model.get_sequence_probability("Ted:NNP now:RB likes:VBZ green:NN Mary:NNP"));
+        assert_eq!(None, constrained_model.get_sequence_probability("Ted:NNP now:RB likes:VBZ"));
+    }
+
+    #[test]
+    fn sequence_probability_unseen_and_malformed_chmm() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 4, None, None);
+        constrained_model.train();
+        assert_eq!(Some(0.0), constrained_model.get_sequence_probability("Bob:NNP now:RB likes:VBZ red:NN"));
+        assert_eq!(Some(0.0), constrained_model.get_sequence_probability("Ted:NNP now:XX likes:VBZ red:NN"));
+        assert_eq!(None, constrained_model.get_sequence_probability("Ted:NNP now likes:VBZ red:NN"));
+    }
+
+    #[test]
+    fn higher_order_chmm() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(2, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, None);
+        constrained_model.train();
+
+        assert_eq!(0.5, constrained_model.hidden_probs[0][format!("{} {}", START_TOKEN, START_TOKEN).as_str()]["NNP VBZ"]);
+        assert_eq!(0.5, constrained_model.hidden_probs[0][format!("{} {}", START_TOKEN, START_TOKEN).as_str()]["NNP RB"]);
+        assert_eq!(0.0, constrained_model.hidden_probs[0]["NNP RB"]["VBZ NN"]);
+        assert_eq!(1.0, constrained_model.hidden_probs[1]["NNP RB"]["VBZ NN"]);
+        assert_eq!(1.0, constrained_model.hidden_probs[1]["NNP VBZ"]["NNP RB"]);
+        assert_eq!(0.5, constrained_model.observed_probs[1]["NNP VBZ"]["Fred sees"]);
+        assert_eq!(0.5, constrained_model.observed_probs[1]["NNP VBZ"]["Mary likes"]);
+
+        assert_ne!(0, constrained_model.sample_sequence(true).len());
+    }
+
+    #[test]
+    fn higher_order_sequence_probability_chmm() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(2, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 4, None, None);
+        constrained_model.train();
+
+        let start = format!("{} {}", START_TOKEN, START_TOKEN);
+        let expected = constrained_model.hidden_probs[0][start.as_str()]["NNP VBZ"]
+            * constrained_model.observed_probs[0]["NNP VBZ"]["Fred sees"]
+            * constrained_model.hidden_probs[1]["NNP VBZ"]["NNP RB"]
+            * constrained_model.observed_probs[1]["NNP RB"]["Mary sometimes"];
+        assert!(expected > 0.0);
+        assert_eq!(Some(expected), constrained_model.get_sequence_probability("Fred:NNP sees:VBZ Mary:NNP sometimes:RB"));
+        assert_eq!(Some(0.0), constrained_model.get_sequence_probability("Bob:NNP sees:VBZ Mary:NNP sometimes:RB"));
+        for _ in 0..20 {
+            let sequence = constrained_model.sample_sequence(true);
+            assert!(constrained_model.get_sequence_probability(&sequence).unwrap() > 0.0, "{}", sequence);
+        }
+    }
+
+    #[test]
+    fn higher_order_with_constraints_chmm() {
+        let hidden_constraints: Vec<Box<dyn Constraint + Send>> = vec![
+            Box::new(EmptyConstraint::new()),
+            Box::new(MatchesConstraint::new(String::from("VBZ"))),
+            Box::new(EmptyConstraint::new()),
+            Box::new(EmptyConstraint::new()),
+        ];
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(2, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, Some(hidden_constraints), None);
+        constrained_model.train();
+
+        assert_eq!(1.0, constrained_model.hidden_probs[0][format!("{} {}", START_TOKEN, START_TOKEN).as_str()]["NNP VBZ"]);
+        assert_eq!(0.0, constrained_model.hidden_probs[0][format!("{} {}", START_TOKEN, START_TOKEN).as_str()]["NNP RB"]);
+        assert_eq!(0.0, constrained_model.hidden_probs[0]["NNP RB"]["VBZ NN"]);
+        assert_eq!(0.0, constrained_model.hidden_probs[0]["NNP VBZ"]["NNP RB"]);
+        assert_eq!(1.0, constrained_model.hidden_probs[1]["NNP RB"]["VBZ NN"]);
+        assert_eq!(1.0, constrained_model.hidden_probs[1]["NNP VBZ"]["NNP RB"]);
+
+        assert_ne!(0, constrained_model.sample_sequence(true).len());
+    }
+
+    #[test]
+    fn sample_n_chmm() {
+        let observed_constraints = get_test_constraints();
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, Some(observed_constraints));
+        constrained_model.train();
+
+        let sequences = constrained_model.sample_n(10);
+        assert_eq!(10, sequences.len());
+        assert!(sequences.iter().all(|sequence| !sequence.is_empty()));
+    }
+
+    #[test]
+    fn sample_sequence_beam_greedy_chmm() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, None);
+        constrained_model.train();
+
+        for _ in 0..10 {
+            assert_eq!("Mary:NNP now:RB likes:VBZ red:NN", constrained_model.sample_sequence_beam(1));
+        }
+    }
+
+    #[test]
+    fn sample_sequence_beam_chmm() {
+        let observed_constraints = get_test_constraints();
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, Some(observed_constraints));
+        constrained_model.train();
+
+        let valid = constrained_model.enumerate_sequences();
+        for _ in 0..20 {
+            let sequence = constrained_model.sample_sequence_beam(5);
+            assert!(valid.contains(&sequence), "{} is not a valid sequence", sequence);
+            assert_eq!(4, sequence.split_whitespace().count());
+        }
+    }
+
+    #[test]
+    fn forward_backward_chmm() {
+        let observed_constraints = get_test_constraints();
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, Some(observed_constraints));
+        constrained_model.train();
+
+        let forward = constrained_model.forward();
+        let backward = constrained_model.backward();
+        assert_eq!(4, forward.len());
+        assert_eq!(4, backward.len());
+
+        let total: f64 = constrained_model.enumerate_paths(ENUMERATION_LIMIT).iter()
+            .map(|path| constrained_model.path_probability(path))
+            .sum();
+        assert!((total - 1.0).abs() < 1e-9);
+        for (alphas, betas) in forward.iter().zip(backward.iter()) {
+            let mass: f64 = alphas.iter().map(|(hidden, alpha)| alpha * betas.get(hidden).unwrap_or(&0.0)).sum();
+            assert!((mass - total).abs() < 1e-9, "{} != {}", mass, total);
+        }
+    }
+
+    #[test]
+    fn sample_n_with_progress_chmm() {
+        let observed_constraints = get_test_constraints();
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, Some(observed_constraints));
+        constrained_model.train();
+
+        let mut reported = vec![];
+        let sequences = constrained_model.sample_n_with_progress(10, 4, |count, _| reported.push(count));
+        assert_eq!(10, sequences.len());
+        assert_eq!(vec![4, 8], reported);
+
+        // Without progress reporting the same number of sequences is produced
+        assert_eq!(10, constrained_model.sample_n(10).len());
+        assert_eq!(10, constrained_model.sample_n_with_progress(10, 0, |_, _| panic!("no progress expected")).len());
+    }
+
+    #[test]
+    fn sample_n_with_rng_chmm() {
+        let observed_constraints = get_test_constraints();
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, Some(observed_constraints));
+        constrained_model.train();
+
+        let first = constrained_model.sample_n_with_rng(5, &mut StdRng::seed_from_u64(42));
+        let second = constrained_model.sample_n_with_rng(5, &mut StdRng::seed_from_u64(42));
+        assert_eq!(5, first.len());
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn par_sample_n_chmm() {
+        fn assert_sync<T: Sync>() {}
+        assert_sync::<ConstrainedHiddenMarkov>();
+
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nTed:NNP now:RB likes:VBZ green:NN"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, None);
+        constrained_model.train();
+
+        let parallel = constrained_model.par_sample_n(20);
+        assert_eq!(20, parallel.len());
+        assert_eq!(constrained_model.sample_n(20), parallel);
+    }
+
+    #[test]
+    fn expected_unique_deterministic_chmm() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nTed:NNP now:RB likes:VBZ green:NN"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, None);
+        constrained_model.train();
+
+        assert_eq!(1.0, constrained_model.expected_unique(1));
+        assert_eq!(1.0, constrained_model.expected_unique(10));
+        assert_eq!(1.0, constrained_model.expected_unique(1000));
+    }
+
+    #[test]
+    fn expected_unique_chmm() {
+        let observed_constraints = get_test_constraints();
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, Some(observed_constraints));
+        constrained_model.train();
+
+        assert!((constrained_model.expected_unique(1) - 1.0).abs() < 1e-9);
+        assert!(constrained_model.expected_unique(10) > 1.0);
+        assert!((constrained_model.expected_unique(100_000) - 12.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn distinct_top_sequences_chmm() {
+        let observed_constraints = get_test_constraints();
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, Some(observed_constraints));
+        constrained_model.train();
+
+        let top = constrained_model.distinct_top_sequences(3);
+        assert_eq!(3, top.len());
+        let best: HashSet<&str> = top[..2].iter().map(|(sequence, _)| sequence.as_str()).collect();
+        assert!(best.contains("Ted:NNP now:RB likes:VBZ red:NN"));
+        assert!(best.contains("Fred:NNP now:RB likes:VBZ red:NN"));
+        assert!((top[0].1 - 1.0 / 6.0).abs() < 1e-9);
+        assert!((top[1].1 - 1.0 / 6.0).abs() < 1e-9);
+        assert!((top[2].1 - 1.0 / 12.0).abs() < 1e-9);
+        assert_eq!(12, constrained_model.distinct_top_sequences(20).len());
+    }
+
+    #[test]
+    fn deterministic_positions_chmm() {
+        let observed_constraints: Vec<Box<dyn Constraint + Send>> = vec![
+            Box::new(MultiConstraint::new(vec![
+                Box::new(StartsWithLetterConstraint::new('t')),
+                Box::new(StartsWithLetterConstraint::new('f')),
+            ], false)),
+            Box::new(EmptyConstraint::new()),
+            Box::new(EmptyConstraint::new()),
+            Box::new(MatchesConstraint::new(String::from("green"))),
+        ];
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nTed:NNP now:RB likes:VBZ green:NN"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, Some(observed_constraints));
+        constrained_model.train();
+        assert_eq!(vec![true; 4], constrained_model.deterministic_positions());
+    }
+
+    #[test]
+    fn non_deterministic_positions_chmm() {
+        let observed_constraints = get_test_constraints();
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, Some(observed_constraints));
+        constrained_model.train();
+        assert_eq!(vec![false, false, false, true], constrained_model.deterministic_positions());
+    }
+
+    #[test]
+    fn segmented_sampling_chmm() {
+        let observed_constraints: Vec<Box<dyn Constraint + Send>> = vec![
+            Box::new(EmptyConstraint::new()),
+            Box::new(MatchesConstraint::new(String::from("likes"))),
+            Box::new(EmptyConstraint::new()),
+        ];
+        let data = String::from("Ted:NNP likes:VBZ red:NN\nMary:NNP likes:VBZ green:NN\nFred:NNP sees:VBZ Mary:NNP\nMary:NNP sees:VBZ red:NN");
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 3, None, Some(observed_constraints));
+        constrained_model.train();
+        assert_eq!(vec![1], constrained_model.cut_points());
+        assert_eq!(vec![0..2, 2..3], constrained_model.segments());
+
+        assert_eq!(vec![(1, String::from("VBZ"))], constrained_model.cut_states);
+
+        // Each segment's sampled distribution matches the whole model's marginal over it
+        let exact = constrained_model.distinct_top_sequences(100);
+        assert_eq!(constrained_model.enumerate_sequences().len(), exact.len());
+        let samples = 4000;
+        let mut rng = StdRng::seed_from_u64(7);
+        let sequences: Vec<String> = (0..samples).map(|_| constrained_model.sample_sequence_segmented_with_rng(true, &mut rng)).collect();
+        for segment in constrained_model.segments() {
+            let slice = |sequence: &str| sequence.split(' ').collect::<Vec<&str>>()[segment.clone()].join(" ");
+            let mut marginals: ProbMap<String, f64> = ProbMap::new();
+            for (sequence, prob) in &exact {
+                *marginals.entry(slice(sequence)).or_insert(0.0) += prob;
+            }
+            let mut frequencies: ProbMap<String, f64> = ProbMap::new();
+            for sequence in &sequences {
+                *frequencies.entry(slice(sequence)).or_insert(0.0) += 1.0 / samples as f64;
+            }
+            assert!(frequencies.keys().all(|tokens| marginals.contains_key(tokens)), "{:?}", frequencies);
+            for (tokens, prob) in &marginals {
+                let frequency = frequencies.get(tokens).copied().unwrap_or(0.0);
+                assert!((frequency - prob).abs() < 0.03, "{:?}: {} vs {}", segment, frequency, prob);
+            }
+        }
+    }
+
+    #[test]
+    fn dead_path_policy_chmm() {
+        let observed_constraints: Vec<Box<dyn Constraint + Send>> = vec![
+            Box::new(EmptyConstraint::new()),
+            Box::new(EmptyConstraint::new()),
+            Box::new(MatchesConstraint::new(String::from("red"))),
+        ];
+        let model = HiddenMarkov::new(1, String::from("Ted:NNP likes:VBZ red:NN\nMary:NNP sees:VBZ Ted:NNP"));
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 3, None, Some(observed_constraints));
+        // Pruning without removing dead states leaves paths through NNP to die at the last position
+        constrained_model.duplicate_matrices();
+        constrained_model.remove_constrain_violating_states();
+
+        let mut rng = StdRng::seed_from_u64(3);
+        let truncated: Vec<String> = (0..50)
+            .map(|_| constrained_model.sample_with_policy(true, DeadPathPolicy::Truncate, &mut rng).unwrap())
+            .collect();
+        assert!(truncated.iter().any(|sequence| sequence.split(' ').count() == 2));
+        assert!(truncated.iter().any(|sequence| sequence.ends_with("red:NN")));
 
-        let mut i: usize = 0;
-        for token in tokens {
-            let (token_observed, token_hidden) = HiddenMarkov::split_token(token);
-            product *= self.hidden_probs[i][&curr_hidden][&token_hidden];
-            product *= self.observed_probs[i][&token_hidden][&token_observed];
-            curr_hidden = token_hidden;
-            i += 1;
+        for _ in 0..50 {
+            let sequence = constrained_model.sample_with_policy(true, DeadPathPolicy::Retry(30), &mut rng).unwrap();
+            assert!(sequence.ends_with("red:NN"));
         }
-        return product;
+
+        let results: Vec<Result<String, ChmmError>> = (0..50)
+            .map(|_| constrained_model.sample_with_policy(true, DeadPathPolicy::Error, &mut rng))
+            .collect();
+        assert!(results.contains(&Err(ChmmError::DeadPath { position: 2, attempts: 1 })));
+        assert!(results.iter().flatten().all(|sequence| sequence.ends_with("red:NN")));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::constraints::empty_constraint::EmptyConstraint;
-    use crate::constraints::starts_with_letter_constraint::StartsWithLetterConstraint;
-    use crate::constraints::matches_constraint::MatchesConstraint;
-    use crate::utils::{get_test_constraints, START_TOKEN};
-    use crate::constraints::multi_constraint::MultiConstraint;
+    #[test]
+    fn observed_sequences_chmm() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 4, None, Some(get_test_constraints()));
+        constrained_model.train();
+        let sequences: Vec<String> = constrained_model.observed_sequences().take(3).collect();
+        assert_eq!(3, sequences.len());
+        for sequence in sequences {
+            assert!(!sequence.is_empty());
+            assert!(!sequence.contains(':'));
+        }
+        assert!(constrained_model.observed_sequences().filter(|sequence| sequence.starts_with("Ted")).take(2).all(|sequence| sequence.ends_with("red")));
+    }
 
     #[test]
-    fn create_constrained_hidden_markov() {
-        let model = ConstrainedHiddenMarkov {
-            hidden_markov_model: HiddenMarkov::new(1, Default::default()),
-            sequence_length: 4,
-            hidden_probs: Default::default(),
-            observed_probs: Default::default(),
-            hidden_constraints: Default::default(),
-            observed_constraints: Default::default()
-        };
-        assert_eq!(1, model.hidden_markov_model.markov_order);
-        assert_eq!(4, model.sequence_length);
+    fn sample_sequence_starting_at_chmm() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 4, None, Some(get_test_constraints()));
+        constrained_model.train();
+        for _ in 0..10 {
+            let sequence = constrained_model.sample_sequence_starting_at(1, "NNP").unwrap();
+            let tokens: Vec<&str> = sequence.split(' ').collect();
+            assert_eq!(3, tokens.len());
+            assert!(tokens[0] == "now:RB" || tokens[0] == "sometimes:RB" || tokens[0].ends_with(":VBZ"));
+            assert_eq!("red:NN", tokens[2]);
+        }
+        assert_eq!(None, constrained_model.sample_sequence_starting_at(1, "XYZ"));
+        assert_eq!(None, constrained_model.sample_sequence_starting_at(4, "NNP"));
+        // NN is never followed by anything in the training data
+        assert_eq!(None, constrained_model.sample_sequence_starting_at(1, "NN"));
     }
 
     #[test]
-    fn clear_probabilities_chmm() {
+    fn explain_zero_probability_chmm() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 4, None, Some(get_test_constraints()));
+        constrained_model.train();
+
+        assert!(constrained_model.explain_zero_probability("Ted:NNP now:RB likes:VBZ red:NN").is_empty());
+        assert_eq!(vec![String::from("position 3: observed 'green' not allowed by constraint")],
+                   constrained_model.explain_zero_probability("Ted:NNP now:RB likes:VBZ green:NN"));
+        assert_eq!(vec![String::from("position 1: transition NNP->NNP never seen in training")],
+                   constrained_model.explain_zero_probability("Ted:NNP Mary:NNP likes:VBZ red:NN"));
+        assert_eq!(vec![String::from("position 1: transition NNP->VBZ has probability 0 (pruned by arc-consistency)"),
+                        String::from("position 2: transition VBZ->NN has probability 0 (pruned by arc-consistency)"),
+                        String::from("position 3: transition NN->NN never seen in training")],
+                   constrained_model.explain_zero_probability("Ted:NNP likes:VBZ red:NN red:NN"));
+        assert_eq!(vec![String::from("position 0: observed 'Bob' never emitted by NNP in training")],
+                   constrained_model.explain_zero_probability("Bob:NNP now:RB likes:VBZ red:NN"));
+        assert_eq!(vec![String::from("sequence has 2 tokens but the model generates 4")],
+                   constrained_model.explain_zero_probability("Ted:NNP now:RB"));
+        assert_eq!(vec![String::from("token 'Ted' has no hidden state")],
+                   constrained_model.explain_zero_probability("Ted now likes red"));
+    }
+
+    #[test]
+    fn explain_zero_probability_higher_order_positions() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(2, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 4, None, Some(get_test_constraints()));
+        constrained_model.train();
+
+        assert!(constrained_model.explain_zero_probability("Ted:NNP now:RB loves:VBZ red:NN").is_empty());
+        assert_eq!(vec![String::from("position 3: observed 'green' not allowed by constraint")],
+                   constrained_model.explain_zero_probability("Ted:NNP now:RB likes:VBZ green:NN"));
+        assert_eq!(vec![String::from("positions 2-3: transition NNP RB->NN VBZ never seen in training"),
+                        String::from("positions 2-3: observed 'red likes' never emitted by NN VBZ in training")],
+                   constrained_model.explain_zero_probability("Ted:NNP now:RB red:NN likes:VBZ"));
+    }
+
+    #[test]
+    fn mixed_order_chmm() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(2, data);
+        let constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, Some(get_test_constraints()));
+        assert!(constrained_model.sample_sequence_mixed_order(true, DeadPathPolicy::Error).is_err());
+        let constrained_model = constrained_model.with_position_orders(vec![1, 2, 2, 2]).unwrap();
+        let mixed_order = constrained_model.mixed_order.as_ref().unwrap();
+        assert_eq!(START_TOKEN, mixed_order.context_key(0, &[]));
+        assert_eq!(format!("{} NNP", START_TOKEN), mixed_order.context_key(1, &[String::from("NNP")]));
+        assert_eq!("RB VBZ", mixed_order.context_key(3, &[String::from("NNP"), String::from("RB"), String::from("VBZ")]));
+
+        for _ in 0..20 {
+            let sequence = constrained_model.sample_sequence_mixed_order(true, DeadPathPolicy::Retry(100)).unwrap();
+            let tokens: Vec<&str> = sequence.split(' ').collect();
+            assert_eq!(4, tokens.len(), "{}", sequence);
+            assert!(tokens[0].starts_with('T') || tokens[0].starts_with('F'));
+            assert_eq!("red:NN", tokens[3]);
+            assert!(constrained_model.get_mixed_order_sequence_probability(&sequence).unwrap() > 0.0);
+        }
+        assert!(constrained_model.get_mixed_order_sequence_probability("Ted:NNP now:RB likes:VBZ red:NN").unwrap() > 0.0);
+        assert_eq!(Some(0.0), constrained_model.get_mixed_order_sequence_probability("Ted:NNP likes:VBZ now:RB red:NN"));
+        assert_eq!(None, constrained_model.get_mixed_order_sequence_probability("Ted now:RB likes:VBZ red:NN"));
+        // Without lookahead about half of the paths die before the end
+        let results: Vec<Result<String, ChmmError>> = (0..100)
+            .map(|_| constrained_model.sample_sequence_mixed_order(true, DeadPathPolicy::Error))
+            .collect();
+        assert!(results.iter().any(|result| matches!(result, Err(ChmmError::DeadPath { attempts: 1, .. }))));
+        assert!(results.iter().flatten().all(|sequence| sequence.split(' ').count() == 4));
+
+        let constrained_model = ConstrainedHiddenMarkov::new(model, 4, None, None);
+        assert!(constrained_model.with_position_orders(vec![1, 2]).is_err());
+    }
+
+    #[test]
+    fn sample_sequence_min_probability_chmm() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 4, None, Some(get_test_constraints()));
+        constrained_model.train();
+
+        let sequence = constrained_model.sample_sequence_min_probability(0.01, 1000).unwrap();
+        assert!(constrained_model.get_sequence_probability(&sequence).unwrap() >= 0.01);
+        let probability = constrained_model.distinct_top_sequences(1)[0].1;
+        let sequence = constrained_model.sample_sequence_min_probability(probability, 1000).unwrap();
+        assert!((probability - constrained_model.get_sequence_probability(&sequence).unwrap()).abs() < 1e-12);
+        assert_eq!(None, constrained_model.sample_sequence_min_probability(probability + 0.01, 50));
+        assert_eq!(None, constrained_model.sample_sequence_min_probability(0.0, 0));
+    }
+
+    #[test]
+    fn averaged_distribution_chmm() {
         let data = String::from(
             "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
         );
         let model = HiddenMarkov::new(1, data);
         let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, None);
+        constrained_model.train();
+        let (transitions, emissions) = constrained_model.averaged_distribution();
+        assert_eq!(model.observed_probs.len(), emissions.len());
+        for (hidden, row) in emissions {
+            for (observed, prob) in row {
+                assert!((model.observed_probs[&hidden][&observed] - prob).abs() < 1e-12);
+            }
+        }
+        assert_eq!(1.0, transitions[START_TOKEN]["NNP"]);
+        for row in transitions.values() {
+            assert!((row.values().sum::<f64>() - 1.0).abs() < 1e-12);
+        }
 
-        constrained_model.hidden_probs.push(model.hidden_probs.clone());
-        constrained_model.hidden_probs.push(model.hidden_probs.clone());
-        constrained_model.hidden_probs.push(model.hidden_probs.clone());
-        constrained_model.observed_probs.push(model.observed_probs.clone());
-        constrained_model.observed_probs.push(model.observed_probs.clone());
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 4, None, Some(get_test_constraints()));
+        constrained_model.train();
+        let (_, emissions) = constrained_model.averaged_distribution();
+        assert_eq!(None, emissions["NN"].get("green").filter(|prob| **prob > 0.0));
+    }
 
-        assert_eq!(3, constrained_model.hidden_probs.len());
-        assert_eq!(2, constrained_model.observed_probs.len());
+    #[test]
+    fn distinct_word_budget_chmm() {
+        let data = String::from("a:X b:X c:X a:X b:X c:X\nc:X b:X a:X c:X a:X b:X\nb:X c:X a:X b:X c:X a:X");
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 6, None, None)
+            .with_sequence_constraints(vec![Box::new(DistinctWordBudgetConstraint::new(2))]);
+        constrained_model.train();
+        for _ in 0..20 {
+            let sequence = constrained_model.sample_sequence_with_policy(false, DeadPathPolicy::Error).unwrap();
+            let words: Vec<&str> = sequence.split(' ').collect();
+            assert_eq!(6, words.len());
+            assert!(words.iter().collect::<HashSet<_>>().len() <= 2);
+        }
 
-        constrained_model.clear_probs();
-        assert_eq!(0, constrained_model.hidden_probs.len());
-        assert_eq!(0, constrained_model.observed_probs.len());
+        // Draw based and mid-sequence samplers share the same restriction
+        for seed in 0..20 {
+            let sequence = constrained_model.sample_sequence_portable(seed, false);
+            assert!(sequence.split(' ').collect::<HashSet<_>>().len() <= 2, "{}", sequence);
+        }
+        // Without the budget these draws would pick a, b and c
+        let sequence = constrained_model.sample_sequence_from_draws(&[0.1, 0.1, 0.5, 0.5, 0.9, 0.9]);
+        assert_eq!(3, sequence.split(' ').count());
+        assert!(sequence.split(' ').collect::<HashSet<_>>().len() <= 2, "{}", sequence);
+        let sequence = constrained_model.sample_sequence_starting_at(2, "X").unwrap();
+        assert!(sequence.split(' ').collect::<HashSet<_>>().len() <= 2, "{}", sequence);
+
+        // No word fits a budget of 0, so every path dies at the first position
+        let constrained_model = constrained_model.with_sequence_constraints(vec![Box::new(DistinctWordBudgetConstraint::new(0))]);
+        assert_eq!(Err(ChmmError::DeadPath { position: 0, attempts: 3 }),
+                   constrained_model.sample_sequence_with_policy(true, DeadPathPolicy::Retry(2)));
     }
-    
+
     #[test]
-    fn constraints_chmm() {
-        let constraints: Vec<Box<dyn Constraint + Send>> = vec![
-            Box::new(StartsWithLetterConstraint::new('f')),
+    fn constraint_summary_chmm() {
+        let model = HiddenMarkov::new(1, String::from("Ted:NNP now:RB likes:VBZ red:NN"));
+        let hidden_constraints: Vec<Box<dyn Constraint + Send>> = vec![
             Box::new(EmptyConstraint::new()),
             Box::new(EmptyConstraint::new()),
-            Box::new(MatchesConstraint::new(String::from("red"))),
+            Box::new(MatchesConstraint::new(String::from("VBZ"))),
+            Box::new(EmptyConstraint::new()),
         ];
-        let model = ConstrainedHiddenMarkov {
-            hidden_markov_model: HiddenMarkov::new(1, Default::default()),
-            sequence_length: 4,
-            hidden_probs: Default::default(),
-            observed_probs: Default::default(),
-            hidden_constraints: Default::default(),
-            observed_constraints: constraints
-        };
-        assert_eq!(4, model.observed_constraints.len());
-        assert_eq!(true, model.observed_constraints[0].is_satisfied_by_state(String::from("Fred")));
-        assert_eq!(true, model.observed_constraints[1].is_satisfied_by_state(String::from("anything")));
-        assert_eq!(true, model.observed_constraints[2].is_satisfied_by_state(String::from("")));
-        assert_eq!(false, model.observed_constraints[3].is_satisfied_by_state(String::from("reds")));
+        let constrained_model = ConstrainedHiddenMarkov::new(model, 4, Some(hidden_constraints), Some(get_test_constraints()));
+        let summary = constrained_model.constraint_summary();
+        assert_eq!(4, summary.len());
+        assert_eq!((0, String::from("starts with 't' or starts with 'f'"), String::from("any")), summary[0]);
+        assert_eq!((1, String::from("any"), String::from("any")), summary[1]);
+        assert_eq!((2, String::from("any"), String::from("matches \"vbz\"")), summary[2]);
+        assert_eq!((3, String::from("matches \"red\""), String::from("any")), summary[3]);
     }
 
     #[test]
-    fn remove_constraint_violating_states_chmm() {
-        let observed_constraints: Vec<Box<dyn Constraint + Send>> = vec![
-            Box::new(StartsWithLetterConstraint::new('t')),
+    fn parallel_constraints_chmm() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let hidden_constraints: Vec<Box<dyn Constraint + Send>> = vec![
             Box::new(EmptyConstraint::new()),
             Box::new(EmptyConstraint::new()),
-            Box::new(MatchesConstraint::new(String::from("red"))),
+            Box::new(MatchesConstraint::new(String::from("VBZ"))),
+            Box::new(EmptyConstraint::new()),
+        ];
+        for order in [1, 2] {
+            let model = HiddenMarkov::new(order, data.clone());
+            let mut serial_model = ConstrainedHiddenMarkov::new(model.clone(), 4, Some(hidden_constraints.clone()), Some(get_test_constraints()));
+            serial_model.train();
+            let mut parallel_model = ConstrainedHiddenMarkov::new(model, 4, Some(hidden_constraints.clone()), Some(get_test_constraints()))
+                .with_parallel_constraints(true);
+            parallel_model.train();
+
+            assert_eq!(serial_model.hidden_probs, parallel_model.hidden_probs);
+            assert_eq!(serial_model.observed_probs, parallel_model.observed_probs);
+        }
+    }
+
+    #[test]
+    fn selective_pruning_chmm() {
+        let ted_mary = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let small_test = crate::utils::get_data("data/small_test.txt").unwrap();
+        let hidden_constraints: Vec<Box<dyn Constraint + Send>> = vec![
+            Box::new(EmptyConstraint::new()),
+            Box::new(EmptyConstraint::new()),
+            Box::new(MatchesConstraint::new(String::from("VBZ"))),
+            Box::new(EmptyConstraint::new()),
         ];
+        for data in [ted_mary, small_test] {
+            for order in [1, 2] {
+                for hidden in [None, Some(hidden_constraints.clone())] {
+                    let model = HiddenMarkov::new(order, data.clone());
+                    let mut standard_model = ConstrainedHiddenMarkov::new(model.clone(), 4, hidden.clone(), Some(get_test_constraints()));
+                    standard_model.train();
+                    let mut selective_model = ConstrainedHiddenMarkov::new(model, 4, hidden, Some(get_test_constraints()))
+                        .with_selective_pruning(true);
+                    selective_model.train();
+
+                    assert_eq!(standard_model.hidden_probs, selective_model.hidden_probs);
+                    assert_eq!(standard_model.observed_probs, selective_model.observed_probs);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn position_accessors_chmm() {
         let data = String::from(
             "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
         );
         let model = HiddenMarkov::new(1, data);
-        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, Some(observed_constraints));
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 4, None, Some(get_test_constraints()));
+        assert!(constrained_model.hidden_at(0).is_none());
+        constrained_model.train();
 
-        constrained_model.duplicate_matrices();
-        constrained_model.remove_constrain_violating_states();
-        assert_eq!(0.0, constrained_model.observed_probs[0]["VBZ"]["likes"]);
-        assert_eq!(0.2, constrained_model.observed_probs[0]["NNP"]["Ted"]);
+        assert_eq!(Some(&constrained_model.hidden_probs[3]), constrained_model.hidden_at(3));
+        assert_eq!(Some(&1.0), constrained_model.observed_at(3).unwrap().get("NN").unwrap().get("red"));
+        assert!(constrained_model.hidden_at(4).is_none());
+        assert!(constrained_model.observed_at(4).is_none());
+    }
 
-        assert_eq!(0.5, constrained_model.observed_probs[1]["VBZ"]["likes"]);
-        assert_eq!(0.2, constrained_model.observed_probs[1]["NNP"]["Ted"]);
+    #[test]
+    fn sample_sequence_from_draws_chmm() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 2, None, None);
+        constrained_model.train();
 
-        assert_eq!(2.0/3.0, constrained_model.observed_probs[3]["NN"]["red"]);
-        assert_eq!(0.0, constrained_model.observed_probs[3]["NN"]["green"]);
-        assert_eq!(0.0, constrained_model.observed_probs[3]["RB"]["now"]);
+        // NNP emits Fred 0.2, Mary 0.6, Ted 0.2 and moves to RB 0.6, VBZ 0.4;
+        // VBZ emits likes 0.5, loves 0.25, sees 0.25
+        assert_eq!("Mary:NNP sees:VBZ", constrained_model.sample_sequence_from_draws(&[0.0, 0.5, 0.7, 0.9]));
+        assert_eq!("Fred:NNP loves:VBZ", constrained_model.sample_sequence_from_draws(&[0.0, 0.1, 0.7, 0.6]));
+        assert_eq!("Ted:NNP", constrained_model.sample_sequence_from_draws(&[0.0, 0.8]));
+    }
+
+    #[test]
+    fn effective_vocabulary_chmm() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, Some(get_test_constraints()));
+        constrained_model.train();
+        assert_eq!(vec![2, 2, 3, 1], constrained_model.effective_vocabulary());
+
+        let mut unconstrained_model = ConstrainedHiddenMarkov::new(model, 4, None, None);
+        unconstrained_model.train();
+        assert_eq!(vec![3, 5, 6, 10], unconstrained_model.effective_vocabulary());
+    }
+
+    #[test]
+    fn positions_equal_unconstrained_chmm() {
+        // Every state has a successor, so nothing is pruned without constraints
+        let data = String::from("a:X b:Y a:X b:Y\nb:Y c:X a:X b:Y\nc:X b:Y");
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 4, None, None);
+        constrained_model.train();
+        assert_eq!(vec![true; 4], constrained_model.positions_equal_unconstrained());
+
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 4, None, Some(get_test_constraints()));
+        constrained_model.train();
+        assert_eq!(vec![false; 4], constrained_model.positions_equal_unconstrained());
+    }
+
+    #[test]
+    fn add_constraint_at_chmm() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut incremental_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, Some(get_test_constraints()));
+        incremental_model.train();
+        incremental_model.add_constraint_at(2, Box::new(StartsWithLetterConstraint::new('l'))).unwrap();
+
+        let observed_constraints = incremental_model.observed_constraints.clone();
+        let mut retrained_model = ConstrainedHiddenMarkov::new(model, 4, None, Some(observed_constraints));
+        retrained_model.train();
+        for i in 0..4 {
+            assert!(ConstrainedHiddenMarkov::matrices_close(&incremental_model.hidden_probs[i], &retrained_model.hidden_probs[i]));
+            assert!(ConstrainedHiddenMarkov::matrices_close(&incremental_model.observed_probs[i], &retrained_model.observed_probs[i]));
+        }
+        assert!(incremental_model.enumerate_sequences().iter().all(|sequence| !sequence.contains("sees")));
+        assert!(incremental_model.add_constraint_at(4, Box::new(EmptyConstraint::new())).is_err());
+    }
+
+    #[test]
+    fn sample_sequence_portable_chmm() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 4, None, Some(get_test_constraints()));
+        constrained_model.train();
+
+        let sequences: Vec<String> = (0..3).map(|seed| constrained_model.sample_sequence_portable(seed, true)).collect();
+        assert_eq!(vec![
+            String::from("Ted:NNP now:RB likes:VBZ red:NN"),
+            String::from("Fred:NNP now:RB likes:VBZ red:NN"),
+            String::from("Fred:NNP now:RB sees:VBZ red:NN"),
+        ], sequences);
+    }
+
+    #[test]
+    fn multi_word_observed_chmm() {
+        let data = String::from("\"New York\":NNP is:VBZ big:JJ\nBoston:NNP is:VBZ old:JJ");
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 3, None, None);
+        constrained_model.train();
+
+        let sequences = constrained_model.enumerate_sequences();
+        assert!(sequences.contains(&String::from("\"New York\":NNP is:VBZ big:JJ")), "{:?}", sequences);
+        assert_eq!(4, sequences.len());
+        let observed = constrained_model.sample_observed_only();
+        assert!(observed.starts_with("New York is ") || observed.starts_with("Boston is "), "{}", observed);
+    }
+
+    #[test]
+    fn multi_word_observed_round_trip_chmm() {
+        let model = HiddenMarkov::new(1, String::from("\"New York\":NNP is:VBZ big:JJ\nBoston:NNP is:VBZ old:JJ"));
+        let observed_constraints: Vec<Box<dyn Constraint + Send>> = vec![
+            Box::new(MatchesConstraint::new(String::from("New York"))),
+            Box::new(EmptyConstraint::new()),
+            Box::new(EmptyConstraint::new()),
+        ];
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 3, None, Some(observed_constraints));
+        constrained_model.train();
+
+        for _ in 0..20 {
+            let sequence = constrained_model.sample_sequence(true);
+            assert!(sequence.starts_with("\"New York\":NNP is:VBZ "), "{}", sequence);
+            assert!(constrained_model.get_sequence_probability(&sequence).unwrap() > 0.0, "{}", sequence);
+            assert!(model.get_sequence_probability(&sequence).unwrap() > 0.0, "{}", sequence);
+            assert!(constrained_model.explain_zero_probability(&sequence).is_empty());
+        }
+        assert!(constrained_model.reachable_with(0, "New York"));
+        assert_eq!(None, constrained_model.get_sequence_probability("\"New York:NNP is:VBZ big:JJ"));
+        assert_eq!(vec![String::from("unclosed quote at token 1 of '\"New York:NNP is:VBZ big:JJ'")],
+                   constrained_model.explain_zero_probability("\"New York:NNP is:VBZ big:JJ"));
+    }
+
+    #[test]
+    fn with_position_constraints_chmm() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB\nTed:NNP likes:VBZ Ted:NNP"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 3, None, None)
+            .with_position_constraints(|i| {
+                let observed: Box<dyn Constraint + Send> = if i % 2 == 0 {
+                    Box::new(StartsWithLetterConstraint::new('t'))
+                } else {
+                    Box::new(EmptyConstraint::new())
+                };
+                (observed, Box::new(EmptyConstraint::new()))
+            });
+        constrained_model.train();
+
+        assert!(constrained_model.observed_constraints[2].box_eq(&StartsWithLetterConstraint::new('t')));
+        assert_eq!(vec![
+            String::from("Ted:NNP likes:VBZ Ted:NNP"),
+            String::from("Ted:NNP loves:VBZ Ted:NNP"),
+            String::from("Ted:NNP sees:VBZ Ted:NNP"),
+        ], constrained_model.enumerate_sequences());
     }
 
     #[test]
-    fn remove_dead_nodes_from_hidden_constraints() {
-        let hidden_constraints: Vec<Box<dyn Constraint + Send>> = vec![
-            Box::new(EmptyConstraint::new()),
-            Box::new(EmptyConstraint::new()),
-            Box::new(EmptyConstraint::new()),
-            Box::new(MatchesConstraint::new(String::from("NNP"))),
-        ];
+    fn nbest_observed_chmm() {
         let data = String::from(
             "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
         );
         let model = HiddenMarkov::new(1, data);
-        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, Some(hidden_constraints), None);
-        constrained_model.duplicate_matrices();
-        constrained_model.remove_constrain_violating_states();
-
-        constrained_model.remove_dead_states();
-
-        assert_eq!(0.25, constrained_model.hidden_probs[3]["VBZ"]["NNP"]);
-        assert_eq!(0.0, constrained_model.hidden_probs[3]["VBZ"]["NN"]);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 4, None, None);
+        constrained_model.train();
 
-        assert_eq!(0.4, constrained_model.hidden_probs[2]["NNP"]["VBZ"]);
-        assert_eq!(1.0, constrained_model.hidden_probs[2]["RB"]["VBZ"]);
-        assert_eq!(0.0, constrained_model.hidden_probs[2]["NNP"]["RB"]);
-        assert_eq!(0.0, constrained_model.hidden_probs[2]["VBZ"]["NN"]);
-        assert_eq!(0.0, constrained_model.hidden_probs[2]["VBZ"]["NNP"]);
+        let nbest = constrained_model.nbest_observed(&["NNP", "RB", "VBZ", "NN"], 2).unwrap();
+        let words: Vec<Vec<&str>> = nbest.iter().map(|choices| choices.iter().map(|(word, _)| word.as_str()).collect()).collect();
+        assert_eq!(vec![vec!["Mary", "Fred"], vec!["now", "sometimes"], vec!["likes", "loves"], vec!["red", "green"]], words);
+        assert!((nbest[0][0].1 - 0.6).abs() < 1e-9);
+        assert!((nbest[3][0].1 - 2.0 / 3.0).abs() < 1e-9);
 
-        assert_eq!(0.6, constrained_model.hidden_probs[1]["NNP"]["RB"]);
-        assert_eq!(0.25, constrained_model.hidden_probs[1]["VBZ"]["NNP"]);
-        assert_eq!(0.0, constrained_model.hidden_probs[1]["RB"]["VBZ"]);
+        assert!(constrained_model.nbest_observed(&["NNP", "NN", "VBZ", "NN"], 2).is_err());
+        assert!(constrained_model.nbest_observed(&["NNP", "RB"], 2).is_err());
     }
 
     #[test]
-    fn remove_dead_nodes_from_observed_constraints() {
-        let observed_constraints: Vec<Box<dyn Constraint + Send>> = vec![
-            Box::new(StartsWithLetterConstraint::new('t')),
+    fn surviving_probability_mass_chmm() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut lightly_constrained = ConstrainedHiddenMarkov::new(model.clone(), 4, None, Some(vec![
+            Box::new(EmptyConstraint::new()),
             Box::new(EmptyConstraint::new()),
             Box::new(EmptyConstraint::new()),
             Box::new(MatchesConstraint::new(String::from("red"))),
-        ];
+        ]));
+        lightly_constrained.train();
+        let mut heavily_constrained = ConstrainedHiddenMarkov::new(model, 4, None, Some(get_test_constraints()));
+        heavily_constrained.train();
+
+        let light = lightly_constrained.surviving_probability_mass();
+        let heavy = heavily_constrained.surviving_probability_mass();
+        assert!(0.0 < heavy && heavy < light && light < 1.0, "{} {}", heavy, light);
+
+        let enumerated: f64 = heavily_constrained.enumerate_paths(ENUMERATION_LIMIT).iter()
+            .map(|path| heavily_constrained.unconstrained_path_probability(path))
+            .sum();
+        assert!((heavy - enumerated).abs() < 1e-12);
+    }
+
+    #[test]
+    fn suggest_chmm() {
         let data = String::from(
             "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
         );
         let model = HiddenMarkov::new(1, data);
-        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, Some(observed_constraints));
-        constrained_model.duplicate_matrices();
-        constrained_model.remove_constrain_violating_states();
-
-        constrained_model.remove_dead_states();
-
-        assert_eq!(1.0, constrained_model.hidden_probs[0][START_TOKEN]["NNP"]);
-        assert_eq!(0.25, constrained_model.hidden_probs[0]["VBZ"]["NNP"]);
-        assert_eq!(0.0, constrained_model.hidden_probs[0]["NNP"]["RB"]);
-        assert_eq!(0.0, constrained_model.hidden_probs[0]["NNP"]["VBZ"]);
-        assert_eq!(0.0, constrained_model.hidden_probs[0]["RB"]["VBZ"]);
-        assert_eq!(0.0, constrained_model.hidden_probs[0]["VBZ"]["NN"]);
-
-        assert_eq!(0.4, constrained_model.hidden_probs[2]["NNP"]["VBZ"]);
-        assert_eq!(0.0, constrained_model.hidden_probs[2]["NNP"]["RB"]);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 4, None, Some(get_test_constraints()));
+        constrained_model.train();
 
-        assert_eq!(0.75, constrained_model.hidden_probs[3]["VBZ"]["NN"]);
-        assert_eq!(0.0, constrained_model.hidden_probs[3]["NNP"]["RB"]);
-        assert_eq!(0.0, constrained_model.hidden_probs[3]["RB"]["VBZ"]);
-        assert_eq!(0.0, constrained_model.hidden_probs[3]["NNP"]["VBZ"]);
-        assert_eq!(0.0, constrained_model.hidden_probs[3]["VBZ"]["NNP"]);
-        assert_eq!(0.0, constrained_model.hidden_probs[3][START_TOKEN]["NNP"]);
+        assert_eq!(vec![String::from("red")], constrained_model.suggest(3, "NN", "r", 5));
+        assert!(constrained_model.suggest(3, "NN", "g", 5).is_empty());
+        assert_eq!(vec![String::from("likes"), String::from("loves")], constrained_model.suggest(2, "VBZ", "L", 5));
+        assert_eq!(1, constrained_model.suggest(2, "VBZ", "", 1).len());
+        assert!(constrained_model.suggest(4, "NN", "r", 5).is_empty());
     }
 
     #[test]
-    fn renormalize_chmm() {
+    fn reachable_with_chmm() {
         let observed_constraints = get_test_constraints();
         let data = String::from(
             "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
         );
         let model = HiddenMarkov::new(1, data);
         let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, Some(observed_constraints));
-        constrained_model.duplicate_matrices();
-        constrained_model.remove_constrain_violating_states();
-        constrained_model.remove_dead_states();
-
-        constrained_model.renormalize();
-
-        assert_eq!(1.0, constrained_model.hidden_probs[0]["VBZ"]["NNP"]);
-        assert_eq!(1.0, constrained_model.hidden_probs[1]["NNP"]["RB"]);
-        assert_eq!(1.0, constrained_model.hidden_probs[1]["VBZ"]["NNP"]);
-        assert_eq!(0.0, constrained_model.hidden_probs[1]["VBZ"]["NN"]);
-        assert_eq!(1.0, constrained_model.hidden_probs[2]["NNP"]["VBZ"]);
-        assert_eq!(0.0, constrained_model.hidden_probs[2]["NNP"]["RB"]);
-        assert_eq!(1.0, constrained_model.hidden_probs[2]["RB"]["VBZ"]);
-        assert_eq!(1.0, constrained_model.hidden_probs[3]["VBZ"]["NN"]);
-        assert_eq!(0.0, constrained_model.hidden_probs[3]["VBZ"]["NNP"]);
-        assert_eq!(0.0, constrained_model.hidden_probs[3]["RB"]["VBZ"]);
+        constrained_model.train();
 
-        assert_eq!(0.5, constrained_model.observed_probs[0]["NNP"]["Fred"]);
-        assert_eq!(0.5, constrained_model.observed_probs[0]["NNP"]["Ted"]);
-        assert_eq!(0.0, constrained_model.observed_probs[0]["NNP"]["Mary"]);
-        assert_eq!(0.0, constrained_model.observed_probs[0]["VBZ"]["likes"]);
-        assert_eq!(0.25, constrained_model.observed_probs[1]["VBZ"]["sees"]);
-        assert_eq!(0.25, constrained_model.observed_probs[1]["VBZ"]["loves"]);
-        assert_eq!(0.5, constrained_model.observed_probs[1]["VBZ"]["likes"]);
-        assert_eq!(1.0/3.0, constrained_model.observed_probs[1]["NN"]["green"]);
-        assert_eq!(2.0/3.0, constrained_model.observed_probs[1]["NN"]["red"]);
-        assert_eq!(0.2, constrained_model.observed_probs[1]["NNP"]["Fred"]);
-        assert_eq!(0.6, constrained_model.observed_probs[1]["NNP"]["Mary"]);
-        assert_eq!(0.2, constrained_model.observed_probs[1]["NNP"]["Ted"]);
-        assert_eq!(0.2, constrained_model.observed_probs[2]["NNP"]["Fred"]);
-        assert_eq!(0.6, constrained_model.observed_probs[2]["NNP"]["Mary"]);
-        assert_eq!(0.2, constrained_model.observed_probs[2]["NNP"]["Ted"]);
-        assert_eq!(1.0, constrained_model.observed_probs[3]["NN"]["red"]);
-        assert_eq!(0.0, constrained_model.observed_probs[3]["NN"]["green"]);
-        assert_eq!(0.0, constrained_model.observed_probs[3]["NNP"]["Fred"]);
-        assert_eq!(0.0, constrained_model.observed_probs[3]["NNP"]["Mary"]);
-        assert_eq!(0.0, constrained_model.observed_probs[3]["NNP"]["Ted"]);
+        assert!(constrained_model.reachable_with(3, "red"));
+        assert!(!constrained_model.reachable_with(1, "red"));
+        assert!(constrained_model.reachable_with(0, "Ted"));
+        assert!(!constrained_model.reachable_with(0, "Mary"));
+        assert!(!constrained_model.reachable_with(4, "red"));
     }
 
     #[test]
-    fn train_chmm() {
+    fn enumerate_sequences_chmm() {
         let observed_constraints = get_test_constraints();
         let data = String::from(
             "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
@@ -551,147 +3159,285 @@ mod tests {
         let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, Some(observed_constraints));
         constrained_model.train();
 
-        assert_eq!(1.0, constrained_model.hidden_probs[0]["VBZ"]["NNP"]);
-        assert_eq!(1.0, constrained_model.hidden_probs[1]["NNP"]["RB"]);
-        assert_eq!(1.0, constrained_model.hidden_probs[1]["VBZ"]["NNP"]);
-        assert_eq!(0.0, constrained_model.hidden_probs[1]["VBZ"]["NN"]);
-        assert_eq!(1.0, constrained_model.hidden_probs[2]["NNP"]["VBZ"]);
-        assert_eq!(0.0, constrained_model.hidden_probs[2]["NNP"]["RB"]);
-        assert_eq!(1.0, constrained_model.hidden_probs[2]["RB"]["VBZ"]);
-        assert_eq!(1.0, constrained_model.hidden_probs[3]["VBZ"]["NN"]);
-        assert_eq!(0.0, constrained_model.hidden_probs[3]["VBZ"]["NNP"]);
-        assert_eq!(0.0, constrained_model.hidden_probs[3]["RB"]["VBZ"]);
-
-        assert_eq!(0.5, constrained_model.observed_probs[0]["NNP"]["Fred"]);
-        assert_eq!(0.5, constrained_model.observed_probs[0]["NNP"]["Ted"]);
-        assert_eq!(0.0, constrained_model.observed_probs[0]["NNP"]["Mary"]);
-        assert_eq!(0.0, constrained_model.observed_probs[0]["VBZ"]["likes"]);
-        assert_eq!(0.25, constrained_model.observed_probs[1]["VBZ"]["sees"]);
-        assert_eq!(0.25, constrained_model.observed_probs[1]["VBZ"]["loves"]);
-        assert_eq!(0.5, constrained_model.observed_probs[1]["VBZ"]["likes"]);
-        assert_eq!(1.0/3.0, constrained_model.observed_probs[1]["NN"]["green"]);
-        assert_eq!(2.0/3.0, constrained_model.observed_probs[1]["NN"]["red"]);
-        assert_eq!(0.2, constrained_model.observed_probs[1]["NNP"]["Fred"]);
-        assert_eq!(0.6, constrained_model.observed_probs[1]["NNP"]["Mary"]);
-        assert_eq!(0.2, constrained_model.observed_probs[1]["NNP"]["Ted"]);
-        assert_eq!(0.2, constrained_model.observed_probs[2]["NNP"]["Fred"]);
-        assert_eq!(0.6, constrained_model.observed_probs[2]["NNP"]["Mary"]);
-        assert_eq!(0.2, constrained_model.observed_probs[2]["NNP"]["Ted"]);
-        assert_eq!(1.0, constrained_model.observed_probs[3]["NN"]["red"]);
-        assert_eq!(0.0, constrained_model.observed_probs[3]["NN"]["green"]);
-        assert_eq!(0.0, constrained_model.observed_probs[3]["NNP"]["Fred"]);
-        assert_eq!(0.0, constrained_model.observed_probs[3]["NNP"]["Mary"]);
-        assert_eq!(0.0, constrained_model.observed_probs[3]["NNP"]["Ted"]);
+        let mut expected = vec![];
+        for first in ["Fred", "Ted"] {
+            for second in ["now", "sometimes"] {
+                for third in ["likes", "loves", "sees"] {
+                    expected.push(format!("{}:NNP {}:RB {}:VBZ red:NN", first, second, third));
+                }
+            }
+        }
+        expected.sort();
+        assert_eq!(expected, constrained_model.enumerate_sequences());
     }
 
     #[test]
-    fn generate_sequence_chmm() {
-        let observed_constraints: Vec<Box<dyn Constraint + Send>> = vec![
-            Box::new(MultiConstraint::new(vec![
-                Box::new(StartsWithLetterConstraint::new('t')),
-                Box::new(StartsWithLetterConstraint::new('f')),
-            ], false)),
-            Box::new(EmptyConstraint::new()),
-            Box::new(EmptyConstraint::new()),
-            Box::new(MatchesConstraint::new(String::from("green"))),
-        ];
+    fn enumerate_sequences_up_to_limit_chmm() {
+        let observed_constraints = get_test_constraints();
         let data = String::from(
-            "Ted:NNP now:RB likes:VBZ green:NN\nTed:NNP now:RB likes:VBZ green:NN"
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
         );
         let model = HiddenMarkov::new(1, data);
         let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, Some(observed_constraints));
         constrained_model.train();
-        assert_eq!("Ted:NNP now:RB likes:VBZ green:NN", constrained_model.sample_sequence(true));
+
+        assert_eq!(5, constrained_model.enumerate_sequences_up_to(5).len());
     }
 
     #[test]
-    fn generate_random_sequence_chmm() {
-        let observed_constraints = get_test_constraints();
+    fn kl_divergence_from_unconstrained_chmm() {
         let data = String::from(
             "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
         );
         let model = HiddenMarkov::new(1, data);
-        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, Some(observed_constraints));
+
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, Some(get_test_constraints()));
         constrained_model.train();
-        assert_eq!(true, constrained_model.sample_sequence(true).ends_with("red:NN"));
+        assert!(constrained_model.kl_divergence_from_unconstrained().abs() < 1e-9);
+
+        let mut unconstrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, None);
+        unconstrained_model.train();
+        assert!(unconstrained_model.kl_divergence_from_unconstrained().abs() < 1e-9);
     }
 
     #[test]
-    fn sequence_probability_chmm() {
+    fn sample_sequence_formatted_custom_separator_chmm() {
+        let model = HiddenMarkov::new(1, String::from("Mary:NNP likes:VBZ red:NN"));
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 3, None, None);
+        constrained_model.train();
+
+        let fmt = SequenceFormat { token_sep: String::from("|"), field_sep: String::from("/"), include_hidden: true };
+        assert_eq!("Mary/NNP|likes/VBZ|red/NN", constrained_model.sample_sequence_formatted(&fmt));
+        assert_eq!(constrained_model.sample_sequence(true), constrained_model.sample_sequence_formatted(&SequenceFormat::default()));
+    }
+
+    #[test]
+    fn sample_sequence_formatted_without_hidden_chmm() {
+        let model = HiddenMarkov::new(1, String::from("Mary:NNP likes:VBZ red:NN"));
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 3, None, None);
+        constrained_model.train();
+
+        let fmt = SequenceFormat { token_sep: String::from(","), include_hidden: false, ..Default::default() };
+        assert_eq!("Mary,likes,red", constrained_model.sample_sequence_formatted(&fmt));
+    }
+
+    #[test]
+    fn sample_observed_only_chmm() {
+        let model = HiddenMarkov::new(1, String::from("Ted:NNP now:RB likes:VBZ green:NN"));
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 4, None, None);
+        constrained_model.train();
+
+        let observed: Vec<String> = constrained_model.sample_sequence(true).split_whitespace()
+            .map(|token| HiddenMarkov::split_token(token).unwrap().0)
+            .collect();
+        assert_eq!("Ted now likes green", constrained_model.sample_observed_only());
+        assert_eq!(observed.join(" "), constrained_model.sample_observed_only());
+    }
+
+    #[test]
+    fn end_token_variable_length_chmm() {
         let data = String::from(
-            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+            "Mary:NNP runs:VBZ\nTed:NNP likes:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP now:RB"
         );
+        let model = HiddenMarkov::new_with_end(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 6, None, None);
+        constrained_model.train();
+
+        let lengths: HashSet<usize> = (0..200)
+            .map(|_| constrained_model.sample_sequence(true))
+            .inspect(|sequence| assert!(!sequence.contains(END_TOKEN)))
+            .map(|sequence| sequence.split_whitespace().count())
+            .collect();
+        assert!(lengths.len() > 1);
+        assert!(lengths.contains(&2));
+        assert!(lengths.iter().all(|length| (2..=6).contains(length)));
+
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 6, None, None).with_min_length(3);
+        constrained_model.train();
+        for _ in 0..100 {
+            assert!(constrained_model.sample_sequence(true).split_whitespace().count() >= 3);
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct CountingConstraint {
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl Constraint for CountingConstraint {
+        fn is_satisfied_by_state(&self, _word: String) -> bool {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            true
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn box_eq(&self, other: &dyn Any) -> bool {
+            other.downcast_ref::<CountingConstraint>().is_some_and(|a| Arc::ptr_eq(&self.calls, &a.calls))
+        }
+
+        fn debug_fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+            write!(f, "Box:{:?}", self)
+        }
+
+        fn constraint_clone(&self) -> Box<dyn Constraint + Send> {
+            Box::new(self.clone())
+        }
+    }
+
+    #[test]
+    fn constraint_results_cached_chmm() {
+        let data = String::from("Mary:NNP likes:VBZ red:NN\nred:NNP likes:VBZ Mary:NN");
         let model = HiddenMarkov::new(1, data);
-        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, None);
+        let calls = Arc::new(AtomicUsize::new(0));
+        let constraint: Box<dyn Constraint + Send> = Box::new(CountingConstraint { calls: calls.clone() });
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 3, None, Some(vec![constraint; 3]));
         constrained_model.train();
-        assert_eq!(0.0007142857142857144, constrained_model.get_sequence_probability("Ted:NNP sometimes:RB loves:VBZ Fred:NNP"))
+        // Mary, likes and red are each tested once despite appearing under several hidden states and positions
+        assert_eq!(3, calls.load(Ordering::SeqCst));
     }
 
     #[test]
-    fn sequence_probability_two_chmm() {
-        let observed_constraints = get_test_constraints();
+    fn sample_for_hidden_sequence_chmm() {
         let data = String::from(
             "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
         );
         let model = HiddenMarkov::new(1, data);
-        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, Some(observed_constraints));
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 4, None, Some(get_test_constraints()));
         constrained_model.train();
-        assert_eq!(1.0/6.0, constrained_model.get_sequence_probability("Ted:NNP now:RB likes:VBZ red:NN"));
-        assert_eq!(1.0/12.0, constrained_model.get_sequence_probability("Ted:NNP now:RB loves:VBZ red:NN"));
-        assert_eq!(1.0/12.0, constrained_model.get_sequence_probability("Ted:NNP now:RB sees:VBZ red:NN"));
-        assert_eq!(1.0/12.0, constrained_model.get_sequence_probability("Ted:NNP sometimes:RB likes:VBZ red:NN"));
-        assert_eq!(1.0/24.0, constrained_model.get_sequence_probability("Ted:NNP sometimes:RB loves:VBZ red:NN"));
-        assert_eq!(1.0/24.0, constrained_model.get_sequence_probability("Ted:NNP sometimes:RB sees:VBZ red:NN"));
-        assert_eq!(1.0/6.0, constrained_model.get_sequence_probability("Fred:NNP now:RB likes:VBZ red:NN"));
-        assert_eq!(1.0/12.0, constrained_model.get_sequence_probability("Fred:NNP now:RB loves:VBZ red:NN"));
-        assert_eq!(1.0/12.0, constrained_model.get_sequence_probability("Fred:NNP now:RB sees:VBZ red:NN"));
-        assert_eq!(1.0/12.0, constrained_model.get_sequence_probability("Fred:NNP sometimes:RB likes:VBZ red:NN"));
-        assert_eq!(1.0/24.0, constrained_model.get_sequence_probability("Fred:NNP sometimes:RB loves:VBZ red:NN"));
-        assert_eq!(1.0/24.0, constrained_model.get_sequence_probability("Fred:NNP sometimes:RB sees:VBZ red:NN"));
+
+        for _ in 0..20 {
+            let sequence = constrained_model.sample_for_hidden_sequence(&["NNP", "RB", "VBZ", "NN"]).unwrap();
+            let hidden: Vec<String> = sequence.split_whitespace().map(|token| HiddenMarkov::split_token(token).unwrap().1).collect();
+            assert_eq!(vec!["NNP", "RB", "VBZ", "NN"], hidden);
+            assert!(constrained_model.get_sequence_probability(&sequence).unwrap() > 0.0);
+        }
+        // Constraints only allow sequences ending in NN, and NN never follows NNP
+        assert_eq!(None, constrained_model.sample_for_hidden_sequence(&["NNP", "VBZ", "NNP", "RB"]));
+        assert_eq!(None, constrained_model.sample_for_hidden_sequence(&["NNP", "NN", "VBZ", "NN"]));
+        assert_eq!(None, constrained_model.sample_for_hidden_sequence(&["NNP", "RB", "VBZ"]));
     }
 
+    #[cfg(feature = "ordered")]
     #[test]
-    fn higher_order_chmm() {
+    fn ordered_output_is_reproducible_chmm() {
         let data = String::from(
             "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
         );
-        let model = HiddenMarkov::new(2, data);
-        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, None);
-        constrained_model.train();
+        let build = || {
+            let mut constrained_model = ConstrainedHiddenMarkov::new(HiddenMarkov::new(1, data.clone()), 4, None, None);
+            constrained_model.train();
+            constrained_model
+        };
+        let (first, second) = (build(), build());
 
-        assert_eq!(0.5, constrained_model.hidden_probs[0][format!("{} {}", START_TOKEN, START_TOKEN).as_str()]["NNP VBZ"]);
-        assert_eq!(0.5, constrained_model.hidden_probs[0][format!("{} {}", START_TOKEN, START_TOKEN).as_str()]["NNP RB"]);
-        assert_eq!(0.0, constrained_model.hidden_probs[0]["NNP RB"]["VBZ NN"]);
-        assert_eq!(1.0, constrained_model.hidden_probs[1]["NNP RB"]["VBZ NN"]);
-        assert_eq!(1.0, constrained_model.hidden_probs[1]["NNP VBZ"]["NNP RB"]);
-        assert_eq!(0.5, constrained_model.observed_probs[1]["NNP VBZ"]["Fred sees"]);
-        assert_eq!(0.5, constrained_model.observed_probs[1]["NNP VBZ"]["Mary likes"]);
+        assert_eq!(first.enumerate_sequences().join("\n"), second.enumerate_sequences().join("\n"));
+        assert_eq!(serde_json::to_string(&first.hidden_markov_model).unwrap(),
+                   serde_json::to_string(&second.hidden_markov_model).unwrap());
+        assert_eq!(first.sample_n_with_rng(20, &mut StdRng::seed_from_u64(7)).join("\n"),
+                   second.sample_n_with_rng(20, &mut StdRng::seed_from_u64(7)).join("\n"));
+    }
 
-        assert_ne!(0, constrained_model.sample_sequence(true).len());
+    #[derive(Debug)]
+    struct NounsStartWith(char);
+
+    impl JointConstraint for NounsStartWith {
+        fn is_satisfied_by(&self, observed: &str, hidden: &str) -> bool {
+            hidden != "NN" || observed.starts_with(self.0)
+        }
     }
 
     #[test]
-    fn higher_order_with_constraints_chmm() {
-        let hidden_constraints: Vec<Box<dyn Constraint + Send>> = vec![
+    fn joint_constraints_chmm() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let joint_constraints: Vec<Box<dyn JointConstraint + Send>> = (0..4)
+            .map(|_| Box::new(NounsStartWith('r')) as Box<dyn JointConstraint + Send>)
+            .collect();
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 4, None, None)
+            .with_joint_constraints(joint_constraints);
+        constrained_model.train();
+
+        assert_eq!(0.0, constrained_model.observed_probs[3]["NN"]["green"]);
+        assert_eq!(1.0, constrained_model.observed_probs[3]["NN"]["red"]);
+        let sequences = constrained_model.enumerate_sequences();
+        assert!(sequences.iter().any(|sequence| sequence.contains("red:NN")));
+        assert!(sequences.iter().all(|sequence| !sequence.contains("green:NN")));
+        // Other hidden states are unaffected
+        assert!(sequences.iter().any(|sequence| sequence.starts_with("Ted:NNP")));
+    }
+
+    #[test]
+    fn train_with_report_chmm() {
+        let data = String::from("Mary:NNP likes:VBZ red:NN\nMary:NNP likes:VBZ green:NN");
+        let model = HiddenMarkov::new(1, data);
+        let observed_constraints: Vec<Box<dyn Constraint + Send>> = vec![
             Box::new(EmptyConstraint::new()),
-            Box::new(MatchesConstraint::new(String::from("VBZ"))),
+            Box::new(EmptyConstraint::new()),
+            Box::new(MatchesConstraint::new(String::from("red"))),
+        ];
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 3, None, Some(observed_constraints));
+        let report = constrained_model.train_with_report();
+
+        assert_eq!(3, report.positions.len());
+        // Only the last position's constraint removes emissions itself (Mary, likes and green)
+        assert_eq!(vec![0, 0, 3], report.positions.iter().map(|position| position.emissions_removed_by_constraints).collect::<Vec<usize>>());
+        assert!(report.positions.iter().all(|position| position.transitions_removed_by_constraints == 0));
+        assert_eq!(1.0, report.positions[0].surviving_mass);
+        assert!((report.positions[2].surviving_mass - 0.5 / 3.0).abs() < 1e-9);
+        // NNP and VBZ can no longer be transitioned to at the last position
+        assert_eq!(2, report.positions[2].transitions_removed_by_arc_consistency);
+
+        // A constraint nothing satisfies only removes emissions at its own position,
+        // arc-consistency then removes every transition at every position
+        let model = HiddenMarkov::new(1, String::from("Mary:NNP likes:VBZ red:NN\nMary:NNP likes:VBZ green:NN"));
+        let observed_constraints: Vec<Box<dyn Constraint + Send>> = vec![
             Box::new(EmptyConstraint::new()),
             Box::new(EmptyConstraint::new()),
+            Box::new(MatchesConstraint::new(String::from("blue"))),
         ];
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 3, None, Some(observed_constraints));
+        let report = constrained_model.train_with_report();
+        assert_eq!(4, report.positions[2].emissions_removed_by_constraints);
+        assert_eq!(0.0, report.positions[2].surviving_mass);
+        assert!(report.positions.iter().all(|position| position.transitions_removed_by_constraints == 0));
+        assert!(report.positions.iter().all(|position| position.transitions_removed_by_arc_consistency == 3));
+    }
+
+    #[test]
+    fn sample_sequence_with_suffix_chmm() {
         let data = String::from(
             "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
         );
-        let model = HiddenMarkov::new(2, data);
-        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, Some(hidden_constraints), None);
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, None);
         constrained_model.train();
 
-        assert_eq!(1.0, constrained_model.hidden_probs[0][format!("{} {}", START_TOKEN, START_TOKEN).as_str()]["NNP VBZ"]);
-        assert_eq!(0.0, constrained_model.hidden_probs[0][format!("{} {}", START_TOKEN, START_TOKEN).as_str()]["NNP RB"]);
-        assert_eq!(0.0, constrained_model.hidden_probs[0]["NNP RB"]["VBZ NN"]);
-        assert_eq!(0.0, constrained_model.hidden_probs[0]["NNP VBZ"]["NNP RB"]);
-        assert_eq!(1.0, constrained_model.hidden_probs[1]["NNP RB"]["VBZ NN"]);
-        assert_eq!(1.0, constrained_model.hidden_probs[1]["NNP VBZ"]["NNP RB"]);
+        for _ in 0..20 {
+            let sequence = constrained_model.sample_sequence_with_suffix(&["red:NN"]).unwrap();
+            assert_eq!(4, sequence.split_whitespace().count());
+            assert!(sequence.ends_with("red:NN"));
 
-        assert_ne!(0, constrained_model.sample_sequence(true).len());
+            let sequence = constrained_model.sample_sequence_with_suffix(&["loves:VBZ", "red:NN"]).unwrap();
+            assert!(sequence.ends_with("loves:VBZ red:NN"));
+            assert_ne!(Some(0.0), constrained_model.get_sequence_probability(&sequence));
+        }
+    }
+
+    #[test]
+    fn sample_sequence_with_unreachable_suffix_chmm() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 4, None, Some(get_test_constraints()));
+        constrained_model.train();
+
+        assert_eq!(None, constrained_model.sample_sequence_with_suffix(&["green:NN"]));
+        assert_eq!(None, constrained_model.sample_sequence_with_suffix(&["red:NN"; 5]));
     }
 }
\ No newline at end of file
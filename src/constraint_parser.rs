@@ -1,37 +1,112 @@
 use crate::{Constraint, EmptyConstraint, MatchesConstraint, StartsWithLetterConstraint};
 use lazy_static::lazy_static;
 use regex::Regex;
-use crate::constraints::rhymes_with_constraint::RhymesWithConstraint;
+use crate::constraints::rhymes_with_constraint::{RhymesWithConstraint, Strictness};
+use crate::error::ChmmError;
 
-pub(crate) fn parse_constraint(constraint_string: String) -> (Vec<Box<dyn Constraint + Send>>, Vec<Box<dyn Constraint + Send>>) {
+/// Parses one `observed:hidden` constraint pair or `TYPE*COUNT` repetition per line,
+/// skipping blank lines and `#` comments
+///
+/// Errors carry the 1-based line number and text of the first malformed line
+pub(crate) fn parse_constraint(constraint_string: String) -> Result<ConstraintLists, ChmmError> {
     let mut hidden_constraints: Vec<Box<dyn Constraint + Send>> = vec![];
     let mut observed_constraints: Vec<Box<dyn Constraint + Send>> = vec![];
 
-    for line in constraint_string.split("\n") {
-        if line.is_empty() { continue; }
-        if line.contains("*") {
-            add_multi_constraint(line, &mut hidden_constraints, &mut observed_constraints);
+    for (index, line) in constraint_string.split("\n").enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') { continue; }
+        let result = if line.contains("*") {
+            add_multi_constraint(trimmed, &mut hidden_constraints, &mut observed_constraints)
         } else {
-            add_constraint(line, &mut hidden_constraints, &mut observed_constraints);
-        }
+            add_constraint(trimmed, &mut hidden_constraints, &mut observed_constraints)
+        };
+        result.map_err(|message| ChmmError::ConstraintSyntax { line: index + 1, text: trimmed.to_string(), message })?;
+    }
+    Ok((hidden_constraints, observed_constraints))
+}
+
+/// Hidden and observed constraints, one per sequence position
+pub(crate) type ConstraintLists = (Vec<Box<dyn Constraint + Send>>, Vec<Box<dyn Constraint + Send>>);
+
+/// Pads empty constraint lists with `EmptyConstraint`s up to an explicit `length`,
+/// or checks that `length` matches the number of constraints given
+pub(crate) fn fit_constraints_to_length(hidden_constraints: Vec<Box<dyn Constraint + Send>>, observed_constraints: Vec<Box<dyn Constraint + Send>>,
+                                        length: Option<usize>) -> Result<ConstraintLists, ChmmError> {
+    match length {
+        None => Ok((hidden_constraints, observed_constraints)),
+        Some(length) if hidden_constraints.is_empty() && observed_constraints.is_empty() => {
+            let empty: Vec<Box<dyn Constraint + Send>> = vec![Box::new(EmptyConstraint::new()); length];
+            Ok((empty.clone(), empty))
+        },
+        Some(length) if hidden_constraints.len() == length => Ok((hidden_constraints, observed_constraints)),
+        Some(length) => Err(ChmmError::SequenceLengthMismatch { length, constraints: hidden_constraints.len() }),
+    }
+}
+
+/// Expands a comma separated template such as `SW(t),NC*3,MATCH(red)` into one
+/// constraint per position, where `TYPE*COUNT` repeats a constraint `COUNT` times
+pub(crate) fn expand_template(spec: &str, length: usize) -> Result<Vec<Box<dyn Constraint + Send>>, ChmmError> {
+    let mut constraints: Vec<Box<dyn Constraint + Send>> = vec![];
+    for item in spec.split(',').map(|item| item.trim()).filter(|item| !item.is_empty()) {
+        let (constraint_type, count) = match item.rsplit_once('*') {
+            Some((constraint_type, count)) => {
+                let count: usize = count.trim().parse().map_err(|_|
+                    ChmmError::InvalidArgument(format!("invalid repetition count in \"{}\"", item)))?;
+                (constraint_type, count)
+            },
+            None => (item, 1),
+        };
+        let constraint = str_to_constraint(constraint_type);
+        for _ in 0..count { constraints.push(constraint.clone()) }
+    }
+    if constraints.len() != length {
+        return Err(ChmmError::SequenceLengthMismatch { length, constraints: constraints.len() });
+    }
+    Ok(constraints)
+}
+
+/// Expands a space separated phrase such as `happy birthday to you` into one observed
+/// `MatchesConstraint` per word, so that only that phrase can be generated
+pub(crate) fn expand_phrase(phrase: &str, length: usize) -> Result<Vec<Box<dyn Constraint + Send>>, ChmmError> {
+    let constraints: Vec<Box<dyn Constraint + Send>> = phrase.split_whitespace()
+        .map(|word| Box::new(MatchesConstraint::new(word.to_string())) as Box<dyn Constraint + Send>)
+        .collect();
+    if constraints.len() != length {
+        return Err(ChmmError::SequenceLengthMismatch { length, constraints: constraints.len() });
+    }
+    Ok(constraints)
+}
+
+/// Expands a space separated hidden tag template such as `NNP * VBZ NN` into one hidden
+/// `MatchesConstraint` per tag, where `*` matches any tag
+pub(crate) fn expand_tag_template(template: &str, length: usize) -> Result<Vec<Box<dyn Constraint + Send>>, ChmmError> {
+    let constraints: Vec<Box<dyn Constraint + Send>> = template.split_whitespace()
+        .map(|tag| match tag {
+            "*" => Box::new(EmptyConstraint::new()) as Box<dyn Constraint + Send>,
+            _ => Box::new(MatchesConstraint::new(tag.to_string())),
+        })
+        .collect();
+    if constraints.len() != length {
+        return Err(ChmmError::SequenceLengthMismatch { length, constraints: constraints.len() });
     }
-    return (hidden_constraints, observed_constraints)
+    Ok(constraints)
 }
 
-fn add_multi_constraint(line: &str, hidden: &mut Vec<Box<dyn Constraint + Send>>, observed: &mut Vec<Box<dyn Constraint + Send>>) {
+fn add_multi_constraint(line: &str, hidden: &mut Vec<Box<dyn Constraint + Send>>, observed: &mut Vec<Box<dyn Constraint + Send>>) -> Result<(), String> {
     let mut line_split = line.split("*");
     let constraint_type = str_to_constraint(line_split.next().unwrap());
-    let count: i32 = line_split.next().unwrap().parse().unwrap();
+    let count: i32 = line_split.next().unwrap().trim().parse()
+        .map_err(|_| String::from("expected a repetition count after '*'"))?;
     for _ in 0..count { hidden.push(constraint_type.clone())}
     for _ in 0..count { observed.push(constraint_type.clone())}
+    Ok(())
 }
 
-fn add_constraint(line: &str, hidden_constraints: &mut Vec<Box<dyn Constraint + Send>>, observed_constraints: &mut Vec<Box<dyn Constraint + Send>>) {
-    let mut line_split = line.split(":");
-    let observed = line_split.next().unwrap();
-    let hidden = line_split.next().unwrap();
+fn add_constraint(line: &str, hidden_constraints: &mut Vec<Box<dyn Constraint + Send>>, observed_constraints: &mut Vec<Box<dyn Constraint + Send>>) -> Result<(), String> {
+    let (observed, hidden) = line.split_once(":").ok_or_else(|| String::from("expected ':' separator"))?;
     observed_constraints.push(str_to_constraint(observed));
     hidden_constraints.push(str_to_constraint(hidden));
+    Ok(())
 }
 
 // TODO: Support for multi-constraints
@@ -40,6 +115,7 @@ fn str_to_constraint(str: &str) -> Box<dyn Constraint + Send> {
         static ref STARTS_WITH_RE: Regex = Regex::new(r"^SW\((.*)\)").unwrap();
         static ref RHYMES_WITH_RE: Regex = Regex::new(r"^RW\((.*)\)").unwrap();
         static ref EMPTY_RE: Regex = Regex::new(r"^NC").unwrap();
+        static ref MATCH_RE: Regex = Regex::new(r"^MATCH\((.*)\)").unwrap();
     }
     match STARTS_WITH_RE.captures(str) {
         Some(capture) => {
@@ -49,12 +125,143 @@ fn str_to_constraint(str: &str) -> Box<dyn Constraint + Send> {
         _ => (),
     }
     match RHYMES_WITH_RE.captures(str) {
-        Some(capture) => return Box::new(RhymesWithConstraint::new(capture[1].to_string())),
+        Some(capture) => return Box::new(RhymesWithConstraint::new(capture[1].to_string(), Strictness::Exact)),
         _ => (),
     }
+    if let Some(capture) = MATCH_RE.captures(str) {
+        return Box::new(MatchesConstraint::new(capture[1].to_string()))
+    }
     match EMPTY_RE.is_match(str) {
         true => return Box::new(EmptyConstraint::new()),
         false => (),
     }
     return Box::new(MatchesConstraint::new(str.to_string())); // default to match
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fit_empty_constraints_to_length() {
+        let (hidden, observed) = fit_constraints_to_length(vec![], vec![], Some(5)).unwrap();
+        assert_eq!(5, hidden.len());
+        assert_eq!(5, observed.len());
+        assert!(observed.iter().all(|constraint| constraint.is_satisfied_by_state(String::from("anything"))));
+    }
+
+    #[test]
+    fn fit_constraints_without_length() {
+        let (hidden, observed) = parse_constraint(String::from("SW(t):NC\nNC*2\nred:NC")).unwrap();
+        let (hidden, observed) = fit_constraints_to_length(hidden, observed, None).unwrap();
+        assert_eq!(4, hidden.len());
+        assert_eq!(4, observed.len());
+    }
+
+    #[test]
+    fn fit_constraints_matching_length() {
+        let (hidden, observed) = parse_constraint(String::from("SW(t):NC\nNC*2\nred:NC")).unwrap();
+        assert!(fit_constraints_to_length(hidden, observed, Some(4)).is_ok());
+    }
+
+    #[test]
+    fn fit_constraints_conflicting_length() {
+        let (hidden, observed) = parse_constraint(String::from("SW(t):NC\nNC*2\nred:NC")).unwrap();
+        let result = fit_constraints_to_length(hidden, observed, Some(6));
+        assert_eq!(ChmmError::SequenceLengthMismatch { length: 6, constraints: 4 }, result.err().unwrap());
+    }
+
+    #[test]
+    fn expand_comma_template() {
+        let constraints = expand_template("NC,NC,SW(t),NC", 4).unwrap();
+        assert_eq!(4, constraints.len());
+        assert!(constraints[0].is_satisfied_by_state(String::from("anything")));
+        assert!(constraints[2].is_satisfied_by_state(String::from("Ted")));
+        assert!(!constraints[2].is_satisfied_by_state(String::from("Mary")));
+    }
+
+    #[test]
+    fn expand_repeated_template() {
+        let constraints = expand_template("SW(t),NC*3,MATCH(red)", 5).unwrap();
+        assert_eq!(5, constraints.len());
+        assert!(constraints[3].is_satisfied_by_state(String::from("anything")));
+        assert!(constraints[4].is_satisfied_by_state(String::from("red")));
+        assert!(!constraints[4].is_satisfied_by_state(String::from("green")));
+    }
+
+    #[test]
+    fn expand_template_length_mismatch() {
+        let result = expand_template("SW(t),NC*3", 6);
+        assert_eq!(ChmmError::SequenceLengthMismatch { length: 6, constraints: 4 }, result.err().unwrap());
+        assert!(expand_template("NC*x", 1).is_err());
+    }
+
+    #[test]
+    fn expand_phrase_constraints() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = crate::HiddenMarkov::new(1, data);
+        let observed_constraints = expand_phrase("Ted now likes green", 4).unwrap();
+        let mut constrained_model = crate::ConstrainedHiddenMarkov::new(model, 4, None, Some(observed_constraints));
+        constrained_model.train();
+        assert_eq!(vec![String::from("Ted:NNP now:RB likes:VBZ green:NN")], constrained_model.enumerate_sequences());
+    }
+
+    #[test]
+    fn expand_phrase_length_mismatch() {
+        let result = expand_phrase("happy birthday to you", 3);
+        assert_eq!(ChmmError::SequenceLengthMismatch { length: 3, constraints: 4 }, result.err().unwrap());
+    }
+
+    #[test]
+    fn parse_constraint_skips_comments() {
+        let (hidden, observed) = parse_constraint(String::from("# first word\nSW(t):NC\n\n  # padding\nNC*2\nred:NC\n")).unwrap();
+        assert_eq!(4, hidden.len());
+        assert_eq!(4, observed.len());
+    }
+
+    #[test]
+    fn parse_constraint_error_line_number() {
+        let result = parse_constraint(String::from("# header\nNC:NC\n\nSW(t)\nred:NC"));
+        let error = result.err().unwrap();
+        assert_eq!(ChmmError::ConstraintSyntax {
+            line: 4,
+            text: String::from("SW(t)"),
+            message: String::from("expected ':' separator"),
+        }, error);
+        assert_eq!("line 4: expected ':' separator in 'SW(t)'", error.to_string());
+
+        let error = parse_constraint(String::from("NC:NC\nNC*two")).err().unwrap();
+        assert_eq!("line 2: expected a repetition count after '*' in 'NC*two'", error.to_string());
+    }
+
+    #[test]
+    fn expand_tag_template_constraints() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = crate::HiddenMarkov::new(1, data);
+        let hidden_constraints = expand_tag_template("NNP RB VBZ NN", 4).unwrap();
+        let mut constrained_model = crate::ConstrainedHiddenMarkov::new(model.clone(), 4, Some(hidden_constraints), None);
+        constrained_model.train();
+        let sequences = constrained_model.enumerate_sequences();
+        assert!(!sequences.is_empty());
+        for sequence in sequences {
+            let tags: Vec<&str> = sequence.split_whitespace().map(|token| token.split_once(':').unwrap().1).collect();
+            assert_eq!(vec!["NNP", "RB", "VBZ", "NN"], tags);
+        }
+
+        let hidden_constraints = expand_tag_template("NNP * VBZ NN", 4).unwrap();
+        assert!(hidden_constraints[1].is_satisfied_by_state(String::from("VBZ")));
+        let mut constrained_model = crate::ConstrainedHiddenMarkov::new(model, 4, Some(hidden_constraints), None);
+        constrained_model.train();
+        assert!(constrained_model.enumerate_sequences().iter().all(|sequence| sequence.ends_with(":NN")));
+    }
+
+    #[test]
+    fn expand_tag_template_length_mismatch() {
+        let result = expand_tag_template("NNP * VBZ", 4);
+        assert_eq!(ChmmError::SequenceLengthMismatch { length: 4, constraints: 3 }, result.err().unwrap());
+    }
+}
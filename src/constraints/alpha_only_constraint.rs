@@ -0,0 +1,64 @@
+use crate::constraints::Constraint;
+use std::any::Any;
+use std::fmt::{Formatter, Error};
+
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub struct AlphaOnlyConstraint { }
+
+impl AlphaOnlyConstraint {
+    pub fn new() -> AlphaOnlyConstraint {
+        AlphaOnlyConstraint {}
+    }
+}
+
+impl Constraint for AlphaOnlyConstraint {
+    fn is_satisfied_by_state(&self, word: String) -> bool {
+        !word.is_empty() && word.chars().all(char::is_alphabetic)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn box_eq(&self, other: &dyn Any) -> bool {
+        other.downcast_ref::<Self>().is_some_and(|a| self == a)
+    }
+
+    fn debug_fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(f, "Box:{:?}", self)
+    }
+
+    fn constraint_clone(&self) -> Box<dyn Constraint + Send> {
+        Box::new(*self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn satisfying_alpha_only_constraint() {
+        let constraint = AlphaOnlyConstraint::new();
+        assert!(constraint.is_satisfied_by_state(String::from("hello")));
+        assert!(constraint.is_satisfied_by_state(String::from("café")));
+    }
+
+    #[test]
+    fn punctuation_alpha_only_constraint() {
+        let constraint = AlphaOnlyConstraint::new();
+        assert!(!constraint.is_satisfied_by_state(String::from("hello!")));
+    }
+
+    #[test]
+    fn numeric_alpha_only_constraint() {
+        let constraint = AlphaOnlyConstraint::new();
+        assert!(!constraint.is_satisfied_by_state(String::from("42")));
+    }
+
+    #[test]
+    fn empty_alpha_only_constraint() {
+        let constraint = AlphaOnlyConstraint::new();
+        assert!(!constraint.is_satisfied_by_state(String::from("")));
+    }
+}
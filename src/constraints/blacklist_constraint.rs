@@ -0,0 +1,76 @@
+use crate::constraints::Constraint;
+use std::any::Any;
+use std::collections::BTreeSet;
+use std::fmt::{Formatter, Error};
+use std::fs;
+use std::io;
+
+/// Satisfied by any word not in the set, ignoring case (e.g. to forbid stopwords)
+#[derive(Debug, PartialEq, Clone)]
+pub struct BlacklistConstraint {
+    pub words: BTreeSet<String>,
+}
+
+impl BlacklistConstraint {
+    pub fn new(words: Vec<String>) -> BlacklistConstraint {
+        BlacklistConstraint {
+            words: words.iter().map(|word| word.to_lowercase()).collect(),
+        }
+    }
+}
+
+/// Reads a word list with one word per line, skipping blank lines
+pub fn blacklist_from_file(path: &str) -> Result<BlacklistConstraint, io::Error> {
+    let words = fs::read_to_string(path)?;
+    Ok(BlacklistConstraint::new(words.lines()
+        .map(|word| word.trim())
+        .filter(|word| !word.is_empty())
+        .map(String::from)
+        .collect()))
+}
+
+impl Constraint for BlacklistConstraint {
+    fn is_satisfied_by_state(&self, word: String) -> bool {
+        !self.words.contains(&word.to_lowercase())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn box_eq(&self, other: &dyn Any) -> bool {
+        other.downcast_ref::<Self>().is_some_and(|a| self == a)
+    }
+
+    fn debug_fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(f, "Box:{:?}", self)
+    }
+
+    fn constraint_clone(&self) -> Box<dyn Constraint + Send> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn satisfying_blacklist_constraint() {
+        let constraint = BlacklistConstraint::new(vec![String::from("The"), String::from("and")]);
+        assert!(!constraint.is_satisfied_by_state(String::from("the")));
+        assert!(!constraint.is_satisfied_by_state(String::from("AND")));
+        assert!(constraint.is_satisfied_by_state(String::from("red")));
+    }
+
+    #[test]
+    fn blacklist_from_stopword_file() {
+        let constraint = blacklist_from_file("data/stopwords.txt").unwrap();
+        for stopword in ["the", "A", "now", "and"] {
+            assert!(!constraint.is_satisfied_by_state(String::from(stopword)));
+        }
+        assert!(constraint.is_satisfied_by_state(String::from("Mary")));
+        assert!(constraint.is_satisfied_by_state(String::from("")));
+        assert_eq!(io::ErrorKind::NotFound, blacklist_from_file("data/does_not_exist.txt").unwrap_err().kind());
+    }
+}
@@ -0,0 +1,38 @@
+use crate::constraints::SequenceConstraint;
+use std::collections::HashSet;
+
+/// Allows at most `budget` distinct observed words in the whole sequence,
+/// e.g. "use at most 3 distinct words"; repeating a word already used is always allowed
+#[derive(Debug, PartialEq, Clone)]
+pub struct DistinctWordBudgetConstraint {
+    pub budget: usize,
+}
+
+impl DistinctWordBudgetConstraint {
+    pub fn new(budget: usize) -> DistinctWordBudgetConstraint {
+        DistinctWordBudgetConstraint { budget }
+    }
+}
+
+impl SequenceConstraint for DistinctWordBudgetConstraint {
+    fn is_satisfied_by(&self, _position: usize, previous_observed: &[String], observed: &str) -> bool {
+        let distinct: HashSet<&str> = previous_observed.iter().map(String::as_str).collect();
+        distinct.contains(observed) || distinct.len() < self.budget
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distinct_word_budget_constraint() {
+        let constraint = DistinctWordBudgetConstraint::new(2);
+        let previous = vec![String::from("a"), String::from("b"), String::from("a")];
+        assert!(constraint.is_satisfied_by(3, &previous, "a"));
+        assert!(constraint.is_satisfied_by(3, &previous, "b"));
+        assert!(!constraint.is_satisfied_by(3, &previous, "c"));
+        assert!(constraint.is_satisfied_by(1, &previous[..1], "c"));
+        assert!(!DistinctWordBudgetConstraint::new(0).is_satisfied_by(0, &[], "a"));
+    }
+}
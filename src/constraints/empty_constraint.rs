@@ -2,7 +2,7 @@ use crate::constraints::Constraint;
 use std::any::Any;
 use std::fmt::{Formatter, Error};
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
 pub struct EmptyConstraint { }
 
 impl EmptyConstraint {
@@ -0,0 +1,81 @@
+use crate::constraints::Constraint;
+use std::any::Any;
+use std::fmt::{Formatter, Error};
+
+const VOWELS: [char; 5] = ['a', 'e', 'i', 'o', 'u'];
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct FirstVowelConstraint {
+    pub vowel: char
+}
+
+impl FirstVowelConstraint {
+    pub fn new(vowel: char) -> FirstVowelConstraint {
+        FirstVowelConstraint {
+            vowel
+        }
+    }
+}
+
+impl Constraint for FirstVowelConstraint {
+    fn is_satisfied_by_state(&self, word: String) -> bool {
+        match word.chars().map(|letter| letter.to_ascii_lowercase()).find(|letter| VOWELS.contains(letter)) {
+            None => false,
+            Some(first_vowel) => first_vowel == self.vowel.to_ascii_lowercase()
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn box_eq(&self, other: &dyn Any) -> bool {
+        other.downcast_ref::<Self>().is_some_and(|a| self == a)
+    }
+
+    fn debug_fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(f, "Box:{:?}", self)
+    }
+
+    fn constraint_clone(&self) -> Box<dyn Constraint + Send> {
+        Box::new(*self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_first_vowel_constraint() {
+        let constraint = FirstVowelConstraint::new('e');
+        assert_eq!('e', constraint.vowel)
+    }
+
+    #[test]
+    fn satisfying_first_vowel_constraint() {
+        let constraint = FirstVowelConstraint::new('e');
+        assert!(constraint.is_satisfied_by_state(String::from("green")));
+        assert!(constraint.is_satisfied_by_state(String::from("Every")));
+    }
+
+    #[test]
+    fn not_satisfying_first_vowel_constraint() {
+        let constraint = FirstVowelConstraint::new('e');
+        assert!(!constraint.is_satisfied_by_state(String::from("Mary")));
+        assert!(!constraint.is_satisfied_by_state(String::from("sometimes")));
+    }
+
+    #[test]
+    fn uppercase_target_first_vowel_constraint() {
+        let constraint = FirstVowelConstraint::new('O');
+        assert!(constraint.is_satisfied_by_state(String::from("now")));
+    }
+
+    #[test]
+    fn vowel_less_first_vowel_constraint() {
+        let constraint = FirstVowelConstraint::new('a');
+        assert!(!constraint.is_satisfied_by_state(String::from("rhythm")));
+        assert!(!constraint.is_satisfied_by_state(String::from("")));
+    }
+}
@@ -0,0 +1,88 @@
+use crate::constraints::Constraint;
+use std::any::Any;
+use std::fmt::{Formatter, Error};
+use std::sync::Arc;
+
+type StatePredicate = dyn Fn(&str) -> bool + Send + Sync;
+
+/// Wraps a closure for one-off constraints, e.g. `FnConstraint::new(|w| w.len() == 5)`
+///
+/// Closures cannot be compared, so an `FnConstraint` never equals another constraint
+#[derive(Clone)]
+pub struct FnConstraint {
+    predicate: Arc<StatePredicate>,
+}
+
+impl FnConstraint {
+    pub fn new<F: Fn(&str) -> bool + Send + Sync + 'static>(predicate: F) -> FnConstraint {
+        FnConstraint {
+            predicate: Arc::new(predicate)
+        }
+    }
+}
+
+impl Constraint for FnConstraint {
+    fn is_satisfied_by_state(&self, state: String) -> bool {
+        (self.predicate)(&state)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn box_eq(&self, _other: &dyn Any) -> bool {
+        false
+    }
+
+    fn debug_fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(f, "Box:FnConstraint")
+    }
+
+    fn constraint_clone(&self) -> Box<dyn Constraint + Send> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constrained_hidden_markov::ConstrainedHiddenMarkov;
+    use crate::constraints::empty_constraint::EmptyConstraint;
+    use crate::hidden_markov::HiddenMarkov;
+
+    #[test]
+    fn satisfying_fn_constraint() {
+        let constraint = FnConstraint::new(|w| w.len() == 5);
+        assert!(constraint.is_satisfied_by_state(String::from("green")));
+        assert!(!constraint.is_satisfied_by_state(String::from("red")));
+    }
+
+    #[test]
+    fn fn_constraint_never_equal() {
+        let constraint: Box<dyn Constraint + Send> = Box::new(FnConstraint::new(|w| w.is_empty()));
+        assert!(constraint != constraint.clone());
+        assert_eq!("Box:FnConstraint", format!("{:?}", constraint));
+    }
+
+    #[test]
+    fn fn_constraint_chmm() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let observed_constraints: Vec<Box<dyn Constraint + Send>> = vec![
+            Box::new(EmptyConstraint::new()),
+            Box::new(EmptyConstraint::new()),
+            Box::new(EmptyConstraint::new()),
+            Box::new(FnConstraint::new(|w| w.len() == 5)),
+        ];
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 4, None, Some(observed_constraints));
+        constrained_model.train();
+
+        for _ in 0..20 {
+            let sequence = constrained_model.sample_sequence(false);
+            let last_word = sequence.split_whitespace().last().unwrap();
+            assert_eq!(5, last_word.len(), "{} does not end with a five letter word", sequence);
+        }
+    }
+}
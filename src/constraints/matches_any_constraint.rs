@@ -0,0 +1,87 @@
+use crate::constraints::Constraint;
+use std::any::Any;
+use std::collections::HashSet;
+use std::fmt::{Formatter, Error};
+
+/// Satisfied by any state in the set, ignoring case, e.g. the hidden
+/// tags {NN, NNP, NNS} for "must be a noun"
+#[derive(Debug, PartialEq, Clone)]
+pub struct MatchesAnyConstraint {
+    pub states: HashSet<String>
+}
+
+impl MatchesAnyConstraint {
+    pub fn new(states: Vec<String>) -> MatchesAnyConstraint {
+        MatchesAnyConstraint {
+            states: states.iter().map(|state| state.to_lowercase()).collect()
+        }
+    }
+}
+
+impl Constraint for MatchesAnyConstraint {
+    fn is_satisfied_by_state(&self, state: String) -> bool {
+        self.states.contains(&state.to_lowercase())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn box_eq(&self, other: &dyn Any) -> bool {
+        other.downcast_ref::<Self>().is_some_and(|a| self == a)
+    }
+
+    fn debug_fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(f, "Box:{:?}", self)
+    }
+
+    fn constraint_clone(&self) -> Box<dyn Constraint + Send> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constrained_hidden_markov::ConstrainedHiddenMarkov;
+    use crate::constraints::empty_constraint::EmptyConstraint;
+    use crate::hidden_markov::HiddenMarkov;
+
+    fn nouns() -> MatchesAnyConstraint {
+        MatchesAnyConstraint::new(vec![String::from("NN"), String::from("NNP")])
+    }
+
+    #[test]
+    fn satisfying_matches_any_constraint() {
+        let constraint = nouns();
+        assert!(constraint.is_satisfied_by_state(String::from("NN")));
+        assert!(constraint.is_satisfied_by_state(String::from("nnp")));
+    }
+
+    #[test]
+    fn not_satisfying_matches_any_constraint() {
+        let constraint = nouns();
+        assert!(!constraint.is_satisfied_by_state(String::from("VBZ")));
+        assert!(!constraint.is_satisfied_by_state(String::from("NNS")));
+    }
+
+    #[test]
+    fn matches_any_hidden_constraint_chmm() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let hidden_constraints: Vec<Box<dyn Constraint + Send>> = vec![
+            Box::new(EmptyConstraint::new()),
+            Box::new(EmptyConstraint::new()),
+            Box::new(nouns()),
+        ];
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 3, Some(hidden_constraints), None);
+        constrained_model.train();
+
+        let sequences = constrained_model.enumerate_sequences();
+        assert!(sequences.iter().any(|sequence| sequence.ends_with(":NN")));
+        assert!(sequences.iter().any(|sequence| sequence.ends_with(":NNP")));
+        assert!(sequences.iter().all(|sequence| !sequence.ends_with(":VBZ")));
+    }
+}
@@ -1,13 +1,26 @@
-pub(crate) mod starts_with_letter_constraint;
-pub(crate) mod empty_constraint;
-pub(crate) mod matches_constraint;
-pub(crate) mod multi_constraint;
-pub(crate) mod rhymes_with_constraint;
+pub mod starts_with_letter_constraint;
+pub mod empty_constraint;
+pub mod matches_constraint;
+pub mod multi_constraint;
+pub mod rhymes_with_constraint;
+pub mod first_vowel_constraint;
+pub mod repeated_letter_constraint;
+pub mod alpha_only_constraint;
+pub mod prefix_from_set_constraint;
+pub mod starts_and_ends_constraint;
+pub mod word_frequency_constraint;
+pub mod stem_matches_constraint;
+pub mod fn_constraint;
+pub mod whitelist_constraint;
+pub mod matches_any_constraint;
+pub mod blacklist_constraint;
+pub mod distinct_word_budget_constraint;
 
 use std::any::Any;
-use std::fmt::{Formatter, Error, Debug};
+use std::fmt::{self, Formatter, Error, Debug};
 
-pub trait Constraint: Any {
+/// Constraints are `Sync` so trained models can be shared across threads
+pub trait Constraint: Any + Sync {
     // Constraint functions
     fn is_satisfied_by_state(&self, state: String) -> bool;
 
@@ -18,6 +31,66 @@ pub trait Constraint: Any {
     fn constraint_clone(&self) -> Box<dyn Constraint + Send>;
 }
 
+/// Constraint on an observed word together with the hidden state emitting it,
+/// e.g. "words tagged NN must start with r"
+pub trait JointConstraint: Debug + Sync {
+    fn is_satisfied_by(&self, observed: &str, hidden: &str) -> bool;
+}
+
+/// Constraint on an observed word given the words chosen before it in the sequence,
+/// e.g. a budget of distinct words; unlike per-position constraints these are
+/// checked while sampling rather than compiled into the trained matrices
+pub trait SequenceConstraint: Debug + Sync {
+    fn is_satisfied_by(&self, position: usize, previous_observed: &[String], observed: &str) -> bool;
+}
+
+/// Boxed `StartsWithLetterConstraint`, for composing with `&` and `|`
+pub fn starts_with(letter: char) -> Box<dyn Constraint + Send> {
+    Box::new(starts_with_letter_constraint::StartsWithLetterConstraint::new(letter))
+}
+
+/// Boxed `MatchesConstraint`, for composing with `&` and `|`
+pub fn matches(word: &str) -> Box<dyn Constraint + Send> {
+    Box::new(matches_constraint::MatchesConstraint::new(String::from(word)))
+}
+
+/// Human-readable description of a constraint, e.g. `starts with 't' or matches "red"`;
+/// `EmptyConstraint` reads "any" and types without a description fall back to `Debug`
+pub fn describe(constraint: &dyn Constraint) -> String {
+    let any = constraint.as_any();
+    if any.is::<empty_constraint::EmptyConstraint>() {
+        String::from("any")
+    } else if let Some(matches) = any.downcast_ref::<matches_constraint::MatchesConstraint>() {
+        format!("matches {:?}", matches.state)
+    } else if let Some(starts_with) = any.downcast_ref::<starts_with_letter_constraint::StartsWithLetterConstraint>() {
+        format!("starts with {:?}", starts_with.letter)
+    } else if let Some(multi) = any.downcast_ref::<multi_constraint::MultiConstraint>() {
+        let parts: Vec<String> = multi.constraints.iter()
+            .map(|part| match part.as_any().is::<multi_constraint::MultiConstraint>() {
+                true => format!("({})", describe(part.as_ref())),
+                false => describe(part.as_ref()),
+            })
+            .collect();
+        match multi.min_satisfied {
+            Some(min_satisfied) => format!("at least {} of ({})", min_satisfied, parts.join(", ")),
+            None => parts.join(if multi.require_all { " and " } else { " or " }),
+        }
+    } else {
+        let mut description = String::new();
+        let _ = fmt::write(&mut description, format_args!("{:?}", DebugConstraint(constraint)));
+        description.trim_start_matches("Box:").to_string()
+    }
+}
+
+/// Formats a borrowed constraint with its `debug_fmt`
+struct DebugConstraint<'a>(&'a dyn Constraint);
+
+impl Debug for DebugConstraint<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        self.0.debug_fmt(f)
+    }
+}
+
 impl PartialEq for Box<dyn Constraint + Send> {
     fn eq(&self, other: &Box<dyn Constraint + Send>) -> bool {
         self.box_eq(other.as_any())
@@ -53,6 +126,17 @@ mod tests {
         assert_eq!(false, dynamic_constraint.is_satisfied_by_state(String::from("foo")));
     }
     
+    #[test]
+    fn describe_constraints() {
+        assert_eq!("any", describe(&EmptyConstraint::new()));
+        let constraint = (starts_with('t') | starts_with('f')) & matches("ted");
+        assert_eq!("(starts with 't' or starts with 'f') and matches \"ted\"", describe(constraint.as_ref()));
+        let constraint = multi_constraint::MultiConstraint::at_least(vec![starts_with('t'), matches("red")], 1);
+        assert_eq!("at least 1 of (starts with 't', matches \"red\")", describe(&constraint));
+        let constraint = rhymes_with_constraint::RhymesWithConstraint::new(String::from("red"), rhymes_with_constraint::Strictness::Exact);
+        assert_eq!("RhymesWithConstraint { word: \"red\", strictness: Exact }", describe(&constraint));
+    }
+
     #[test]
     fn dynamic_type_constraint_array() {
         let mut dynamic_constraints: Vec<Box<dyn Constraint + Send>> = vec![];
@@ -1,24 +1,76 @@
 use crate::constraints::Constraint;
 use std::any::Any;
 use std::fmt::{Formatter, Error};
+use std::ops::{BitAnd, BitOr};
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct MultiConstraint {
     pub constraints: Vec<Box<dyn Constraint + Send>>,
-    pub require_all: bool
+    pub require_all: bool,
+    /// When set, at least this many constraints must be satisfied (overrides `require_all`)
+    pub min_satisfied: Option<usize>,
 }
 
 impl MultiConstraint {
     pub fn new(constraints: Vec<Box<dyn Constraint + Send>>, require_all: bool) -> MultiConstraint {
         MultiConstraint {
             constraints,
-            require_all
+            require_all,
+            min_satisfied: None,
         }
     }
+
+    /// Satisfied when at least `min_satisfied` of the constraints are
+    pub fn at_least(constraints: Vec<Box<dyn Constraint + Send>>, min_satisfied: usize) -> MultiConstraint {
+        MultiConstraint {
+            constraints,
+            require_all: false,
+            min_satisfied: Some(min_satisfied),
+        }
+    }
+}
+
+impl MultiConstraint {
+    /// Combines two constraints, flattening either side that is already
+    /// a MultiConstraint with the same `require_all`
+    fn combine(lhs: Box<dyn Constraint + Send>, rhs: Box<dyn Constraint + Send>, require_all: bool) -> Box<dyn Constraint + Send> {
+        let mut constraints = vec![];
+        for constraint in [lhs, rhs] {
+            match constraint.as_any().downcast_ref::<MultiConstraint>() {
+                Some(multi) if multi.require_all == require_all && multi.min_satisfied.is_none() => constraints.extend(multi.constraints.iter().cloned()),
+                _ => constraints.push(constraint),
+            }
+        }
+        Box::new(MultiConstraint::new(constraints, require_all))
+    }
+}
+
+/// `a & b` is satisfied when both constraints are
+impl BitAnd for Box<dyn Constraint + Send> {
+    type Output = Box<dyn Constraint + Send>;
+
+    fn bitand(self, rhs: Self) -> Self::Output {
+        MultiConstraint::combine(self, rhs, true)
+    }
+}
+
+/// `a | b` is satisfied when either constraint is
+impl BitOr for Box<dyn Constraint + Send> {
+    type Output = Box<dyn Constraint + Send>;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        MultiConstraint::combine(self, rhs, false)
+    }
 }
 
 impl Constraint for MultiConstraint {
     fn is_satisfied_by_state(&self, word: String) -> bool {
+        if let Some(min_satisfied) = self.min_satisfied {  // satisfies at least min_satisfied constraints
+            return self.constraints.iter()
+                .filter(|constraint| constraint.is_satisfied_by_state(String::from(&word)))
+                .take(min_satisfied)
+                .count() >= min_satisfied;
+        }
         if self.require_all {  // satisfies all constraints
             for constraint in self.constraints.iter() {
                 if !constraint.is_satisfied_by_state(String::from(&word)) {
@@ -58,6 +110,7 @@ mod tests {
     use super::*;
     use crate::constraints::starts_with_letter_constraint::StartsWithLetterConstraint;
     use crate::constraints::matches_constraint::MatchesConstraint;
+    use crate::constraints::{matches, starts_with};
 
     #[test]
     fn new_multi_constraint() {
@@ -112,4 +165,67 @@ mod tests {
         assert_eq!(false, constraint.is_satisfied_by_state(String::from("zebra")));
         assert_eq!(false, constraint.is_satisfied_by_state(String::from("Apple")));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn satisfying_any_operator_constraint() {
+        let constraint = starts_with('x') | starts_with('z') | starts_with('a');
+        let expected: Box<dyn Constraint + Send> = Box::new(MultiConstraint::new(vec![
+            starts_with('x'),
+            starts_with('z'),
+            starts_with('a'),
+        ], false));
+        assert_eq!(&expected, &constraint);
+        assert!(constraint.is_satisfied_by_state(String::from("Xylophone")));
+        assert!(constraint.is_satisfied_by_state(String::from("zebra")));
+        assert!(constraint.is_satisfied_by_state(String::from("Apple")));
+        assert!(!constraint.is_satisfied_by_state(String::from("Beaver")));
+    }
+
+    #[test]
+    fn satisfying_all_operator_constraint() {
+        let constraint = starts_with('x') & matches("Xylo");
+        let expected: Box<dyn Constraint + Send> = Box::new(MultiConstraint::new(vec![
+            starts_with('x'),
+            matches("Xylo"),
+        ], true));
+        assert_eq!(&expected, &constraint);
+        assert!(constraint.is_satisfied_by_state(String::from("Xylo")));
+        assert!(!constraint.is_satisfied_by_state(String::from("X-ray")));
+    }
+
+    #[test]
+    fn mixed_operator_constraint() {
+        let constraint = (starts_with('t') | starts_with('f')) & matches("Fred");
+        assert!(constraint.is_satisfied_by_state(String::from("Fred")));
+        assert!(!constraint.is_satisfied_by_state(String::from("Ted")));
+        assert_eq!(&constraint.clone(), &constraint);
+    }
+
+    fn two_of_three() -> MultiConstraint {
+        MultiConstraint::at_least(vec![
+            starts_with('r'),
+            Box::new(StartsWithLetterConstraint::new('f')),
+            matches("red"),
+        ], 2)
+    }
+
+    #[test]
+    fn satisfying_at_least_multi_constraint() {
+        let constraint = two_of_three();
+        assert!(constraint.is_satisfied_by_state(String::from("red")));
+    }
+
+    #[test]
+    fn not_satisfying_at_least_multi_constraint() {
+        let constraint = two_of_three();
+        assert!(!constraint.is_satisfied_by_state(String::from("fred")));
+        assert!(!constraint.is_satisfied_by_state(String::from("blue")));
+    }
+
+    #[test]
+    fn at_least_multi_constraint_not_flattened() {
+        let combined = Box::new(two_of_three()) as Box<dyn Constraint + Send> | matches("blue");
+        assert!(combined.is_satisfied_by_state(String::from("blue")));
+        assert!(!combined.is_satisfied_by_state(String::from("rose")));
+    }
+}
@@ -0,0 +1,76 @@
+use crate::constraints::Constraint;
+use std::any::Any;
+use std::fmt::{Formatter, Error};
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct PrefixFromSetConstraint {
+    pub prefixes: Vec<String>
+}
+
+impl PrefixFromSetConstraint {
+    pub fn new(prefixes: Vec<String>) -> PrefixFromSetConstraint {
+        PrefixFromSetConstraint {
+            prefixes: prefixes.iter().map(|prefix| prefix.to_lowercase()).collect()
+        }
+    }
+}
+
+impl Constraint for PrefixFromSetConstraint {
+    fn is_satisfied_by_state(&self, word: String) -> bool {
+        let word = word.to_lowercase();
+        self.prefixes.iter().any(|prefix| word.starts_with(prefix.as_str()))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn box_eq(&self, other: &dyn Any) -> bool {
+        other.downcast_ref::<Self>().is_some_and(|a| self == a)
+    }
+
+    fn debug_fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(f, "Box:{:?}", self)
+    }
+
+    fn constraint_clone(&self) -> Box<dyn Constraint + Send> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verb_prefixes() -> PrefixFromSetConstraint {
+        PrefixFromSetConstraint::new(vec![String::from("un"), String::from("Re"), String::from("dis")])
+    }
+
+    #[test]
+    fn new_prefix_from_set_constraint() {
+        let constraint = verb_prefixes();
+        assert_eq!(vec![String::from("un"), String::from("re"), String::from("dis")], constraint.prefixes);
+    }
+
+    #[test]
+    fn satisfying_prefix_from_set_constraint() {
+        let constraint = verb_prefixes();
+        assert!(constraint.is_satisfied_by_state(String::from("undo")));
+        assert!(constraint.is_satisfied_by_state(String::from("Rewrite")));
+        assert!(constraint.is_satisfied_by_state(String::from("DISLIKE")));
+    }
+
+    #[test]
+    fn not_satisfying_prefix_from_set_constraint() {
+        let constraint = verb_prefixes();
+        assert!(!constraint.is_satisfied_by_state(String::from("likes")));
+        assert!(!constraint.is_satisfied_by_state(String::from("")));
+    }
+
+    #[test]
+    fn empty_set_prefix_from_set_constraint() {
+        let constraint = PrefixFromSetConstraint::new(vec![]);
+        assert!(!constraint.is_satisfied_by_state(String::from("undo")));
+        assert!(!constraint.is_satisfied_by_state(String::from("")));
+    }
+}
@@ -0,0 +1,80 @@
+use crate::constraints::Constraint;
+use std::any::Any;
+use std::fmt::{Formatter, Error};
+
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub struct RepeatedLetterConstraint {
+    /// Only this letter may be the repeated one when set
+    pub letter: Option<char>
+}
+
+impl RepeatedLetterConstraint {
+    pub fn new() -> RepeatedLetterConstraint {
+        RepeatedLetterConstraint {
+            letter: None
+        }
+    }
+
+    pub fn of_letter(letter: char) -> RepeatedLetterConstraint {
+        RepeatedLetterConstraint {
+            letter: Some(letter.to_ascii_lowercase())
+        }
+    }
+}
+
+impl Constraint for RepeatedLetterConstraint {
+    fn is_satisfied_by_state(&self, word: String) -> bool {
+        let letters: Vec<char> = word.chars().map(|letter| letter.to_ascii_lowercase()).collect();
+        letters.windows(2).any(|pair| pair[0] == pair[1] && pair[0].is_alphabetic()
+            && (self.letter.is_none() || self.letter == Some(pair[0])))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn box_eq(&self, other: &dyn Any) -> bool {
+        other.downcast_ref::<Self>().is_some_and(|a| self == a)
+    }
+
+    fn debug_fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(f, "Box:{:?}", self)
+    }
+
+    fn constraint_clone(&self) -> Box<dyn Constraint + Send> {
+        Box::new(*self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn satisfying_repeated_letter_constraint() {
+        let constraint = RepeatedLetterConstraint::new();
+        assert!(constraint.is_satisfied_by_state(String::from("green")));
+        assert!(constraint.is_satisfied_by_state(String::from("letter")));
+        assert!(constraint.is_satisfied_by_state(String::from("Aardvark")));
+    }
+
+    #[test]
+    fn not_satisfying_repeated_letter_constraint() {
+        let constraint = RepeatedLetterConstraint::new();
+        assert!(!constraint.is_satisfied_by_state(String::from("red")));
+        assert!(!constraint.is_satisfied_by_state(String::from("--")));
+    }
+
+    #[test]
+    fn empty_repeated_letter_constraint() {
+        let constraint = RepeatedLetterConstraint::new();
+        assert!(!constraint.is_satisfied_by_state(String::from("")));
+    }
+
+    #[test]
+    fn specific_repeated_letter_constraint() {
+        let constraint = RepeatedLetterConstraint::of_letter('T');
+        assert!(constraint.is_satisfied_by_state(String::from("letter")));
+        assert!(!constraint.is_satisfied_by_state(String::from("green")));
+    }
+}
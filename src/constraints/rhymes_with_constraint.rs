@@ -8,23 +8,71 @@ use std::fmt::{Formatter, Error};
 ///
 /// This isn't the most accurate method and sometimes
 /// gives incorrect results (e.g. Fred and red returning
-/// false but Ted and red returning true)
+/// false but Ted and red returning true), which `Strictness::Loose` addresses
 #[derive(Debug, PartialEq, Clone)]
 pub struct RhymesWithConstraint {
-    pub word: String
+    pub word: String,
+    pub strictness: Strictness,
+}
+
+/// How closely the metaphone encodings of two words must match to rhyme
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Strictness {
+    /// The encodings match after their first phoneme, as in `ttaw::metaphone::rhyme`
+    Exact,
+    /// Also accepts words with the same spelled rime (see `spelled_rime`) whose
+    /// encodings, primary or secondary, end in the same phoneme (e.g. Fred and red)
+    Loose,
 }
 
 impl RhymesWithConstraint {
-    pub fn new(word: String) -> RhymesWithConstraint {
+    pub fn new(word: String, strictness: Strictness) -> RhymesWithConstraint {
         RhymesWithConstraint {
-            word: word.to_lowercase()
+            word: word.to_lowercase(),
+            strictness,
         }
     }
 }
 
+/// Whether `a` and `b` have the same spelled rime and their primary or
+/// secondary encodings end in the same phoneme
+///
+/// Metaphone drops vowels, so the rime keeps words that only share their
+/// final consonants (e.g. red and heart) from rhyming
+fn loosely_rhymes(a: &str, b: &str) -> bool {
+    if spelled_rime(a).is_none() || spelled_rime(a) != spelled_rime(b) { return false }
+    let (a, b) = (ttaw::metaphone::encoding(a), ttaw::metaphone::encoding(b));
+    [&a.primary, &a.secondary].iter().any(|a_encoding| {
+        [&b.primary, &b.secondary].iter().any(|b_encoding| {
+            a_encoding.chars().last().is_some_and(|phoneme| b_encoding.ends_with(phoneme))
+        })
+    })
+}
+
+/// The first letter of the last vowel group of `word` followed by the consonants
+/// after it (e.g. "ed" for both red and bread), or `None` if it has no vowel
+///
+/// A final silent e after a consonant is dropped first and y counts as a vowel
+/// after the first letter
+fn spelled_rime(word: &str) -> Option<String> {
+    let mut letters: Vec<char> = word.trim().chars().collect();
+    if letters.len() > 2 && letters.ends_with(&['e']) && !is_vowel(&letters, letters.len() - 2) {
+        letters.pop();
+    }
+    let last_vowel = (0..letters.len()).rev().find(|i| is_vowel(&letters, *i))?;
+    let nucleus = (0..=last_vowel).rev().take_while(|i| is_vowel(&letters, *i)).last()?;
+    Some(std::iter::once(letters[nucleus]).chain(letters[last_vowel + 1..].iter().copied()).collect())
+}
+
+fn is_vowel(letters: &[char], i: usize) -> bool {
+    "aeiou".contains(letters[i]) || (letters[i] == 'y' && i > 0)
+}
+
 impl Constraint for RhymesWithConstraint {
     fn is_satisfied_by_state(&self, word: String) -> bool {
-        ttaw::metaphone::rhyme(self.word.as_str(), word.to_lowercase().as_str())
+        let word = word.to_lowercase();
+        ttaw::metaphone::rhyme(self.word.as_str(), word.as_str())
+            || (self.strictness == Strictness::Loose && loosely_rhymes(self.word.as_str(), word.as_str()))
     }
 
     fn as_any(&self) -> &dyn Any {
@@ -50,28 +98,28 @@ mod tests {
 
     #[test]
     fn new_rhymes_with_constraint() {
-        let constraint = RhymesWithConstraint::new(String::from("FrEd"));
+        let constraint = RhymesWithConstraint::new(String::from("FrEd"), Strictness::Exact);
         assert_eq!("fred", constraint.word)
     }
 
     #[test]
     fn satisfying_rhymes_with_constraint() {
-        let constraint = RhymesWithConstraint::new(String::from("Mary"));
+        let constraint = RhymesWithConstraint::new(String::from("Mary"), Strictness::Exact);
         assert_eq!(true, constraint.is_satisfied_by_state(String::from("Berry")));
         assert_eq!(true, constraint.is_satisfied_by_state(String::from("jeRRy")));
         assert_eq!(true, constraint.is_satisfied_by_state(String::from("gary")));
 
-        let constraint = RhymesWithConstraint::new(String::from("Ted"));
+        let constraint = RhymesWithConstraint::new(String::from("Ted"), Strictness::Exact);
         assert_eq!(true, constraint.is_satisfied_by_state(String::from("red")));
 
-        // Currently a problem for the ttaw method of determining rhymes
-        // let constraint = RhymesWithConstraint::new(String::from("Fred"));
+        // Currently a problem for the ttaw method of determining rhymes, see `Strictness::Loose`
+        // let constraint = RhymesWithConstraint::new(String::from("Fred"), Strictness::Exact);
         // assert_eq!(true, constraint.is_satisfied_by_state(String::from("red")));
     }
 
     #[test]
     fn not_satisfying_rhymes_constraint() {
-        let constraint = RhymesWithConstraint::new(String::from("Mary"));
+        let constraint = RhymesWithConstraint::new(String::from("Mary"), Strictness::Exact);
         assert_eq!(false, constraint.is_satisfied_by_state(String::from("Marge")));
         assert_eq!(false, constraint.is_satisfied_by_state(String::from("Ted")));
         assert_eq!(false, constraint.is_satisfied_by_state(String::from("green")));
@@ -79,7 +127,44 @@ mod tests {
 
     #[test]
     fn empty_satisfying_matches_constraint() {
-        let constraint = RhymesWithConstraint::new(String::from("Fred"));
-        assert_eq!(false, constraint.is_satisfied_by_state(String::from("")))
+        let constraint = RhymesWithConstraint::new(String::from("Fred"), Strictness::Exact);
+        assert!(!constraint.is_satisfied_by_state(String::from("")));
+        let constraint = RhymesWithConstraint::new(String::from("Fred"), Strictness::Loose);
+        assert!(!constraint.is_satisfied_by_state(String::from("")));
+    }
+
+    #[test]
+    fn loose_rhymes_with_constraint() {
+        let constraint = RhymesWithConstraint::new(String::from("Fred"), Strictness::Loose);
+        assert!(constraint.is_satisfied_by_state(String::from("red")));
+        assert!(constraint.is_satisfied_by_state(String::from("bread")));
+        assert!(constraint.is_satisfied_by_state(String::from("sled")));
+        for word in ["rat", "road", "ride", "root"] {
+            assert!(!constraint.is_satisfied_by_state(String::from(word)), "{}", word);
+        }
+
+        let constraint = RhymesWithConstraint::new(String::from("Mary"), Strictness::Loose);
+        assert!(constraint.is_satisfied_by_state(String::from("Berry")));
+        assert!(!constraint.is_satisfied_by_state(String::from("Marge")));
+        assert!(!constraint.is_satisfied_by_state(String::from("Ted")));
+        assert!(!constraint.is_satisfied_by_state(String::from("green")));
+
+        let constraint = RhymesWithConstraint::new(String::from("red"), Strictness::Loose);
+        assert!(constraint.is_satisfied_by_state(String::from("Ted")));
+        assert!(!constraint.is_satisfied_by_state(String::from("ran")));
+        assert!(!constraint.is_satisfied_by_state(String::from("green")));
+        for word in ["heart", "art", "dirt", "court", "forty", "word"] {
+            assert!(!constraint.is_satisfied_by_state(String::from(word)), "{}", word);
+        }
+    }
+
+    #[test]
+    fn spelled_rimes() {
+        assert_eq!(Some(String::from("ed")), spelled_rime("bread"));
+        assert_eq!(Some(String::from("ert")), spelled_rime("heart"));
+        assert_eq!(Some(String::from("id")), spelled_rime("ride"));
+        assert_eq!(Some(String::from("y")), spelled_rime("mary"));
+        assert_eq!(Some(String::from("e")), spelled_rime("free"));
+        assert_eq!(None, spelled_rime("shh"));
     }
 }
\ No newline at end of file
@@ -0,0 +1,77 @@
+use crate::constraints::Constraint;
+use std::any::Any;
+use std::fmt::{Formatter, Error};
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct StartsAndEndsConstraint {
+    pub start: char,
+    pub end: char
+}
+
+impl StartsAndEndsConstraint {
+    pub fn new(start: char, end: char) -> StartsAndEndsConstraint {
+        StartsAndEndsConstraint {
+            start,
+            end
+        }
+    }
+}
+
+impl Constraint for StartsAndEndsConstraint {
+    fn is_satisfied_by_state(&self, word: String) -> bool {
+        match (word.chars().next(), word.chars().last()) {
+            (Some(first_letter), Some(last_letter)) =>
+                first_letter.eq_ignore_ascii_case(&self.start) && last_letter.eq_ignore_ascii_case(&self.end),
+            _ => false
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn box_eq(&self, other: &dyn Any) -> bool {
+        other.downcast_ref::<Self>().is_some_and(|a| self == a)
+    }
+
+    fn debug_fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(f, "Box:{:?}", self)
+    }
+
+    fn constraint_clone(&self) -> Box<dyn Constraint + Send> {
+        Box::new(*self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_starts_and_ends_constraint() {
+        let constraint = StartsAndEndsConstraint::new('t', 'p');
+        assert_eq!('t', constraint.start);
+        assert_eq!('p', constraint.end);
+    }
+
+    #[test]
+    fn satisfying_starts_and_ends_constraint() {
+        let constraint = StartsAndEndsConstraint::new('t', 't');
+        assert!(constraint.is_satisfied_by_state(String::from("tot")));
+        assert!(constraint.is_satisfied_by_state(String::from("TreaT")));
+    }
+
+    #[test]
+    fn not_satisfying_starts_and_ends_constraint() {
+        let constraint = StartsAndEndsConstraint::new('t', 't');
+        assert!(!constraint.is_satisfied_by_state(String::from("top")));
+        assert!(!constraint.is_satisfied_by_state(String::from("")));
+    }
+
+    #[test]
+    fn single_letter_starts_and_ends_constraint() {
+        let constraint = StartsAndEndsConstraint::new('t', 't');
+        assert!(constraint.is_satisfied_by_state(String::from("t")));
+        assert!(!StartsAndEndsConstraint::new('t', 'p').is_satisfied_by_state(String::from("t")));
+    }
+}
@@ -0,0 +1,156 @@
+use crate::constraints::Constraint;
+use std::any::Any;
+use std::fmt::{Formatter, Error};
+
+/// Matches words sharing a stem with `state`, e.g. "run" matches "running" and "runs"
+///
+/// Stems come from the first step of the Porter stemmer (plurals and -ed/-ing/-y
+/// endings), so derivational suffixes such as "-er" or "-ness" are kept and irregular
+/// forms like "ran" do not match "run". Only lowercase ASCII letters are stemmed.
+#[derive(Debug, PartialEq, Clone)]
+pub struct StemMatchesConstraint {
+    pub state: String,
+    stem: String,
+}
+
+impl StemMatchesConstraint {
+    pub fn new(state: String) -> StemMatchesConstraint {
+        let state = state.to_lowercase();
+        StemMatchesConstraint {
+            stem: stem(&state),
+            state,
+        }
+    }
+}
+
+impl Constraint for StemMatchesConstraint {
+    fn is_satisfied_by_state(&self, state: String) -> bool {
+        stem(&state.to_lowercase()) == self.stem
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn box_eq(&self, other: &dyn Any) -> bool {
+        other.downcast_ref::<Self>().is_some_and(|a| self == a)
+    }
+
+    fn debug_fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(f, "Box:{:?}", self)
+    }
+
+    fn constraint_clone(&self) -> Box<dyn Constraint + Send> {
+        Box::new(self.clone())
+    }
+}
+
+/// Porter step 1: strips plurals, -ed and -ing, then turns a final y into i
+fn stem(word: &str) -> String {
+    if word.len() <= 2 || !word.bytes().all(|c| c.is_ascii_lowercase()) {
+        return word.to_string();
+    }
+    let mut word = word.to_string();
+
+    // Step 1a
+    if word.ends_with("sses") || word.ends_with("ies") {
+        word.truncate(word.len() - 2);
+    } else if word.ends_with('s') && !word.ends_with("ss") {
+        word.pop();
+    }
+
+    // Step 1b
+    if word.ends_with("eed") {
+        if measure(&word[..word.len() - 3]) > 0 { word.pop(); }
+    } else if let Some(suffix) = ["ed", "ing"].iter().find(|suffix| word.ends_with(*suffix)) {
+        let base = &word[..word.len() - suffix.len()];
+        if has_vowel(base) {
+            word.truncate(base.len());
+            if word.ends_with("at") || word.ends_with("bl") || word.ends_with("iz") {
+                word.push('e');
+            } else if ends_with_double_consonant(&word) && !(word.ends_with('l') || word.ends_with('s') || word.ends_with('z')) {
+                word.pop();
+            } else if measure(&word) == 1 && ends_with_cvc(&word) {
+                word.push('e');
+            }
+        }
+    }
+
+    // Step 1c
+    if word.ends_with('y') && has_vowel(&word[..word.len() - 1]) {
+        word.pop();
+        word.push('i');
+    }
+    word
+}
+
+fn is_consonant(word: &[u8], i: usize) -> bool {
+    match word[i] {
+        b'a' | b'e' | b'i' | b'o' | b'u' => false,
+        b'y' => i == 0 || !is_consonant(word, i - 1),
+        _ => true,
+    }
+}
+
+/// Number of vowel-consonant sequences, the m in [C](VC)^m[V]
+fn measure(word: &str) -> usize {
+    let bytes = word.as_bytes();
+    let mut count = 0;
+    let mut previous_vowel = false;
+    for i in 0..bytes.len() {
+        let consonant = is_consonant(bytes, i);
+        if consonant && previous_vowel { count += 1 }
+        previous_vowel = !consonant;
+    }
+    count
+}
+
+fn has_vowel(word: &str) -> bool {
+    (0..word.len()).any(|i| !is_consonant(word.as_bytes(), i))
+}
+
+fn ends_with_double_consonant(word: &str) -> bool {
+    let bytes = word.as_bytes();
+    let n = bytes.len();
+    n >= 2 && bytes[n - 1] == bytes[n - 2] && is_consonant(bytes, n - 1)
+}
+
+/// Ends consonant-vowel-consonant where the last consonant is not w, x or y
+fn ends_with_cvc(word: &str) -> bool {
+    let bytes = word.as_bytes();
+    let n = bytes.len();
+    n >= 3 && is_consonant(bytes, n - 3) && !is_consonant(bytes, n - 2) && is_consonant(bytes, n - 1)
+        && !matches!(bytes[n - 1], b'w' | b'x' | b'y')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_stem_matches_constraint() {
+        let constraint = StemMatchesConstraint::new(String::from("Run"));
+        assert_eq!("run", constraint.state);
+    }
+
+    #[test]
+    fn satisfying_stem_matches_constraint() {
+        let constraint = StemMatchesConstraint::new(String::from("run"));
+        assert!(constraint.is_satisfied_by_state(String::from("running")));
+        assert!(constraint.is_satisfied_by_state(String::from("runs")));
+        assert!(constraint.is_satisfied_by_state(String::from("Run")));
+        let constraint = StemMatchesConstraint::new(String::from("hoped"));
+        assert!(constraint.is_satisfied_by_state(String::from("hoping")));
+        assert!(constraint.is_satisfied_by_state(String::from("hope")));
+    }
+
+    #[test]
+    fn not_satisfying_stem_matches_constraint() {
+        let constraint = StemMatchesConstraint::new(String::from("runner"));
+        assert!(!constraint.is_satisfied_by_state(String::from("swim")));
+        assert!(!constraint.is_satisfied_by_state(String::from("run")));
+        // Irregular forms are not stemmed
+        let constraint = StemMatchesConstraint::new(String::from("run"));
+        assert!(!constraint.is_satisfied_by_state(String::from("ran")));
+    }
+}
@@ -0,0 +1,76 @@
+use crate::constraints::Constraint;
+use std::any::Any;
+use std::collections::BTreeSet;
+use std::fmt::{Formatter, Error};
+
+/// Satisfied by any word in the set, ignoring case unless `case_sensitive`
+/// (e.g. to tell "US" from "us" in code-like tokens)
+#[derive(Debug, PartialEq, Clone)]
+pub struct WhitelistConstraint {
+    pub words: BTreeSet<String>,
+    pub case_sensitive: bool,
+}
+
+impl WhitelistConstraint {
+    pub fn new(words: Vec<String>) -> WhitelistConstraint {
+        WhitelistConstraint {
+            words: words.iter().map(|word| word.to_lowercase()).collect(),
+            case_sensitive: false,
+        }
+    }
+
+    pub fn case_sensitive(words: Vec<String>) -> WhitelistConstraint {
+        WhitelistConstraint {
+            words: words.into_iter().collect(),
+            case_sensitive: true,
+        }
+    }
+}
+
+impl Constraint for WhitelistConstraint {
+    fn is_satisfied_by_state(&self, word: String) -> bool {
+        if self.case_sensitive {
+            self.words.contains(&word)
+        } else {
+            self.words.contains(&word.to_lowercase())
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn box_eq(&self, other: &dyn Any) -> bool {
+        other.downcast_ref::<Self>().is_some_and(|a| self == a)
+    }
+
+    fn debug_fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(f, "Box:{:?}", self)
+    }
+
+    fn constraint_clone(&self) -> Box<dyn Constraint + Send> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn satisfying_whitelist_constraint() {
+        let constraint = WhitelistConstraint::new(vec![String::from("US"), String::from("red")]);
+        assert!(constraint.is_satisfied_by_state(String::from("US")));
+        assert!(constraint.is_satisfied_by_state(String::from("us")));
+        assert!(constraint.is_satisfied_by_state(String::from("Red")));
+        assert!(!constraint.is_satisfied_by_state(String::from("green")));
+    }
+
+    #[test]
+    fn case_sensitive_whitelist_constraint() {
+        let constraint = WhitelistConstraint::case_sensitive(vec![String::from("US"), String::from("red")]);
+        assert!(constraint.is_satisfied_by_state(String::from("US")));
+        assert!(!constraint.is_satisfied_by_state(String::from("us")));
+        assert!(!constraint.is_satisfied_by_state(String::from("Red")));
+    }
+}
@@ -0,0 +1,94 @@
+use crate::constraints::Constraint;
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt::{Formatter, Error};
+use std::sync::Arc;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct WordFrequencyConstraint {
+    /// Corpus frequency of each observed word, shared between clones
+    pub frequencies: Arc<HashMap<String, f64>>,
+    pub min: f64,
+    pub max: f64
+}
+
+impl WordFrequencyConstraint {
+    /// Satisfied by words whose frequency lies in `[min, max]`; unseen words have frequency 0
+    pub fn new(frequencies: HashMap<String, f64>, min: f64, max: f64) -> WordFrequencyConstraint {
+        WordFrequencyConstraint {
+            frequencies: Arc::new(frequencies),
+            min,
+            max
+        }
+    }
+
+    /// Satisfied by words seen at least `min` times
+    pub fn at_least(frequencies: HashMap<String, f64>, min: f64) -> WordFrequencyConstraint {
+        WordFrequencyConstraint::new(frequencies, min, f64::INFINITY)
+    }
+}
+
+impl Constraint for WordFrequencyConstraint {
+    fn is_satisfied_by_state(&self, word: String) -> bool {
+        let frequency = *self.frequencies.get(&word).unwrap_or(&0.0);
+        frequency >= self.min && frequency <= self.max
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn box_eq(&self, other: &dyn Any) -> bool {
+        other.downcast_ref::<Self>().is_some_and(|a| self == a)
+    }
+
+    fn debug_fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(f, "Box:WordFrequencyConstraint {{ words: {}, min: {}, max: {} }}", self.frequencies.len(), self.min, self.max)
+    }
+
+    fn constraint_clone(&self) -> Box<dyn Constraint + Send> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hidden_markov::HiddenMarkov;
+
+    fn test_frequencies() -> HashMap<String, f64> {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        HiddenMarkov::new(1, data).observed_frequencies()
+    }
+
+    #[test]
+    fn at_least_word_frequency_constraint() {
+        let constraint = WordFrequencyConstraint::at_least(test_frequencies(), 2.0);
+        assert!(constraint.is_satisfied_by_state(String::from("Mary")));
+        assert!(!constraint.is_satisfied_by_state(String::from("Ted")));
+    }
+
+    #[test]
+    fn range_word_frequency_constraint() {
+        let constraint = WordFrequencyConstraint::new(test_frequencies(), 1.0, 2.0);
+        assert!(constraint.is_satisfied_by_state(String::from("Ted")));
+        assert!(constraint.is_satisfied_by_state(String::from("likes")));
+        assert!(!constraint.is_satisfied_by_state(String::from("Mary")));
+    }
+
+    #[test]
+    fn unseen_word_frequency_constraint() {
+        let constraint = WordFrequencyConstraint::new(test_frequencies(), 1.0, 2.0);
+        assert!(!constraint.is_satisfied_by_state(String::from("Bob")));
+        let constraint = WordFrequencyConstraint::new(test_frequencies(), 0.0, 0.0);
+        assert!(constraint.is_satisfied_by_state(String::from("Bob")));
+    }
+
+    #[test]
+    fn clone_word_frequency_constraint() {
+        let constraint: Box<dyn Constraint + Send> = Box::new(WordFrequencyConstraint::at_least(test_frequencies(), 2.0));
+        assert_eq!(&constraint.clone(), &constraint);
+    }
+}
@@ -0,0 +1,41 @@
+use std::fmt;
+
+/// Errors returned by model operations
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChmmError {
+    /// Two models with different markov orders were combined
+    MarkovOrderMismatch { expected: u32, found: u32 },
+    /// An explicit sequence length disagrees with the number of constraints
+    SequenceLengthMismatch { length: usize, constraints: usize },
+    /// A command line or config value could not be used
+    InvalidArgument(String),
+    /// The config file could not be read or parsed
+    InvalidConfig(String),
+    /// A line of the constraint grammar is malformed, with its 1-based line number and text
+    ConstraintSyntax { line: usize, text: String, message: String },
+    /// A sampled path had no surviving successor at a markov order token position
+    DeadPath { position: usize, attempts: usize },
+    /// A quoted observed chunk opened at the 1-based token index of `text` was never closed
+    UnclosedQuote { token: usize, text: String },
+}
+
+impl fmt::Display for ChmmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChmmError::MarkovOrderMismatch { expected, found } =>
+                write!(f, "markov order mismatch: expected {} but found {}", expected, found),
+            ChmmError::SequenceLengthMismatch { length, constraints } =>
+                write!(f, "sequence length {} does not match the {} constraints given", length, constraints),
+            ChmmError::InvalidArgument(message) => write!(f, "{}", message),
+            ChmmError::InvalidConfig(message) => write!(f, "{}", message),
+            ChmmError::ConstraintSyntax { line, text, message } =>
+                write!(f, "line {}: {} in '{}'", line, message, text),
+            ChmmError::DeadPath { position, attempts } =>
+                write!(f, "sampled path died at position {} after {} attempt(s)", position, attempts),
+            ChmmError::UnclosedQuote { token, text } =>
+                write!(f, "unclosed quote at token {} of '{}'", token, text),
+        }
+    }
+}
+
+impl std::error::Error for ChmmError {}
@@ -1,22 +1,76 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::io::BufRead;
 
 use rand::Rng;
 
-use crate::utils::START_TOKEN;
+use crate::constraints::Constraint;
+use crate::error::ChmmError;
+use crate::utils::{format_observed, split_tokens, ProbMap, END_TOKEN, START_TOKEN, UNKNOWN_TOKEN};
 
-#[derive(Debug, Clone)]
+/// Largest probability difference `compare` treats as unchanged
+pub(crate) const COMPARE_TOLERANCE: f64 = 1e-9;
+
+/// Cleanup applied to the observed word of each training token; hidden tags are left untouched
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TokenNormalizer {
+    pub lowercase: bool,
+    /// Strip leading and trailing ASCII punctuation, unless nothing else is left
+    pub strip_punctuation: bool,
+    /// Replace every digit with `0`, so that e.g. "1999" and "2024" share one token
+    pub collapse_digits: bool,
+}
+
+impl TokenNormalizer {
+    pub fn normalize(&self, observed: &str) -> String {
+        let mut normalized = observed.to_owned();
+        if self.strip_punctuation {
+            let stripped = normalized.trim_matches(|c: char| c.is_ascii_punctuation());
+            if !stripped.is_empty() { normalized = stripped.to_owned() }
+        }
+        if self.lowercase {
+            normalized = normalized.to_lowercase();
+        }
+        if self.collapse_digits {
+            normalized = normalized.chars().map(|c| if c.is_ascii_digit() { '0' } else { c }).collect();
+        }
+        normalized
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct HiddenMarkov {
     pub markov_order: u32,
-    pub hidden_probs: HashMap<String, HashMap<String, f64>>,
-    pub observed_probs: HashMap<String, HashMap<String, f64>>,
+    pub hidden_probs: ProbMap<String, ProbMap<String, f64>>,
+    pub observed_probs: ProbMap<String, ProbMap<String, f64>>,
+    /// Raw transition counts the hidden probabilities were normalized from
+    #[serde(default)]
+    pub hidden_counts: ProbMap<String, ProbMap<String, f64>>,
+    /// Raw emission counts the observed probabilities were normalized from
+    #[serde(default)]
+    pub observed_counts: ProbMap<String, ProbMap<String, f64>>,
+    /// 1-based (line, token) positions of training tokens skipped for missing the `:`,
+    /// or of the unclosed quote of a line skipped as a whole
+    #[serde(skip)]
+    pub skipped_tokens: Vec<(usize, usize)>,
 }
 
 impl HiddenMarkov {
+    /// Trains a new model on line separated `observed:hidden` tokens
+    ///
+    /// Panics if `markov_order` is 0
+    ///
+    /// Empty or whitespace-only data produces an empty model (see `is_empty`)
+    /// that samples empty sequences and cannot score sequences
     pub fn new(markov_order: u32, data: String) -> HiddenMarkov {
         let mut hmm = HiddenMarkov {
            markov_order,
            hidden_probs: Default::default(),
            observed_probs: Default::default(),
+           hidden_counts: Default::default(),
+           observed_counts: Default::default(),
+           skipped_tokens: Default::default(),
         };
 
         hmm.train(data);
@@ -24,24 +78,345 @@ impl HiddenMarkov {
         return hmm;
     }
 
+    /// Trains like `new`, cleaning up each observed word with `normalizer` first
+    pub fn new_with_normalizer(markov_order: u32, data: String, normalizer: TokenNormalizer) -> HiddenMarkov {
+        let mut hmm = HiddenMarkov::new(markov_order, String::new());
+        hmm.train_with_normalizer(data, &normalizer);
+        hmm
+    }
+
+    /// Trains like `new`, but folds observed words seen at most `unknown_threshold`
+    /// times into `UNKNOWN_TOKEN`, whose emission probabilities are then used
+    /// when scoring words that never appeared in training
+    pub fn new_with_unknown(markov_order: u32, data: String, unknown_threshold: usize) -> HiddenMarkov {
+        HiddenMarkov::new(markov_order, HiddenMarkov::fold_rare_observed(&data, unknown_threshold))
+    }
+
+    fn fold_rare_observed(data: &str, unknown_threshold: usize) -> String {
+        // Lines with an unclosed quote are kept as they are and skipped in training
+        let lines: Vec<Result<Vec<String>, ChmmError>> = data.split('\n').map(split_tokens).collect();
+        let mut observed_counts: ProbMap<&str, usize> = ProbMap::new();
+        for token in lines.iter().flatten().flatten() {
+            let (observed, _) = token.split_once(':').unwrap_or((token, ""));
+            *observed_counts.entry(observed).or_insert(0) += 1;
+        }
+        let lines: Vec<String> = lines.iter().zip(data.split('\n')).map(|(tokens, line)| match tokens {
+            Ok(tokens) => tokens.iter().map(|token| match token.split_once(':') {
+                Some((observed, hidden)) if observed_counts[observed] <= unknown_threshold => format!("{}:{}", UNKNOWN_TOKEN, hidden),
+                _ => token.to_owned(),
+            }).collect::<Vec<String>>().join(" "),
+            Err(_) => line.to_owned(),
+        }).collect();
+        lines.join("\n")
+    }
+
+    /// Trains like `new`, but also learns line ends as transitions into `END_TOKEN`,
+    /// an absorbing hidden state that lets constrained models end sequences early
+    pub fn new_with_end(markov_order: u32, data: String) -> HiddenMarkov {
+        let mut hmm = HiddenMarkov::new(markov_order, HiddenMarkov::append_end_tokens(&data, markov_order));
+        hmm.make_end_absorbing();
+        hmm
+    }
+
+    fn append_end_tokens(data: &str, markov_order: u32) -> String {
+        let end_token = format!("{}:{}", END_TOKEN, END_TOKEN);
+        let lines: Vec<String> = data.split('\n').map(|line| match split_tokens(line) {
+            Ok(mut tokens) => {
+                if !tokens.is_empty() {
+                    tokens.extend(vec![end_token.clone(); markov_order as usize]);
+                }
+                tokens.join(" ")
+            },
+            // Kept as it is and skipped in training
+            Err(_) => line.to_owned(),
+        }).collect();
+        lines.join("\n")
+    }
+
+    /// Every hidden state containing `END_TOKEN` transitions to the all-end state,
+    /// which only transitions to and emits itself
+    fn make_end_absorbing(&mut self) {
+        let end_state = vec![END_TOKEN; self.markov_order as usize].join(" ");
+        let mut end_states: Vec<String> = self.observed_probs.keys()
+            .filter(|hidden| hidden.split_whitespace().any(|state| state == END_TOKEN))
+            .cloned()
+            .collect();
+        end_states.push(end_state.clone());
+        for hidden in end_states {
+            self.hidden_probs.insert(hidden, ProbMap::from([(end_state.clone(), 1.0)]));
+        }
+        self.observed_probs.insert(end_state.clone(), ProbMap::from([(end_state, 1.0)]));
+    }
+
     pub fn train(&mut self, data: String) {
+        self.train_with_normalizer(data, &TokenNormalizer::default())
+    }
+
+    pub fn train_with_normalizer(&mut self, data: String, normalizer: &TokenNormalizer) {
+        assert!(self.markov_order >= 1, "markov_order must be >= 1");
         self.clear_probs();
 
         let lines = data.split("\n");
-        for line in lines {
-            self.process_line(line)
+        for (line_number, line) in lines.enumerate() {
+            self.process_line(line_number + 1, line, normalizer)
+        }
+
+        self.normalize();
+    }
+
+    /// Trains like `train`, reading `observed:hidden` lines from `reader` one at a time
+    /// so that only the counts, not the whole corpus, are held in memory
+    pub fn train_from_reader(&mut self, reader: impl BufRead) -> Result<(), io::Error> {
+        assert!(self.markov_order >= 1, "markov_order must be >= 1");
+        self.clear_probs();
+
+        let normalizer = TokenNormalizer::default();
+        for (line_number, line) in reader.lines().enumerate() {
+            self.process_line(line_number + 1, &line?, &normalizer)
         }
 
         self.normalize();
+        Ok(())
+    }
+
+    /// Builds a model from pre-aggregated transition and emission counts,
+    /// keyed the same way as `hidden_probs` and `observed_probs`
+    pub fn from_counts(markov_order: u32, hidden_counts: ProbMap<String, ProbMap<String, f64>>,
+                       observed_counts: ProbMap<String, ProbMap<String, f64>>) -> HiddenMarkov {
+        assert!(markov_order >= 1, "markov_order must be >= 1");
+        let mut hmm = HiddenMarkov {
+            markov_order,
+            hidden_probs: hidden_counts,
+            observed_probs: observed_counts,
+            hidden_counts: Default::default(),
+            observed_counts: Default::default(),
+            skipped_tokens: Default::default(),
+        };
+
+        hmm.normalize();
+
+        hmm
+    }
+
+    /// Drops every transition and emission seen fewer than `min_count` times
+    /// in training, then re-normalizes the remaining raw counts
+    ///
+    /// Models without raw counts (e.g. saved before they were kept) are left unchanged
+    pub fn prune_rare(&mut self, min_count: f64) {
+        if self.hidden_counts.is_empty() { return }
+        self.hidden_probs = HiddenMarkov::prune_nested_map(&self.hidden_counts, min_count);
+        self.observed_probs = HiddenMarkov::prune_nested_map(&self.observed_counts, min_count);
+        self.normalize();
+    }
+
+    fn prune_nested_map(map: &ProbMap<String, ProbMap<String, f64>>, min_count: f64) -> ProbMap<String, ProbMap<String, f64>> {
+        map.iter()
+            .map(|(outer_key, inner_map)| {
+                let kept: ProbMap<String, f64> = inner_map.iter()
+                    .filter(|(_, count)| **count >= min_count)
+                    .map(|(inner_key, count)| (inner_key.to_owned(), *count))
+                    .collect();
+                (outer_key.to_owned(), kept)
+            })
+            .filter(|(_, inner_map)| !inner_map.is_empty())
+            .collect()
+    }
+
+    /// Every observed word seen in training
+    pub fn observed_vocabulary(&self) -> HashSet<String> {
+        self.observed_probs.values()
+            .flat_map(|emissions| emissions.keys())
+            .flat_map(|observed| observed.split_whitespace())
+            .map(String::from)
+            .collect()
+    }
+
+    /// Every hidden state seen in training, excluding the start token
+    pub fn hidden_vocabulary(&self) -> HashSet<String> {
+        self.observed_probs.keys()
+            .flat_map(|hidden| hidden.split_whitespace())
+            .map(String::from)
+            .collect()
+    }
+
+    /// How often each observed word was seen in training, summed over hidden states
+    pub fn observed_frequencies(&self) -> HashMap<String, f64> {
+        let mut frequencies: HashMap<String, f64> = HashMap::new();
+        for emissions in self.observed_counts.values() {
+            for (observed, count) in emissions {
+                for word in observed.split_whitespace() {
+                    *frequencies.entry(word.to_owned()).or_insert(0.0) += count;
+                }
+            }
+        }
+        frequencies
+    }
+
+    /// The `n` hidden tags most likely to emit `observed`, with their emission
+    /// probability of it, most probable first
+    pub fn likely_tags(&self, observed: &str, n: usize) -> Vec<(String, f64)> {
+        let mut tags: HashMap<String, f64> = HashMap::new();
+        for (hidden, emissions) in &self.observed_probs {
+            for (observed_key, prob) in emissions {
+                // Pair up each observed and hidden token of the markov order token
+                for (word, tag) in observed_key.split_whitespace().zip(hidden.split_whitespace()) {
+                    if word != observed || tag == START_TOKEN { continue }
+                    let weight = tags.entry(tag.to_owned()).or_insert(0.0);
+                    *weight = weight.max(*prob);
+                }
+            }
+        }
+        let mut tags: Vec<(String, f64)> = tags.into_iter().collect();
+        tags.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        tags.truncate(n);
+        tags
+    }
+
+    /// Fraction of the emission mass of `hidden_state` kept by `constraint`,
+    /// i.e. the summed probability of the observed words that satisfy it
+    pub fn constraint_coverage(&self, hidden_state: &str, constraint: &dyn Constraint) -> f64 {
+        self.observed_probs.get(hidden_state)
+            .map(|emissions| emissions.iter()
+                .filter(|(observed, _)| constraint.is_satisfied_by_state(format_observed(observed, false)))
+                .map(|(_, prob)| prob)
+                .sum())
+            .unwrap_or(0.0)
+    }
+
+    /// Blends two models of the same markov order, weighting this model's
+    /// probabilities by `weight` and `other`'s by `1 - weight` (absent keys count as 0)
+    ///
+    /// The blend has no raw counts, so count based operations such as `prune_rare`
+    /// and `observed_frequencies` treat it like a model saved without them
+    pub fn interpolate(&self, other: &HiddenMarkov, weight: f64) -> Result<HiddenMarkov, ChmmError> {
+        if self.markov_order != other.markov_order {
+            return Err(ChmmError::MarkovOrderMismatch { expected: self.markov_order, found: other.markov_order });
+        }
+        let mut interpolated = HiddenMarkov::from_counts(
+            self.markov_order,
+            HiddenMarkov::interpolate_nested_map(&self.hidden_probs, &other.hidden_probs, weight),
+            HiddenMarkov::interpolate_nested_map(&self.observed_probs, &other.observed_probs, weight),
+        );
+        // `from_counts` kept the blended probabilities as if they were counts
+        interpolated.hidden_counts.clear();
+        interpolated.observed_counts.clear();
+        Ok(interpolated)
+    }
+
+    /// A model with this model's transitions and `other`'s emissions, e.g. to pair a
+    /// well-estimated transition model with a domain-specific emission model
+    ///
+    /// Both models must have the same markov order and hidden vocabulary
+    pub fn with_emissions_from(&self, other: &HiddenMarkov) -> Result<HiddenMarkov, ChmmError> {
+        if self.markov_order != other.markov_order {
+            return Err(ChmmError::MarkovOrderMismatch { expected: self.markov_order, found: other.markov_order });
+        }
+        let (vocabulary, other_vocabulary) = (self.hidden_vocabulary(), other.hidden_vocabulary());
+        if vocabulary != other_vocabulary {
+            let mut differences: Vec<&String> = vocabulary.symmetric_difference(&other_vocabulary).collect();
+            differences.sort();
+            return Err(ChmmError::InvalidArgument(format!("hidden vocabularies differ in {:?}", differences)));
+        }
+        Ok(HiddenMarkov {
+            observed_probs: other.observed_probs.clone(),
+            observed_counts: other.observed_counts.clone(),
+            skipped_tokens: vec![],
+            ..self.clone()
+        })
+    }
+
+    /// Every (context, next hidden state) transition whose probability differs between
+    /// the two models by more than `COMPARE_TOLERANCE`, with this model's probability
+    /// then `other`'s (absent transitions count as 0), sorted by context and next state
+    pub fn compare(&self, other: &HiddenMarkov) -> Result<Vec<(String, String, f64, f64)>, ChmmError> {
+        if self.markov_order != other.markov_order {
+            return Err(ChmmError::MarkovOrderMismatch { expected: self.markov_order, found: other.markov_order });
+        }
+        let mut transitions: HashSet<(&String, &String)> = HashSet::new();
+        for hidden_probs in [&self.hidden_probs, &other.hidden_probs] {
+            for (context, next_states) in hidden_probs {
+                transitions.extend(next_states.keys().map(|next| (context, next)));
+            }
+        }
+
+        let mut differences: Vec<(String, String, f64, f64)> = transitions.into_iter()
+            .map(|(context, next)| (context.to_owned(), next.to_owned(),
+                                    HiddenMarkov::get_prob(&self.hidden_probs, context, next),
+                                    HiddenMarkov::get_prob(&other.hidden_probs, context, next)))
+            .filter(|(_, _, prob, other_prob)| (prob - other_prob).abs() > COMPARE_TOLERANCE)
+            .collect();
+        differences.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+        Ok(differences)
+    }
+
+    fn interpolate_nested_map(map: &ProbMap<String, ProbMap<String, f64>>, other_map: &ProbMap<String, ProbMap<String, f64>>,
+                              weight: f64) -> ProbMap<String, ProbMap<String, f64>> {
+        let mut interpolated: ProbMap<String, ProbMap<String, f64>> = ProbMap::new();
+        for (source, source_weight) in [(map, weight), (other_map, 1.0 - weight)] {
+            if source_weight == 0.0 { continue }
+            for (outer_key, inner_map) in source {
+                let interpolated_inner = interpolated.entry(outer_key.to_owned()).or_default();
+                for (inner_key, value) in inner_map {
+                    *interpolated_inner.entry(inner_key.to_owned()).or_insert(0.0) += source_weight * value;
+                }
+            }
+        }
+        interpolated
+    }
+
+    /// Writes the trained model to `model_file` as JSON
+    pub fn save(&self, model_file: &str) -> Result<(), io::Error> {
+        let file = fs::File::create(model_file)?;
+        serde_json::to_writer(file, self)?;
+        Ok(())
+    }
+
+    /// Reads a model previously written by `save`
+    pub fn load(model_file: &str) -> Result<HiddenMarkov, io::Error> {
+        let file = fs::File::open(model_file)?;
+        Ok(serde_json::from_reader(io::BufReader::new(file))?)
+    }
+
+    /// True when no transitions were learned, e.g. from empty training data
+    pub fn is_empty(&self) -> bool {
+        self.hidden_probs.is_empty()
     }
 
     fn clear_probs(&mut self) {
         self.hidden_probs.clear();
         self.observed_probs.clear();
+        self.hidden_counts.clear();
+        self.observed_counts.clear();
+        self.skipped_tokens.clear();
+    }
+
+    fn process_line(&mut self, line_number: usize, line: &str, normalizer: &TokenNormalizer) {
+        // Tokens without a hidden state would otherwise train an empty hidden state
+        let line_tokens = match split_tokens(line) {
+            Ok(tokens) => tokens,
+            Err(ChmmError::UnclosedQuote { token, .. }) => {
+                self.skipped_tokens.push((line_number, token));
+                return
+            },
+            Err(_) => return,
+        };
+        // A skipped token ends the chain like a line break, so its neighbors are not linked
+        let mut chains = vec![vec![]];
+        for (index, token) in line_tokens.iter().enumerate() {
+            if let Some((observed, hidden)) = token.split_once(':') {
+                chains.last_mut().unwrap().push(format!("{}:{}", normalizer.normalize(observed), hidden));
+            } else {
+                self.skipped_tokens.push((line_number, index + 1));
+                chains.push(vec![]);
+            }
+        }
+        for chain in chains {
+            self.process_chain(&chain);
+        }
     }
 
-    fn process_line(&mut self, line: &str) {
-        let mut tokens = line.split_whitespace().peekable();
+    fn process_chain(&mut self, chain: &[String]) {
+        let mut tokens = chain.iter().map(String::as_str).peekable();
         let mut token = vec![];
         let mut curr_token = vec![];
         let mut is_first_token = true;
@@ -67,8 +442,9 @@ impl HiddenMarkov {
         let mut full_next_hidden = "".to_owned();
         let mut full_next_observed = "".to_owned();
         for (token, next_token) in tokens.iter().zip(next_tokens.iter()) {
-            let (_observed, hidden) = HiddenMarkov::split_token(token);
-            let (next_observed, next_hidden) = HiddenMarkov::split_token(next_token);
+            // Chains only hold tokens with a colon
+            let (_observed, hidden) = HiddenMarkov::split_token(token).unwrap_or_default();
+            let (next_observed, next_hidden) = HiddenMarkov::split_token(next_token).unwrap_or_default();
 
             full_hidden.push_str(hidden.as_str());
             full_hidden.push(' ');
@@ -85,35 +461,37 @@ impl HiddenMarkov {
     }
 
     fn increment_hidden(&mut self, hidden: String, next_hidden: String) {
-        let inner_hidden_map = self.hidden_probs.entry(hidden).or_insert(HashMap::new());
+        let inner_hidden_map = self.hidden_probs.entry(hidden).or_default();
         inner_hidden_map.entry(next_hidden.to_owned()).or_insert(0.0);
         inner_hidden_map.insert(next_hidden.to_owned(), inner_hidden_map[next_hidden.as_str()] + 1.0);
     }
 
     fn increment_observed(&mut self, hidden: String, observed: String) {
-        let inner_observed_map = self.observed_probs.entry(hidden).or_insert(HashMap::new());
+        let inner_observed_map = self.observed_probs.entry(hidden).or_default();
         inner_observed_map.entry(observed.to_owned()).or_insert(0.0);
         inner_observed_map.insert(observed.to_owned(), inner_observed_map[observed.as_str()] + 1.0);
     }
 
-    pub fn split_token(token: &str) -> (String, String) {
-        if token.eq(START_TOKEN) { return (String::from(START_TOKEN), String::from(START_TOKEN)) }
-        let mut token_split = token.split(":");
-        let observed = token_split.next().unwrap();
-        let hidden = token_split.next().unwrap();
-        (String::from(observed), String::from(hidden))
+    /// Splits an `observed:hidden` token at its first colon, or `None` if it has no colon
+    pub fn split_token(token: &str) -> Option<(String, String)> {
+        if token.eq(START_TOKEN) { return Some((String::from(START_TOKEN), String::from(START_TOKEN))) }
+        token.split_once(':').map(|(observed, hidden)| (String::from(observed), String::from(hidden)))
     }
 
     fn normalize(&mut self) {
+        // Keep the raw counts around before they are turned into probabilities
+        self.hidden_counts = self.hidden_probs.clone();
+        self.observed_counts = self.observed_probs.clone();
         // One way to save time would be to count sum during increments
         // into separate "normalize_sums" hashmap
         HiddenMarkov::normalize_nested_map(&mut self.hidden_probs);
         HiddenMarkov::normalize_nested_map(&mut self.observed_probs);
     }
 
-    fn normalize_nested_map(map: &mut HashMap<String, HashMap<String, f64>>) {
+    fn normalize_nested_map(map: &mut ProbMap<String, ProbMap<String, f64>>) {
         for (_, outer_map) in map.iter_mut() {
             let sum: f64 = outer_map.values().sum();
+            if sum == 0.0 { continue; }  // leave zero-sum states at 0.0 rather than NaN
             for (_, inner_map_val) in outer_map.iter_mut() {
                 *inner_map_val = *inner_map_val / sum;
                 // if *inner_map_val <= 0.00001 {
@@ -125,6 +503,7 @@ impl HiddenMarkov {
 
     pub fn sample_sequence(&self, length: i32) -> String {
         let mut sequence = String::from("");
+        if self.is_empty() { return sequence; }
         let mut start_string = "".to_owned();
         for _ in 0..self.markov_order {
             start_string.push_str(START_TOKEN);
@@ -142,7 +521,7 @@ impl HiddenMarkov {
             if self.observed_probs.contains_key(curr_hidden) {
                 let observed = HiddenMarkov::next_token(&self.observed_probs[curr_hidden]);
                 for (observed, hidden) in observed.split_whitespace().zip(curr_hidden.split_whitespace()) {
-                    sequence += format!("{}:{} ", observed, hidden).as_str();
+                    sequence += format!("{}:{} ", format_observed(observed, true), hidden).as_str();
                 }
                 sequence.pop();
                 if i != length - 1 { sequence += " " }
@@ -151,7 +530,7 @@ impl HiddenMarkov {
         return sequence;
     }
 
-    fn next_token(prev_token_map: &HashMap<String, f64>) -> &str {
+    fn next_token(prev_token_map: &ProbMap<String, f64>) -> &str {
         let mut sum = 0.0;
         let rand_value: f64 = rand::thread_rng().gen();
         for potential_token in prev_token_map {
@@ -163,38 +542,111 @@ impl HiddenMarkov {
         return ""
     }
 
-    pub fn get_sequence_probability(&self, sequence: &str) -> f64 {
-        let tokens = sequence.split_whitespace();
+    /// Most likely hidden tags for a sequence of observed words (Viterbi decoding),
+    /// or `None` if the words cannot be emitted in that order
+    ///
+    /// Words are decoded one markov order token at a time, so their number must be
+    /// a multiple of the markov order
+    pub fn viterbi_tags(&self, observed: &[&str]) -> Option<Vec<String>> {
+        let markov_order = self.markov_order as usize;
+        if observed.is_empty() || !observed.len().is_multiple_of(markov_order) { return None }
+        let start_state = vec![START_TOKEN; markov_order].join(" ");
+
+        // Best log probability of each hidden state and the path reaching it
+        let mut best: ProbMap<String, (f64, Vec<String>)> = ProbMap::from([(start_state, (0.0, vec![]))]);
+        for chunk in observed.chunks(markov_order) {
+            let observed_token = chunk.join(" ");
+            let mut next_best: ProbMap<String, (f64, Vec<String>)> = ProbMap::new();
+            for (prev_hidden, (log_prob, path)) in &best {
+                let transitions = match self.hidden_probs.get(prev_hidden) {
+                    Some(transitions) => transitions,
+                    None => continue,
+                };
+                for (hidden, transition_prob) in transitions {
+                    let emission_prob = HiddenMarkov::get_prob(&self.observed_probs, hidden, &observed_token);
+                    if *transition_prob == 0.0 || emission_prob == 0.0 { continue }
+                    let candidate = log_prob + transition_prob.ln() + emission_prob.ln();
+                    if next_best.get(hidden).is_none_or(|(current, _)| candidate > *current) {
+                        let mut next_path = path.clone();
+                        next_path.push(hidden.to_owned());
+                        next_best.insert(hidden.to_owned(), (candidate, next_path));
+                    }
+                }
+            }
+            if next_best.is_empty() { return None }
+            best = next_best;
+        }
+
+        let (_, path) = best.into_values().max_by(|a, b| a.0.total_cmp(&b.0).then_with(|| b.1.cmp(&a.1)))?;
+        Some(path.iter().flat_map(|hidden| hidden.split_whitespace()).map(String::from).collect())
+    }
+
+    /// Calculate the probability to generate a given sequence, or `None` if the model
+    /// is empty or the sequence has an unclosed quote or a token without a colon
+    pub fn get_sequence_probability(&self, sequence: &str) -> Option<f64> {
+        if self.is_empty() { return None; }
+        let tokens = split_tokens(sequence).ok()?;
         let mut product: f64 = 1.0;
         let mut curr_hidden = String::from(START_TOKEN);
 
         for token in tokens {
-            let (token_observed, token_hidden) = HiddenMarkov::split_token(token);
+            let (token_observed, token_hidden) = HiddenMarkov::split_token(&token)?;
 
-            product *= self.hidden_probs[&curr_hidden][&token_hidden];
-            product *= self.observed_probs[&token_hidden][&token_observed];
+            product *= HiddenMarkov::get_prob(&self.hidden_probs, &curr_hidden, &token_hidden);
+            product *= HiddenMarkov::get_prob(&self.observed_probs, &token_hidden, self.known_or_unknown(&token_observed));
             curr_hidden = token_hidden;
         }
 
-        return product;
+        Some(product)
+    }
+
+    /// Calculate the probability to generate a given sequence and then end, so that
+    /// over all complete sequences of any length the probabilities sum to 1
+    ///
+    /// Only meaningful for models trained with `new_with_end`; `None` if the model is empty
+    /// or has a markov order above 1, whose end tokens share a state with the last tokens
+    pub fn get_complete_sequence_probability(&self, sequence: &str) -> Option<f64> {
+        if self.markov_order > 1 { return None }
+        let product = self.get_sequence_probability(sequence)?;
+        let last_hidden = match split_tokens(sequence).ok()?.last() {
+            Some(token) => HiddenMarkov::split_token(token)?.1,
+            None => String::from(START_TOKEN),
+        };
+        Some(product * HiddenMarkov::get_prob(&self.hidden_probs, &last_hidden, END_TOKEN))
+    }
+
+    /// Maps words never seen in training to `UNKNOWN_TOKEN` when the model
+    /// was trained with unknown word folding
+    fn known_or_unknown<'a>(&self, observed: &'a str) -> &'a str {
+        let is_known = |word: &str| self.observed_probs.values().any(|emissions| emissions.contains_key(word));
+        if !is_known(observed) && is_known(UNKNOWN_TOKEN) { UNKNOWN_TOKEN } else { observed }
+    }
+
+    /// Probability stored in a nested map, 0.0 for unseen states
+    pub(crate) fn get_prob(map: &ProbMap<String, ProbMap<String, f64>>, outer_key: &str, inner_key: &str) -> f64 {
+        map.get(outer_key).and_then(|inner_map| inner_map.get(inner_key)).copied().unwrap_or(0.0)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::constraints::starts_with_letter_constraint::StartsWithLetterConstraint;
 
     #[test]
     fn create_hidden_markov() {
         let model = HiddenMarkov {
             markov_order: 1,
             hidden_probs: Default::default(),
-            observed_probs: Default::default()
+            observed_probs: Default::default(),
+            hidden_counts: Default::default(),
+            observed_counts: Default::default(),
+            skipped_tokens: Default::default(),
         };
 
         assert_eq!(1, model.markov_order);
-        assert_eq!(HashMap::default(), model.hidden_probs);
-        assert_eq!(HashMap::default(), model.observed_probs);
+        assert_eq!(ProbMap::default(), model.hidden_probs);
+        assert_eq!(ProbMap::default(), model.observed_probs);
     }
 
     #[test]
@@ -208,7 +660,10 @@ mod tests {
         let mut model = HiddenMarkov {
             markov_order: 1,
             hidden_probs: Default::default(),
-            observed_probs: Default::default()
+            observed_probs: Default::default(),
+            hidden_counts: Default::default(),
+            observed_counts: Default::default(),
+            skipped_tokens: Default::default(),
         };
         model.train(data);
 
@@ -262,31 +717,71 @@ mod tests {
             "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
         );
         let model = HiddenMarkov::new(1, data);
-        assert_eq!(0.0004999999999999999, model.get_sequence_probability("Ted:NNP sometimes:RB loves:VBZ Fred:NNP"))
+        assert_eq!(Some(0.0004999999999999999), model.get_sequence_probability("Ted:NNP sometimes:RB loves:VBZ Fred:NNP"))
+    }
+
+    #[test]
+    fn hidden_markov_unseen_sequence_probability() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN"
+        );
+        let model = HiddenMarkov::new(1, data);
+        assert_eq!(Some(0.0), model.get_sequence_probability("Ted:NNP green:NN"));
+        assert_eq!(Some(0.0), model.get_sequence_probability("Zed:NNP now:RB"));
+    }
+
+    #[test]
+    fn hidden_markov_malformed_sequence_probability() {
+        let model = HiddenMarkov::new(1, String::from("Ted:NNP now:RB likes:VBZ green:NN"));
+        assert_eq!(None, model.get_sequence_probability("Ted:NNP now likes:VBZ green:NN"));
+        assert_eq!(None, model.get_sequence_probability("Ted now likes green"));
+    }
+
+    #[test]
+    fn empty_corpus_hidden_markov() {
+        let model = HiddenMarkov::new(1, String::new());
+
+        assert!(model.is_empty());
+        assert_eq!(ProbMap::default(), model.hidden_probs);
+        assert_eq!(ProbMap::default(), model.observed_probs);
+        assert_eq!("", model.sample_sequence(4));
+        assert_eq!(None, model.get_sequence_probability("Ted:NNP now:RB"));
+    }
+
+    #[test]
+    fn whitespace_corpus_hidden_markov() {
+        let model = HiddenMarkov::new(2, String::from("  \n\t\n   \n"));
+
+        assert!(model.is_empty());
+        assert_eq!("", model.sample_sequence(4));
+        assert_eq!(None, model.get_sequence_probability("Ted:NNP now:RB"));
     }
 
     #[test]
     fn clear_hidden_markov() {
-        let mut inner_hidden_probs = HashMap::new();
+        let mut inner_hidden_probs = ProbMap::new();
         inner_hidden_probs.insert(String::from("VBZ"), 0.5);
-        let mut hidden_probs = HashMap::new();
+        let mut hidden_probs = ProbMap::new();
         hidden_probs.insert(String::from("NNP"), inner_hidden_probs);
 
-        let mut inner_observed_probs = HashMap::new();
+        let mut inner_observed_probs = ProbMap::new();
         inner_observed_probs.insert(String::from("loves"), 0.25);
-        let mut observed_probs = HashMap::new();
+        let mut observed_probs = ProbMap::new();
         observed_probs.insert(String::from("VBZ"), inner_observed_probs);
 
         let mut model = HiddenMarkov {
             markov_order: 1,
             hidden_probs,
             observed_probs,
+            hidden_counts: Default::default(),
+            observed_counts: Default::default(),
+            skipped_tokens: Default::default(),
         };
 
         model.clear_probs();
         assert_eq!(1, model.markov_order);
-        assert_eq!(HashMap::default(), model.hidden_probs);
-        assert_eq!(HashMap::default(), model.observed_probs);
+        assert_eq!(ProbMap::default(), model.hidden_probs);
+        assert_eq!(ProbMap::default(), model.observed_probs);
     }
 
     #[test]
@@ -294,7 +789,10 @@ mod tests {
         let mut model = HiddenMarkov {
             markov_order: 1,
             hidden_probs: Default::default(),
-            observed_probs: Default::default()
+            observed_probs: Default::default(),
+            hidden_counts: Default::default(),
+            observed_counts: Default::default(),
+            skipped_tokens: Default::default(),
         };
         model.increment_hidden(String::from("VBZ"), String::from("NN"));
         model.increment_hidden(String::from("VBZ"), String::from("NN"));
@@ -307,7 +805,10 @@ mod tests {
         let mut model = HiddenMarkov {
             markov_order: 1,
             hidden_probs: Default::default(),
-            observed_probs: Default::default()
+            observed_probs: Default::default(),
+            hidden_counts: Default::default(),
+            observed_counts: Default::default(),
+            skipped_tokens: Default::default(),
         };
         model.increment_observed(String::from("NN"), String::from("red"));
         model.increment_observed(String::from("NN"), String::from("red"));
@@ -321,7 +822,10 @@ mod tests {
         let mut model = HiddenMarkov {
             markov_order: 1,
             hidden_probs: Default::default(),
-            observed_probs: Default::default()
+            observed_probs: Default::default(),
+            hidden_counts: Default::default(),
+            observed_counts: Default::default(),
+            skipped_tokens: Default::default(),
         };
         model.increment(vec![START_TOKEN], vec![START_TOKEN]);
         model.increment(vec!["loves:VBZ"], vec!["red:NN"]);
@@ -334,35 +838,39 @@ mod tests {
 
     #[test]
     fn split_token_test() {
-        let (observed, hidden) = HiddenMarkov::split_token("Fred:NNP");
+        let (observed, hidden) = HiddenMarkov::split_token("Fred:NNP").unwrap();
         assert_eq!("Fred", observed);
         assert_eq!("NNP", hidden);
     }
 
     #[test]
     fn split_token_missing() {
-        let (observed, hidden) = HiddenMarkov::split_token("Fred:");
+        let (observed, hidden) = HiddenMarkov::split_token("Fred:").unwrap();
         assert_eq!("Fred", observed);
         assert_eq!("", hidden);
+        assert_eq!(None, HiddenMarkov::split_token("Fred"));
     }
 
     #[test]
     fn normalize_markov() {
-        let mut inner_hidden_probs = HashMap::new();
+        let mut inner_hidden_probs = ProbMap::new();
         inner_hidden_probs.insert(String::from("VBZ"), 2.0);
         inner_hidden_probs.insert(String::from("RB"), 3.0);
-        let mut hidden_probs = HashMap::new();
+        let mut hidden_probs = ProbMap::new();
         hidden_probs.insert(String::from("NNP"), inner_hidden_probs);
 
-        let mut inner_observed_probs = HashMap::new();
+        let mut inner_observed_probs = ProbMap::new();
         inner_observed_probs.insert(String::from("likes"), 2.0);
-        let mut observed_probs = HashMap::new();
+        let mut observed_probs = ProbMap::new();
         observed_probs.insert(String::from("VBZ"), inner_observed_probs);
 
         let mut model = HiddenMarkov {
             markov_order: 1,
             hidden_probs,
             observed_probs,
+            hidden_counts: Default::default(),
+            observed_counts: Default::default(),
+            skipped_tokens: Default::default(),
         };
 
         assert_eq!(3.0, model.hidden_probs["NNP"]["RB"]);
@@ -372,6 +880,41 @@ mod tests {
         assert_eq!(0.6, model.hidden_probs["NNP"]["RB"]);
         assert_eq!(0.4, model.hidden_probs["NNP"]["VBZ"]);
         assert_eq!(1.0, model.observed_probs["VBZ"]["likes"]);
+
+        let model = HiddenMarkov::new(1, String::from("Mary:NNP likes:VBZ\nTed:NNP \"New York:NNP red:NN"));
+        assert_eq!(vec![(2, 2)], model.skipped_tokens);
+        assert!(!model.observed_probs.contains_key("NN"));
+    }
+
+    #[test]
+    fn normalize_zero_sum_markov() {
+        let mut inner_hidden_probs = ProbMap::new();
+        inner_hidden_probs.insert(String::from("VBZ"), 0.0);
+        inner_hidden_probs.insert(String::from("RB"), 0.0);
+        let mut hidden_probs = ProbMap::new();
+        hidden_probs.insert(String::from("NNP"), inner_hidden_probs);
+
+        let mut inner_observed_probs = ProbMap::new();
+        inner_observed_probs.insert(String::from("likes"), 2.0);
+        let mut observed_probs = ProbMap::new();
+        observed_probs.insert(String::from("VBZ"), inner_observed_probs);
+        observed_probs.insert(String::from("NN"), ProbMap::new());
+
+        let mut model = HiddenMarkov {
+            markov_order: 1,
+            hidden_probs,
+            observed_probs,
+            hidden_counts: Default::default(),
+            observed_counts: Default::default(),
+            skipped_tokens: Default::default(),
+        };
+        model.normalize();
+
+        assert_eq!(0.0, model.hidden_probs["NNP"]["VBZ"]);
+        assert_eq!(0.0, model.hidden_probs["NNP"]["RB"]);
+        assert_eq!(1.0, model.observed_probs["VBZ"]["likes"]);
+        assert!(model.hidden_probs.values().chain(model.observed_probs.values())
+            .all(|inner_map| inner_map.values().all(|prob| !prob.is_nan())));
     }
 
     #[test]
@@ -382,7 +925,10 @@ mod tests {
         let mut model = HiddenMarkov {
             markov_order: 2,
             hidden_probs: Default::default(),
-            observed_probs: Default::default()
+            observed_probs: Default::default(),
+            hidden_counts: Default::default(),
+            observed_counts: Default::default(),
+            skipped_tokens: Default::default(),
         };
         model.train(data);
 
@@ -400,4 +946,330 @@ mod tests {
 
         assert_ne!(0, model.sample_sequence(4).len());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn from_counts_hidden_markov() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let counts = |entries: &[(&str, &str, f64)]| {
+            let mut map: ProbMap<String, ProbMap<String, f64>> = ProbMap::new();
+            for (outer, inner, count) in entries {
+                map.entry(String::from(*outer)).or_default().insert(String::from(*inner), *count);
+            }
+            map
+        };
+        let hidden_counts = counts(&[
+            (START_TOKEN, "NNP", 4.0),
+            ("NNP", "RB", 3.0), ("NNP", "VBZ", 2.0),
+            ("RB", "VBZ", 2.0),
+            ("VBZ", "NN", 3.0), ("VBZ", "NNP", 1.0),
+        ]);
+        let observed_counts = counts(&[
+            ("NNP", "Ted", 1.0), ("NNP", "Mary", 3.0), ("NNP", "Fred", 1.0),
+            ("RB", "now", 2.0), ("RB", "sometimes", 1.0),
+            ("VBZ", "likes", 2.0), ("VBZ", "loves", 1.0), ("VBZ", "sees", 1.0),
+            ("NN", "green", 1.0), ("NN", "red", 2.0),
+        ]);
+
+        let model = HiddenMarkov::from_counts(1, hidden_counts.clone(), observed_counts.clone());
+        let trained = HiddenMarkov::new(1, data);
+        assert_eq!(trained, model);
+        assert_eq!(hidden_counts, model.hidden_counts);
+        assert_eq!(observed_counts, model.observed_counts);
+    }
+
+    fn assert_probs_close(expected: &ProbMap<String, ProbMap<String, f64>>, actual: &ProbMap<String, ProbMap<String, f64>>) {
+        assert_eq!(expected.len(), actual.len());
+        for (outer_key, inner_map) in expected {
+            assert_eq!(inner_map.len(), actual[outer_key].len());
+            for (inner_key, prob) in inner_map {
+                assert!((prob - actual[outer_key][inner_key]).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn interpolate_hidden_markov() {
+        let model = HiddenMarkov::new(1, String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN"
+        ));
+        let other = HiddenMarkov::new(1, String::from("Fred:NNP sees:VBZ Mary:NNP sometimes:RB\nBob:NNP runs:VBZ"));
+
+        let with_itself = model.interpolate(&model, 0.3).unwrap();
+        assert_probs_close(&model.hidden_probs, &with_itself.hidden_probs);
+        assert_probs_close(&model.observed_probs, &with_itself.observed_probs);
+
+        let only_other = model.interpolate(&other, 0.0).unwrap();
+        assert_probs_close(&other.hidden_probs, &only_other.hidden_probs);
+        assert_probs_close(&other.observed_probs, &only_other.observed_probs);
+
+        let blended = model.interpolate(&other, 0.5).unwrap();
+        assert!((blended.observed_probs["NNP"]["Ted"] - 0.5 * model.observed_probs["NNP"]["Ted"]).abs() < 1e-12);
+        assert!((blended.observed_probs["NNP"]["Bob"] - 0.5 * other.observed_probs["NNP"]["Bob"]).abs() < 1e-12);
+
+        // Blended probabilities are not counts, so pruning by count leaves the blend as it is
+        assert!(blended.hidden_counts.is_empty() && blended.observed_counts.is_empty());
+        assert!(blended.observed_frequencies().is_empty());
+        let mut pruned = blended.clone();
+        pruned.prune_rare(2.0);
+        assert_eq!(blended, pruned);
+    }
+
+    #[test]
+    fn interpolate_order_mismatch_hidden_markov() {
+        let model = HiddenMarkov::new(1, String::from("Mary:NNP likes:VBZ red:NN"));
+        let other = HiddenMarkov::new(2, String::from("Mary:NNP likes:VBZ red:NN"));
+        assert_eq!(Err(ChmmError::MarkovOrderMismatch { expected: 1, found: 2 }), model.interpolate(&other, 0.5));
+    }
+
+    #[test]
+    fn with_emissions_from_hidden_markov() {
+        let transitions = HiddenMarkov::new(1, String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN"
+        ));
+        let emissions = HiddenMarkov::new(1, String::from("Alice:NNP quickly:RB parses:VBZ tokens:NN\nBob:NNP writes:VBZ code:NN"));
+        let combined = transitions.with_emissions_from(&emissions).unwrap();
+        assert_eq!(transitions.hidden_probs, combined.hidden_probs);
+        assert_eq!(transitions.hidden_counts, combined.hidden_counts);
+        assert_eq!(emissions.observed_probs, combined.observed_probs);
+        assert_ne!(emissions.hidden_probs, combined.hidden_probs);
+
+        let other_order = HiddenMarkov::new(2, String::from("Alice:NNP quickly:RB parses:VBZ tokens:NN"));
+        assert_eq!(Err(ChmmError::MarkovOrderMismatch { expected: 1, found: 2 }), transitions.with_emissions_from(&other_order));
+        let missing_tag = HiddenMarkov::new(1, String::from("Alice:NNP parses:VBZ tokens:NN"));
+        assert_eq!(Err(ChmmError::InvalidArgument(String::from("hidden vocabularies differ in [\"RB\"]"))),
+                   transitions.with_emissions_from(&missing_tag));
+    }
+
+    #[test]
+    fn train_from_reader_hidden_markov() {
+        let data = "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB";
+        for markov_order in 1..=2 {
+            let mut streamed = HiddenMarkov::new(markov_order, String::new());
+            streamed.train_from_reader(io::Cursor::new(data)).unwrap();
+            assert_eq!(HiddenMarkov::new(markov_order, String::from(data)), streamed);
+        }
+
+        let mut streamed = HiddenMarkov::new(1, String::new());
+        streamed.train_from_reader(io::Cursor::new("Mary:NNP likes\nTed:NNP")).unwrap();
+        assert_eq!(vec![(1, 2)], streamed.skipped_tokens);
+        assert!(streamed.train_from_reader(io::Cursor::new(vec![0xff, 0xfe])).is_err());
+    }
+
+    #[test]
+    fn fold_rare_observed_hidden_markov() {
+        let folded = HiddenMarkov::fold_rare_observed("Mary:NNP likes:VBZ red:NN\nMary:NNP sees:VBZ red:NN", 1);
+        assert_eq!(format!("Mary:NNP {}:VBZ red:NN\nMary:NNP {}:VBZ red:NN", UNKNOWN_TOKEN, UNKNOWN_TOKEN), folded);
+        let folded = HiddenMarkov::fold_rare_observed("Mary:NNP likes:VBZ red:NN\nMary:NNP sees:VBZ red:NN", 2);
+        assert_eq!(format!("{}:NNP {}:VBZ {}:NN\n{}:NNP {}:VBZ {}:NN",
+                           UNKNOWN_TOKEN, UNKNOWN_TOKEN, UNKNOWN_TOKEN, UNKNOWN_TOKEN, UNKNOWN_TOKEN, UNKNOWN_TOKEN), folded);
+    }
+
+    #[test]
+    fn unknown_word_probability_hidden_markov() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data.clone());
+        let unknown_model = HiddenMarkov::new_with_unknown(1, data, 1);
+
+        assert_eq!(Some(0.0), model.get_sequence_probability("Bob:NNP likes:VBZ red:NN"));
+        assert!(unknown_model.get_sequence_probability("Bob:NNP likes:VBZ red:NN").unwrap() > 0.0);
+        assert_eq!(0.4, unknown_model.observed_probs["NNP"][UNKNOWN_TOKEN]);
+        assert!(unknown_model.get_sequence_probability("Mary:NNP likes:VBZ red:NN").unwrap() > 0.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "markov_order must be >= 1")]
+    fn zero_order_hidden_markov() {
+        HiddenMarkov::new(0, String::from("Mary:NNP likes:VBZ red:NN"));
+    }
+
+    #[test]
+    #[should_panic(expected = "markov_order must be >= 1")]
+    fn zero_order_from_counts() {
+        HiddenMarkov::from_counts(0, ProbMap::new(), ProbMap::new());
+    }
+
+    #[test]
+    fn first_order_hidden_markov() {
+        let model = HiddenMarkov::new(1, String::from("Mary:NNP likes:VBZ red:NN"));
+        assert_eq!(1.0, model.hidden_probs[START_TOKEN]["NNP"]);
+    }
+
+    #[test]
+    fn vocabulary_hidden_markov() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        for markov_order in 1..=2 {
+            let model = HiddenMarkov::new(markov_order, data.clone());
+            let observed: HashSet<String> = ["Ted", "now", "likes", "green", "Mary", "red", "loves", "Fred", "sees", "sometimes"]
+                .iter().map(|word| String::from(*word)).collect();
+            let hidden: HashSet<String> = ["NNP", "RB", "VBZ", "NN"].iter().map(|state| String::from(*state)).collect();
+            assert_eq!(observed, model.observed_vocabulary());
+            assert_eq!(hidden, model.hidden_vocabulary());
+        }
+    }
+
+    #[test]
+    fn constraint_coverage_hidden_markov() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let constraint = StartsWithLetterConstraint::new('t');
+        assert_eq!(0.2, model.constraint_coverage("NNP", &constraint));
+        assert_eq!(0.0, model.constraint_coverage("VBZ", &constraint));
+        assert_eq!(0.0, model.constraint_coverage("JJ", &constraint));
+    }
+
+    #[test]
+    fn end_token_hidden_markov() {
+        let data = String::from("Mary:NNP runs:VBZ\nTed:NNP likes:VBZ red:NN\n");
+        let model = HiddenMarkov::new_with_end(1, data);
+        assert_eq!(0.5, model.hidden_probs["VBZ"][END_TOKEN]);
+        assert_eq!(1.0, model.hidden_probs["NN"][END_TOKEN]);
+        assert_eq!(1.0, model.hidden_probs[END_TOKEN][END_TOKEN]);
+        assert_eq!(1.0, model.observed_probs[END_TOKEN][END_TOKEN]);
+        assert!(!model.hidden_probs[START_TOKEN].contains_key(END_TOKEN));
+    }
+
+    #[test]
+    fn skip_malformed_tokens_hidden_markov() {
+        let model = HiddenMarkov::new(1, String::from("Mary:NNP likes:VBZ\nTed:NNP now likes:VBZ red:NN"));
+        assert_eq!(vec![(2, 2)], model.skipped_tokens);
+        assert!(!model.observed_probs.contains_key(""));
+        assert!(!model.hidden_probs.values().any(|transitions| transitions.contains_key("")));
+        // Ted:NNP and likes:VBZ are not linked across the skipped token; likes:VBZ starts a new chain
+        assert_eq!(1.0, model.hidden_counts["NNP"]["VBZ"]);
+        assert_eq!(2.0, model.hidden_counts[START_TOKEN]["NNP"]);
+        assert_eq!(1.0, model.hidden_counts[START_TOKEN]["VBZ"]);
+        assert_eq!(1.0, model.hidden_probs["VBZ"]["NN"]);
+        assert_eq!(1.0, model.observed_probs["VBZ"]["likes"]);
+    }
+
+    #[test]
+    fn prune_rare_hidden_markov() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let mut model = HiddenMarkov::new(1, data);
+        model.prune_rare(2.0);
+
+        // Mary was seen 3 times as NNP, Ted and Fred once
+        assert_eq!(1.0, model.observed_probs["NNP"]["Mary"]);
+        assert!(!model.observed_probs["NNP"].contains_key("Ted"));
+        assert!(!model.observed_probs["NNP"].contains_key("Fred"));
+        assert_eq!(1.0, model.observed_probs["NN"]["red"]);
+        assert_eq!(1.0, model.observed_probs["VBZ"]["likes"]);
+        for emissions in model.observed_probs.values().chain(model.hidden_probs.values()) {
+            assert!((emissions.values().sum::<f64>() - 1.0).abs() < 1e-9);
+        }
+        assert_eq!(2.0, model.observed_counts["VBZ"]["likes"]);
+    }
+
+    #[test]
+    fn compare_hidden_markov() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data.clone());
+        assert_eq!(Vec::<(String, String, f64, f64)>::new(), model.compare(&model.clone()).unwrap());
+
+        let mut modified = model.clone();
+        modified.hidden_probs.get_mut("RB").unwrap().insert(String::from("VBZ"), 0.5);
+        modified.hidden_probs.get_mut("RB").unwrap().insert(String::from("NN"), 0.5);
+        assert_eq!(vec![
+            (String::from("RB"), String::from("NN"), 0.0, 0.5),
+            (String::from("RB"), String::from("VBZ"), 1.0, 0.5),
+        ], model.compare(&modified).unwrap());
+
+        let other_order = HiddenMarkov::new(2, data);
+        assert_eq!(Some(ChmmError::MarkovOrderMismatch { expected: 1, found: 2 }), model.compare(&other_order).err());
+    }
+
+    #[test]
+    fn complete_sequence_probabilities_sum_to_one_hidden_markov() {
+        let model = HiddenMarkov::new_with_end(1, String::from("Mary:NNP runs:VBZ\nTed:NNP likes:VBZ red:NN"));
+        let mut sequences = vec![];
+        for name in ["Mary", "Ted"] {
+            for verb in ["runs", "likes"] {
+                sequences.push(format!("{}:NNP {}:VBZ", name, verb));
+                sequences.push(format!("{}:NNP {}:VBZ red:NN", name, verb));
+            }
+        }
+        let total: f64 = sequences.iter()
+            .map(|sequence| model.get_complete_sequence_probability(sequence).unwrap())
+            .sum();
+        assert!((total - 1.0).abs() < 1e-9);
+        assert_eq!(Some(0.0), model.get_complete_sequence_probability("Mary:NNP"));
+        assert_eq!(Some(0.0), model.get_complete_sequence_probability(""));
+
+        let second_order = HiddenMarkov::new_with_end(2, String::from("Mary:NNP runs:VBZ\nTed:NNP likes:VBZ red:NN"));
+        assert_eq!(None, second_order.get_complete_sequence_probability("Mary:NNP runs:VBZ"));
+    }
+
+    #[test]
+    fn likely_tags_hidden_markov() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB\nred:JJ"
+        );
+        let model = HiddenMarkov::new(1, data);
+        assert_eq!(vec![(String::from("NNP"), 0.6)], model.likely_tags("Mary", 3));
+        assert_eq!(vec![String::from("JJ"), String::from("NN")],
+                   model.likely_tags("red", 3).into_iter().map(|(tag, _)| tag).collect::<Vec<String>>());
+        assert_eq!(1, model.likely_tags("red", 1).len());
+        assert!(model.likely_tags("blue", 3).is_empty());
+    }
+
+    #[test]
+    fn viterbi_tags_hidden_markov() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data.clone());
+        assert_eq!(Some(vec![String::from("NNP"), String::from("RB"), String::from("VBZ"), String::from("NN")]),
+                   model.viterbi_tags(&["Ted", "now", "likes", "green"]));
+        assert_eq!(None, model.viterbi_tags(&["Ted", "now", "likes", "blue"]));
+        assert_eq!(None, model.viterbi_tags(&["now", "Ted"]));
+
+        let model = HiddenMarkov::new(2, data);
+        assert_eq!(Some(vec![String::from("NNP"), String::from("VBZ"), String::from("NNP"), String::from("RB")]),
+                   model.viterbi_tags(&["Fred", "sees", "Mary", "sometimes"]));
+        assert_eq!(None, model.viterbi_tags(&["Fred", "sees", "Mary"]));
+    }
+
+    #[test]
+    fn normalized_tokens_hidden_markov() {
+        let data = String::from("Mary:NNP likes:VBZ Red,:NN\nMary:NNP likes:VBZ red:NN\nTed:NNP sees:VBZ 1999:CD\nTed:NNP sees:VBZ 2024:CD");
+        let normalizer = TokenNormalizer { lowercase: true, strip_punctuation: true, collapse_digits: true };
+        let model = HiddenMarkov::new_with_normalizer(1, data.clone(), normalizer);
+        assert_eq!(Some(&1.0), model.observed_probs["NN"].get("red"));
+        assert_eq!(Some(&1.0), model.observed_probs["CD"].get("0000"));
+        assert!(model.observed_probs["NNP"].contains_key("mary"));
+
+        let model = HiddenMarkov::new(1, data);
+        assert_eq!(Some(&0.5), model.observed_probs["NN"].get("Red,"));
+        assert_eq!(",", TokenNormalizer { strip_punctuation: true, ..Default::default() }.normalize(","));
+    }
+
+    #[test]
+    fn save_load_hidden_markov() {
+        let data = String::from("Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN");
+        let model = HiddenMarkov::new(2, data);
+        let model_file = std::env::temp_dir().join(format!("chmm_model_{}.json", std::process::id()));
+        let model_file = model_file.to_str().unwrap();
+
+        model.save(model_file).unwrap();
+        let loaded = HiddenMarkov::load(model_file).unwrap();
+        fs::remove_file(model_file).unwrap();
+        assert_eq!(model, loaded);
+    }
+
+    #[test]
+    fn load_missing_hidden_markov() {
+        assert!(HiddenMarkov::load("data/does_not_exist.json").is_err());
+    }
+}
@@ -0,0 +1,23 @@
+#![allow(dead_code)]
+
+//! Constrained hidden Markov models trained on `observed:hidden` token sequences
+//!
+//! `benchmark` times training and sampling for use from other benchmarking code
+
+pub use crate::constrained_hidden_markov::ConstrainedHiddenMarkov;
+pub use crate::hidden_markov::HiddenMarkov;
+pub use crate::time_analysis::{benchmark, benchmark_selective_pruning, BenchmarkResult};
+use crate::constraints::Constraint;
+use crate::constraints::matches_constraint::MatchesConstraint;
+use crate::constraints::empty_constraint::EmptyConstraint;
+use crate::constraints::multi_constraint::MultiConstraint;
+use crate::constraints::starts_with_letter_constraint::StartsWithLetterConstraint;
+
+pub mod constrained_hidden_markov;
+pub mod hidden_markov;
+pub mod backoff_hidden_markov;
+pub mod utils;
+pub mod constraints;
+mod time_analysis;
+pub mod error;
+mod mixed_order;
@@ -2,61 +2,287 @@
 
 extern crate core;
 
-use crate::constrained_hidden_markov::ConstrainedHiddenMarkov;
-use crate::hidden_markov::HiddenMarkov;
-use crate::console::{Args};
+use constrained_hmm::{constraints, error, utils, ConstrainedHiddenMarkov, HiddenMarkov};
+use crate::console::{Args, Command, Verbosity};
 use crate::constraints::Constraint;
 use crate::constraints::matches_constraint::MatchesConstraint;
 use crate::constraints::empty_constraint::EmptyConstraint;
-use crate::constraints::multi_constraint::MultiConstraint;
 use crate::constraints::starts_with_letter_constraint::StartsWithLetterConstraint;
 use std::time::Instant;
-use crate::constraint_parser::parse_constraint;
-use crate::utils::{get_data, print_sequences, write_sequences};
+use std::{io, process};
+use crate::config::Config;
+use crate::constraint_parser::{fit_constraints_to_length, parse_constraint};
+use crate::utils::{corpus_stats, get_data, get_data_dir, print_sequences, print_sequences_json, write_sequences, write_sequences_checkpointed, write_sequences_json, CORPUS_EXTENSION};
 
 mod console;
-mod constrained_hidden_markov;
-mod hidden_markov;
-mod utils;
-mod constraints;
-mod time_analysis;
 mod constraint_parser;
 mod config;
 
+/// Number of generated sequences between progress updates
+const PROGRESS_INTERVAL: u32 = 1000;
+
 fn main() {
     let args = Args::new();
+    if args.validate {
+        validate(&args.config_file);
+    }
+    match args.command {
+        Command::Run => run(args),
+        Command::Train => train(args),
+        Command::Generate => generate(args),
+        Command::Stats => stats(args),
+    }
+}
+
+fn run(args: Args) {
+    let data = read_training_data(&args);
+    let model = build_model(data, args.markov_order, args.verbosity);
+    constrain_and_generate(model, args);
+}
+
+fn train(args: Args) {
+    let data = read_training_data(&args);
+    let model = build_model(data, args.markov_order, args.verbosity);
+    model.save(&args.model_file).unwrap_or_else(|e| {
+        eprintln!("Unable to write model to {}: {}", args.model_file, e);
+        process::exit(1)
+    });
+}
 
-    let data = get_data(args.training_file);
-    let (hidden_constraints, observed_constraints) = parse_constraint(args.constraint_string);
+fn generate(args: Args) {
+    let model = HiddenMarkov::load(&args.model_file).unwrap_or_else(|e| {
+        eprintln!("Unable to read model from {}: {}", args.model_file, e);
+        process::exit(1)
+    });
+    constrain_and_generate(model, args);
+}
 
-    let constrained_model = train_model(data, args.markov_order, hidden_constraints, observed_constraints);
-    let sequences = generate_sequences(&constrained_model, args.num_of_sequences);
+fn stats(args: Args) {
+    let stats = corpus_stats(&read_training_data(&args));
+    println!("Lines: {}\nTokens: {}\nObserved words: {}\nHidden tags: {}\nMalformed tokens: {}",
+             stats.line_count, stats.token_count, stats.observed_words, stats.hidden_tags, stats.malformed_tokens.len());
+    for token in stats.malformed_tokens {
+        println!("    {}", token);
+    }
+}
+
+fn validate(config_file: &str) -> ! {
+    match validate_pipeline(config_file) {
+        Ok(()) => {
+            println!("{} is valid", config_file);
+            process::exit(0)
+        },
+        Err(problems) => {
+            for problem in problems {
+                eprintln!("{}", problem);
+            }
+            process::exit(1)
+        },
+    }
+}
 
-    if args.output_file.is_empty() {
-        print_sequences(sequences);
+/// Runs every step up to generation for `config_path` (loading the config and training
+/// data, parsing the constraints, training the model and checking that some sequence
+/// satisfies the constraints), collecting each problem found instead of stopping at the first
+fn validate_pipeline(config_path: &str) -> Result<(), Vec<String>> {
+    let config = Config::parse(&config_path.to_string()).map_err(|e| vec![e.to_string()])?;
+    let mut problems = vec![];
+
+    let markov_order = match config.markov_order.trim().parse::<u32>() {
+        Ok(order) if order > 0 => Some(order),
+        _ => {
+            problems.push(format!("markov_order must be a positive integer, got \"{}\"", config.markov_order));
+            None
+        },
+    };
+    let data = get_data(&config.training_file)
+        .map_err(|e| problems.push(format!("unable to read training data from {}: {}", config.training_file, e)))
+        .ok();
+    let sequence_length = config.sequence_length;
+    let constraints = parse_constraint(config.constraints)
+        .and_then(|(hidden_constraints, observed_constraints)|
+            fit_constraints_to_length(hidden_constraints, observed_constraints, sequence_length))
+        .map_err(|e| problems.push(format!("invalid constraints: {}", e)))
+        .ok();
+
+    if let (Some(markov_order), Some(data), Some((hidden_constraints, observed_constraints))) = (markov_order, data, constraints) {
+        let length = hidden_constraints.len();
+        if length < markov_order as usize {
+            problems.push(format!("sequence length {} is shorter than the markov order {}", length, markov_order));
+        } else {
+            let model = HiddenMarkov::new(markov_order, data);
+            let mut constrained_model = ConstrainedHiddenMarkov::new(model, length, Some(hidden_constraints), Some(observed_constraints));
+            constrained_model.train();
+            if constrained_model.surviving_probability_mass() == 0.0 {
+                problems.push(String::from("constraints are unsatisfiable: no sequence in the training data's model satisfies them"));
+            }
+        }
+    }
+
+    if problems.is_empty() { Ok(()) } else { Err(problems) }
+}
+
+fn read_training_data(args: &Args) -> String {
+    if args.training_dir.is_empty() {
+        get_data(&args.training_file).unwrap_or_else(|e| exit_with_read_error(&args.training_file, e))
     } else {
-        write_sequences(sequences, args.output_file);
+        get_data_dir(&args.training_dir, CORPUS_EXTENSION).unwrap_or_else(|e| exit_with_read_error(&args.training_dir, e))
+    }
+}
+
+fn constrain_and_generate(model: HiddenMarkov, args: Args) {
+    if args.verbosity >= Verbosity::Verbose {
+        eprintln!("Markov order: {}\nConstraints: {}", model.markov_order, args.constraint_string);
+    }
+    let (hidden_constraints, observed_constraints) = parse_constraint(args.constraint_string.clone())
+        .and_then(|(hidden_constraints, observed_constraints)|
+            fit_constraints_to_length(hidden_constraints, observed_constraints, args.sequence_length))
+        .unwrap_or_else(|e| {
+            eprintln!("Invalid constraints: {}", e);
+            process::exit(1)
+        });
+
+    let constrained_model = train_model(model, hidden_constraints, observed_constraints, args.verbosity);
+    if let Some(every) = args.checkpoint_every {
+        let generated = write_sequences_checkpointed(&args.output_file, args.num_of_sequences as usize, every, args.resume,
+                                                     |count| constrained_model.sample_n(count)).unwrap_or_else(|e| {
+            eprintln!("Unable to write sequences to {}: {}", args.output_file, e);
+            process::exit(1)
+        });
+        if args.verbosity >= Verbosity::Normal {
+            eprintln!("Generated {} sequences", generated);
+        }
+        return;
+    }
+    let sequences = generate_sequences(&constrained_model, args.num_of_sequences, args.verbosity);
+
+    match (args.output_file.is_empty(), args.output_format.as_str()) {
+        (true, "json") => print_sequences_json(sequences),
+        (true, _) => print_sequences(sequences),
+        (false, "json") => write_sequences_json(sequences, args.output_file).expect("Unable to write"),
+        (false, _) => write_sequences(sequences, args.output_file),
     }
 }
 
-fn train_model(data: String, markov_order: u32, hidden_constraints: Vec<Box<dyn Constraint + Send>>, observed_constraints: Vec<Box<dyn Constraint + Send>>) -> ConstrainedHiddenMarkov {
+fn exit_with_read_error(path: &str, error: io::Error) -> ! {
+    eprintln!("Unable to read training data from {}: {}", path, error);
+    process::exit(1)
+}
+
+fn build_model(data: String, markov_order: u32, verbosity: Verbosity) -> HiddenMarkov {
     let start = Instant::now();
-    println!("Data length: {}\nSequence length: {}", data.len(), hidden_constraints.len());
+    if verbosity >= Verbosity::Normal {
+        eprintln!("Data length: {}", data.len());
+    }
     let model = HiddenMarkov::new(markov_order, data);
-    let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), hidden_constraints.len(),
+    if verbosity >= Verbosity::Normal && !model.skipped_tokens.is_empty() {
+        let (line, token) = model.skipped_tokens[0];
+        eprintln!("Warning: skipped {} tokens missing an observed:hidden colon or opening an unclosed quote (first at line {}, token {})",
+                  model.skipped_tokens.len(), line, token);
+    }
+    if verbosity >= Verbosity::Normal {
+        eprintln!("Model training time elapsed: {:.2?}", start.elapsed());
+    }
+    model
+}
+
+fn train_model(model: HiddenMarkov, hidden_constraints: Vec<Box<dyn Constraint + Send>>, observed_constraints: Vec<Box<dyn Constraint + Send>>,
+               verbosity: Verbosity) -> ConstrainedHiddenMarkov {
+    let start = Instant::now();
+    if verbosity >= Verbosity::Normal {
+        eprintln!("Sequence length: {}", hidden_constraints.len());
+    }
+    let mut constrained_model = ConstrainedHiddenMarkov::new(model, hidden_constraints.len(),
                                                              Some(hidden_constraints), Some(observed_constraints));
     constrained_model.train();
-    println!("Training time elapsed: {:.2?}", start.elapsed());
+    if verbosity >= Verbosity::Normal {
+        eprintln!("Training time elapsed: {:.2?}", start.elapsed());
+    }
     return constrained_model
 }
 
-fn generate_sequences(constrained_model: &ConstrainedHiddenMarkov, n: u32) -> Vec<String> {
+fn generate_sequences(constrained_model: &ConstrainedHiddenMarkov, n: u32, verbosity: Verbosity) -> Vec<String> {
     let start = Instant::now();
-    let mut sequences = vec![];
-    for _ in 0..n {
-        sequences.push(constrained_model.sample_sequence(true));
+    let show_progress = verbosity >= Verbosity::Normal && n >= PROGRESS_INTERVAL;
+    let sequences = if show_progress {
+        constrained_model.sample_n_with_progress(n as usize, PROGRESS_INTERVAL as usize, |count, elapsed| {
+            eprint!("\rGenerated {}/{} sequences ({:.2?})", count, n, elapsed);
+        })
+    } else {
+        constrained_model.sample_n(n as usize)
+    };
+    if show_progress {
+        eprintln!();
     }
     let elapsed = start.elapsed();
-    println!("Generation time Elapsed: {:.2?}", elapsed);
+    if verbosity >= Verbosity::Normal {
+        eprintln!("Generation time Elapsed: {:.2?}", elapsed);
+    }
     return sequences
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn write_config(name: &str, contents: &str) -> String {
+        let config_file = std::env::temp_dir().join(format!("chmm_validate_{}_{}.yaml", name, std::process::id()));
+        fs::write(&config_file, contents).unwrap();
+        config_file.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn validate_valid_config() {
+        assert_eq!(Ok(()), validate_pipeline("config.yaml"));
+    }
+
+    #[test]
+    fn validate_unsatisfiable_config() {
+        let config_file = write_config("unsatisfiable", "training_file : \"data/small_test.txt\"\nmarkov_order : 1\nconstraints : \"purple:NC\\nNC:NC\"\n");
+        let result = validate_pipeline(&config_file);
+        fs::remove_file(&config_file).unwrap();
+        let problems = result.unwrap_err();
+        assert_eq!(1, problems.len());
+        assert!(problems[0].starts_with("constraints are unsatisfiable"));
+    }
+
+    #[test]
+    fn validate_collects_problems() {
+        let config_file = write_config("problems", "training_file : \"data/does_not_exist.txt\"\nmarkov_order : 0\n\
+                                                   constraints : \"NC:NC\\nSW(t)\"\n");
+        let result = validate_pipeline(&config_file);
+        fs::remove_file(&config_file).unwrap();
+        let problems = result.unwrap_err();
+        assert_eq!(3, problems.len());
+        assert!(problems[1].starts_with("unable to read training data from data/does_not_exist.txt"));
+        assert_eq!("invalid constraints: line 2: expected ':' separator in 'SW(t)'", problems[2]);
+
+        let config_file = write_config("mismatch", "training_file : \"data/small_test.txt\"\nmarkov_order : 1\n\
+                                                   constraints : \"NC:NC\"\nsequence_length : 3\n");
+        let result = validate_pipeline(&config_file);
+        fs::remove_file(&config_file).unwrap();
+        assert_eq!(Err(vec![String::from("invalid constraints: sequence length 3 does not match the 1 constraints given")]), result);
+        assert!(validate_pipeline("data/does_not_exist.yaml").is_err());
+    }
+
+    #[test]
+    fn generate_loads_trained_model() {
+        let temp_dir = std::env::temp_dir();
+        let model_file = temp_dir.join(format!("chmm_cli_model_{}.json", std::process::id()));
+        let output_file = temp_dir.join(format!("chmm_cli_sequences_{}.txt", std::process::id()));
+        let model_file = model_file.to_str().unwrap();
+        let output_file = output_file.to_str().unwrap();
+
+        train(Args::parse_from(["constrained_hmm", "train", "--model", model_file, "-f", "data/small_test.txt", "-q"]).unwrap());
+        generate(Args::parse_from(["constrained_hmm", "generate", "--model", model_file, "-n", "3", "-o", output_file, "-q"]).unwrap());
+
+        let sequences = fs::read_to_string(output_file).unwrap();
+        fs::remove_file(model_file).unwrap();
+        fs::remove_file(output_file).unwrap();
+        assert_eq!(3, sequences.lines().count());
+        for sequence in sequences.lines() {
+            assert!(sequence.ends_with("red:NN"));
+        }
+    }
+}
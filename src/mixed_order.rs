@@ -0,0 +1,140 @@
+use crate::error::ChmmError;
+use crate::hidden_markov::HiddenMarkov;
+use crate::utils::{ProbMap, START_TOKEN};
+
+/// Token-level transition tables of every order up to a model's markov order,
+/// used to sample and score with a different order at each sequence position
+///
+/// The tables are derived from the raw counts of a `HiddenMarkov` trained at the
+/// maximum order used: each trained (context, next) chunk pair is unrolled into
+/// token-level transitions from every shorter context. Contexts are the previous
+/// hidden states, oldest first and padded with `START_TOKEN`, as in `BackoffHiddenMarkov`
+#[derive(Debug, Clone, PartialEq)]
+pub struct MixedOrderModel {
+    /// Markov order used at each sequence position
+    pub orders: Vec<usize>,
+    /// `transitions[k - 1]` maps the previous k hidden states to next state probabilities
+    pub transitions: Vec<ProbMap<String, ProbMap<String, f64>>>,
+    /// Emission probabilities of single hidden states
+    pub emissions: ProbMap<String, ProbMap<String, f64>>,
+}
+
+impl MixedOrderModel {
+    /// Every order must be between 1 and `model.markov_order`, and `model` must
+    /// still have its raw counts (i.e. not be an old model file saved without them)
+    pub fn new(model: &HiddenMarkov, orders: Vec<usize>) -> Result<MixedOrderModel, ChmmError> {
+        let max_order = model.markov_order as usize;
+        if let Some(order) = orders.iter().find(|order| **order == 0 || **order > max_order) {
+            return Err(ChmmError::InvalidArgument(
+                format!("position order {} must be between 1 and the model's markov order {}", order, max_order)));
+        }
+        if model.hidden_counts.is_empty() {
+            return Err(ChmmError::InvalidArgument(String::from("mixed orders need a model with raw training counts")));
+        }
+
+        let mut transitions: Vec<ProbMap<String, ProbMap<String, f64>>> = vec![ProbMap::new(); max_order];
+        for (context, next_counts) in &model.hidden_counts {
+            for (next, count) in next_counts {
+                let window: Vec<&str> = context.split_whitespace().chain(next.split_whitespace()).collect();
+                let context_length = window.len() - next.split_whitespace().count();
+                for j in context_length..window.len() {
+                    for order in 1..=max_order.min(j) {
+                        *transitions[order - 1].entry(window[j - order..j].join(" ")).or_default()
+                            .entry(window[j].to_owned()).or_insert(0.0) += count;
+                    }
+                }
+            }
+        }
+
+        let mut emissions: ProbMap<String, ProbMap<String, f64>> = ProbMap::new();
+        for (hidden, observed_counts) in &model.observed_counts {
+            for (observed, count) in observed_counts {
+                for (word, tag) in observed.split_whitespace().zip(hidden.split_whitespace()) {
+                    *emissions.entry(tag.to_owned()).or_default().entry(word.to_owned()).or_insert(0.0) += count;
+                }
+            }
+        }
+
+        transitions.iter_mut().for_each(normalize_rows);
+        normalize_rows(&mut emissions);
+        Ok(MixedOrderModel { orders, transitions, emissions })
+    }
+
+    /// The context key at `position` given the hidden states before it: the last
+    /// `orders[position]` of them, padded with `START_TOKEN` near the start
+    pub fn context_key(&self, position: usize, previous_hidden: &[String]) -> String {
+        let order = self.orders[position];
+        let padding = order.saturating_sub(previous_hidden.len());
+        let mut context = vec![START_TOKEN; padding];
+        context.extend(previous_hidden[previous_hidden.len() + padding - order..].iter().map(String::as_str));
+        context.join(" ")
+    }
+
+    /// Next hidden state probabilities at `position` following `previous_hidden`
+    pub fn transitions_at(&self, position: usize, previous_hidden: &[String]) -> Option<&ProbMap<String, f64>> {
+        self.transitions[self.orders[position] - 1].get(&self.context_key(position, previous_hidden))
+    }
+
+    /// Probability of a sequence of (observed, hidden) tokens, one per position
+    pub fn sequence_probability(&self, tokens: &[(String, String)]) -> f64 {
+        let mut previous_hidden: Vec<String> = vec![];
+        let mut product = 1.0;
+        for (position, (observed, hidden)) in tokens.iter().enumerate() {
+            product *= self.transitions_at(position, &previous_hidden).and_then(|next| next.get(hidden)).unwrap_or(&0.0);
+            product *= self.emissions.get(hidden).and_then(|emissions| emissions.get(observed)).unwrap_or(&0.0);
+            previous_hidden.push(hidden.to_owned());
+        }
+        product
+    }
+}
+
+fn normalize_rows(matrix: &mut ProbMap<String, ProbMap<String, f64>>) {
+    for row in matrix.values_mut() {
+        let sum: f64 = row.values().sum();
+        row.values_mut().for_each(|value| *value /= sum);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_model() -> HiddenMarkov {
+        HiddenMarkov::new(2, String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        ))
+    }
+
+    #[test]
+    fn mixed_order_context_keys() {
+        let model = MixedOrderModel::new(&test_model(), vec![1, 2, 2, 1]).unwrap();
+        let history: Vec<String> = vec![String::from("NNP"), String::from("RB"), String::from("VBZ")];
+        assert_eq!(START_TOKEN, model.context_key(0, &[]));
+        assert_eq!(format!("{} NNP", START_TOKEN), model.context_key(1, &history[..1]));
+        assert_eq!("NNP RB", model.context_key(2, &history[..2]));
+        assert_eq!("VBZ", model.context_key(3, &history));
+    }
+
+    #[test]
+    fn mixed_order_transitions() {
+        let model = MixedOrderModel::new(&test_model(), vec![1, 2, 2, 1]).unwrap();
+        // Chunk pairs unroll into token transitions within and across chunks
+        assert_eq!(1.0, model.transitions[0][START_TOKEN]["NNP"]);
+        assert_eq!(1.0, model.transitions[1]["NNP RB"]["VBZ"]);
+        assert!((2.0 / 3.0 - model.transitions[0]["VBZ"]["NN"]).abs() < 1e-12);
+        assert_eq!(0.5, model.emissions["NN"]["red"]);
+        let tokens: Vec<(String, String)> = [("Ted", "NNP"), ("now", "RB"), ("likes", "VBZ"), ("red", "NN")].iter()
+            .map(|(observed, hidden)| (observed.to_string(), hidden.to_string()))
+            .collect();
+        assert!(model.sequence_probability(&tokens) > 0.0);
+    }
+
+    #[test]
+    fn invalid_mixed_orders() {
+        assert!(MixedOrderModel::new(&test_model(), vec![1, 3]).is_err());
+        assert!(MixedOrderModel::new(&test_model(), vec![0, 1]).is_err());
+        let mut model = test_model();
+        model.hidden_counts.clear();
+        assert!(MixedOrderModel::new(&model, vec![1, 2]).is_err());
+    }
+}
@@ -1,127 +1,155 @@
 use std::fs;
 use std::time::{Duration, Instant};
-use crate::{ConstrainedHiddenMarkov, HiddenMarkov};
+use crate::{ConstrainedHiddenMarkov, Constraint, EmptyConstraint, HiddenMarkov, MatchesConstraint};
 
-fn time_analysis_alphabet_size() {
-    // Make sure the strings are equal length even if there aren't a lot of unique
-    // tokens, they should still be like 000000001 or something
-    //
-    let avg_count = 5;
-    let avg_gen_count = 10;
-    let mut data_str:String = String::from("alphabet_size,average_train_time,average_gen_time\n");
-
-    for alphabet_size in 5..=100 {
-        let mut avg_train_sum = Duration::new(0, 0);
-        let mut avg_gen_sum = Duration::new(0, 0);
-        for _ in 0..avg_count {
-
-            // Make arbitrary database
-            let mut data:String = "".to_string();
-            for i in 0..alphabet_size {
-                for j in 0..alphabet_size {
-                    data.push_str(&*format!("{:04}:{:04} ", i, j));
-                }
-                data.push('\n');
-            }
-            // println!("DATA:\n{}", data);
-
-            // Time Markov model training
-            let start = Instant::now();
-            let model = HiddenMarkov::new(2, data);
-            let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 10,
-                                                                     None, None);
-            constrained_model.train();
-            avg_train_sum += start.elapsed();
+/// Average training and generation times measured by `benchmark`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BenchmarkResult {
+    pub average_train_time: Duration,
+    pub average_gen_time: Duration,
+}
 
-        }
+/// Times training and sampling an unconstrained model on an arbitrary corpus of
+/// `alphabet_size` distinct tokens, averaging each over `repeats` runs
+///
+/// An `alphabet_size` or `repeats` of 0 is treated as 1
+pub fn benchmark(alphabet_size: usize, sequence_length: usize, markov_order: u32, repeats: usize) -> BenchmarkResult {
+    let alphabet_size = alphabet_size.max(1);
+    let repeats = repeats.max(1);
+    let mut train_sum = Duration::new(0, 0);
+    let mut gen_sum = Duration::new(0, 0);
 
-        // Make arbitrary database
-        let mut data:String = "".to_string();
-        for i in 0..alphabet_size {
-            for j in 0..alphabet_size {
-                data.push_str(&*format!("{:04}:{:04} ", i, j));
-            }
-            data.push('\n');
-        }
-        data.push_str(&*format!("{:04}:{:04} ", 0, alphabet_size-1));
-        data.push_str(&*format!("{:04}:{:04} ", 0, 0));
-        data.push('\n');
+    for _ in 0..repeats {
+        let data = benchmark_data(alphabet_size, false);
 
         // Time Markov model training
-        let model = HiddenMarkov::new(1, data);
-        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), 10,
+        let start = Instant::now();
+        let model = HiddenMarkov::new(markov_order, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, sequence_length,
                                                                  None, None);
         constrained_model.train();
+        train_sum += start.elapsed();
+    }
 
-        for _ in 0..avg_gen_count {
-            let start = Instant::now();
-            let _str = constrained_model.sample_sequence(false);
-            avg_gen_sum += start.elapsed();
-            // println!("Gen: {}", _str);
+    // The closing line links the last token back to the first so long sequences can be sampled
+    let model = HiddenMarkov::new(markov_order, benchmark_data(alphabet_size, true));
+    let mut constrained_model = ConstrainedHiddenMarkov::new(model, sequence_length,
+                                                             None, None);
+    constrained_model.train();
+
+    for _ in 0..repeats {
+        let start = Instant::now();
+        let _str = constrained_model.sample_sequence(false);
+        gen_sum += start.elapsed();
+    }
+
+    BenchmarkResult {
+        average_train_time: train_sum / repeats as u32,
+        average_gen_time: gen_sum / repeats as u32,
+    }
+}
+
+fn benchmark_data(alphabet_size: usize, closed: bool) -> String {
+    // Make sure the strings are equal length even if there aren't a lot of unique
+    // tokens, they should still be like 000000001 or something
+    let mut data:String = "".to_string();
+    for i in 0..alphabet_size {
+        for j in 0..alphabet_size {
+            data.push_str(&format!("{:04}:{:04} ", i, j));
         }
+        data.push('\n');
+    }
+    if closed {
+        data.push_str(&format!("{:04}:{:04} ", 0, alphabet_size-1));
+        data.push_str(&format!("{:04}:{:04} ", 0, 0));
+        data.push('\n');
+    }
+    data
+}
 
-        println!("Alphabet size: {}  total elapsed time: {:.3?}   average train time: {:.3?} average gen time: {:.3?}", alphabet_size, avg_train_sum, avg_train_sum /avg_count, avg_gen_sum/avg_gen_count);
-        data_str.push_str(&*format!("{},{:.3?},{:.3?}\n", alphabet_size, avg_train_sum / avg_count, avg_gen_sum / avg_gen_count));
+fn time_analysis_alphabet_size() {
+    let avg_count = 5;
+    let mut data_str:String = String::from("alphabet_size,average_train_time,average_gen_time\n");
+
+    for alphabet_size in 5..=100 {
+        let result = benchmark(alphabet_size, 10, 1, avg_count);
+        println!("Alphabet size: {}  average train time: {:.3?} average gen time: {:.3?}", alphabet_size, result.average_train_time, result.average_gen_time);
+        data_str.push_str(&format!("{},{:.3?},{:.3?}\n", alphabet_size, result.average_train_time, result.average_gen_time));
     }
     fs::write("MarkovRunningTimes.csv", data_str).expect("Unable to write to file.");
 }
 
 fn time_analysis_seq_length() {
     let avg_count = 2;
-    let avg_gen_count = 3;
     let alphabet_size = 10;
 
     let mut data_str:String = String::from("sequence_length,average_train_time,average_gen_time\n");
 
     for seq_length in (50..=10000).step_by(50) {
-        // for seq_length in (15..=25).step_by(5) {
-        let mut avg_train_sum = Duration::new(0, 0);
-        let mut avg_gen_sum = Duration::new(0, 0);
-
-        for _ in 0..avg_count {
-
-            // Make arbitrary database
-            let mut data:String = "".to_string();
-            for i in 0..alphabet_size {
-                for j in 0..alphabet_size {
-                    data.push_str(&*format!("{:04}:{:04} ", i, j));
-                }
-                data.push('\n');
-            }
-            // Time Markov model training
+        let result = benchmark(alphabet_size, seq_length, 1, avg_count);
+        println!("Sequence length: {}  average train time: {:.3?} average gen time: {:.3?}", seq_length, result.average_train_time, result.average_gen_time);
+        data_str.push_str(&format!("{},{:.3?},{:.3?}\n", seq_length, result.average_train_time, result.average_gen_time));
+    }
+    fs::write("MarkovRunningTimesLengths.csv", data_str).expect("Unable to write to file.");
+}
+
+/// Average training times of the standard back-to-front and the selective (most constrained
+/// position first) arc-consistency, in that order, on a sequence of `sequence_length` whose
+/// hidden state is fixed at its middle and end
+///
+/// The benchmark corpus cycles through its `alphabet_size` hidden states, so each fixed
+/// state prunes every other state at every earlier position
+pub fn benchmark_selective_pruning(alphabet_size: usize, sequence_length: usize, repeats: usize) -> (Duration, Duration) {
+    let alphabet_size = alphabet_size.max(1);
+    let sequence_length = sequence_length.max(1);
+    let repeats = repeats.max(1);
+    let model = HiddenMarkov::new(1, benchmark_data(alphabet_size, true));
+    let mut hidden_constraints: Vec<Box<dyn Constraint + Send>> = vec![Box::new(EmptyConstraint::new()); sequence_length];
+    for position in [sequence_length / 2, sequence_length - 1] {
+        hidden_constraints[position] = Box::new(MatchesConstraint::new(format!("{:04}", position % alphabet_size)));
+    }
+
+    let mut sums = [Duration::new(0, 0); 2];
+    for (sum, selective_pruning) in sums.iter_mut().zip([false, true]) {
+        for _ in 0..repeats {
+            let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), sequence_length, Some(hidden_constraints.clone()), None)
+                .with_selective_pruning(selective_pruning);
             let start = Instant::now();
-            let model = HiddenMarkov::new(2, data);
-            let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), seq_length,
-                                                                     None, None);
             constrained_model.train();
-            avg_train_sum += start.elapsed();
+            *sum += start.elapsed();
         }
+    }
+    (sums[0] / repeats as u32, sums[1] / repeats as u32)
+}
 
-        // Make arbitrary database
-        let mut data:String = "".to_string();
-        for i in 0..alphabet_size {
-            for j in 0..alphabet_size {
-                data.push_str(&*format!("{:04}:{:04} ", i, j));
-            }
-            data.push('\n');
-        }
-        data.push_str(&*format!("{:04}:{:04} ", 0, alphabet_size-1));
-        data.push_str(&*format!("{:04}:{:04} ", 0, 0));
-        data.push('\n');
+fn time_analysis_selective_pruning() {
+    for seq_length in (100..=1000).step_by(100) {
+        let (standard, selective) = benchmark_selective_pruning(10, seq_length, 2);
+        println!("Sequence length: {}  standard train time: {:.3?} selective train time: {:.3?}", seq_length, standard, selective);
+    }
+}
 
-        // Time Markov model training
-        let model = HiddenMarkov::new(1, data);
-        let mut constrained_model = ConstrainedHiddenMarkov::new(model.clone(), seq_length,
-                                                                 None, None);
-        constrained_model.train();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        for _ in 0..avg_gen_count {
-            let start = Instant::now();
-            let _str = constrained_model.sample_sequence(false);
-            avg_gen_sum += start.elapsed();
-        }
-        println!("Sequence length: {}  total elapsed time: {:.3?}   average train time: {:.3?} average gen time: {:.3?}", seq_length, avg_train_sum, avg_train_sum /avg_count, avg_gen_sum/avg_gen_count);
-        data_str.push_str(&*format!("{},{:.3?},{:.3?}\n", seq_length, avg_train_sum / avg_count, avg_gen_sum / avg_gen_count));
+    #[test]
+    fn benchmark_small_configuration() {
+        let result = benchmark(5, 10, 1, 2);
+        assert!(result.average_train_time > Duration::new(0, 0));
+        assert!(result.average_gen_time > Duration::new(0, 0));
+    }
+
+    #[test]
+    fn benchmark_empty_alphabet() {
+        let result = benchmark(0, 3, 1, 0);
+        assert!(result.average_gen_time > Duration::new(0, 0));
+    }
+
+    #[test]
+    fn benchmark_selective_pruning_small_configuration() {
+        let (standard, selective) = benchmark_selective_pruning(5, 10, 1);
+        assert!(standard > Duration::new(0, 0));
+        assert!(selective > Duration::new(0, 0));
     }
-    fs::write("MarkovRunningTimesLengths.csv", data_str).expect("Unable to write to file.");
 }
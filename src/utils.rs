@@ -1,13 +1,154 @@
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::io::Write;
+use std::io;
+use std::io::{BufRead, Write};
+#[cfg(feature = "gzip")]
+use std::io::Read;
 use rand::seq::SliceRandom;
 use rand::thread_rng;
+use crate::error::ChmmError;
 use crate::{ConstrainedHiddenMarkov, Constraint, EmptyConstraint, MatchesConstraint, MultiConstraint, StartsWithLetterConstraint};
 
 pub(crate) const START_TOKEN: &str = "<<START>>";
+/// Absorbing hidden state (and its observed word) marking the end of a training line
+pub(crate) const END_TOKEN: &str = "<<END>>";
+/// Observed word standing in for words that were rare or unseen in training
+pub(crate) const UNKNOWN_TOKEN: &str = "<<UNK>>";
 
-pub(crate) fn get_data(file_path: String) -> String {
-    return fs::read_to_string(file_path).expect("Unable to read data file");
+/// Joins the words of a quoted multi-word observed chunk such as `"New York":NNP`
+/// inside the model, where states are otherwise split on whitespace
+pub(crate) const CHUNK_JOINER: char = '\u{1f}';
+
+/// Map backing the probability matrices; ordered with the `ordered` feature so
+/// that sampling with a seeded generator and iteration are reproducible across runs
+#[cfg(not(feature = "ordered"))]
+pub(crate) type ProbMap<K, V> = HashMap<K, V>;
+#[cfg(feature = "ordered")]
+pub(crate) type ProbMap<K, V> = std::collections::BTreeMap<K, V>;
+
+pub fn get_data(file_path: &str) -> Result<String, io::Error> {
+    if file_path.ends_with(".gz") {
+        read_gzip(file_path)
+    } else {
+        fs::read_to_string(file_path)
+    }
+}
+
+#[cfg(feature = "gzip")]
+fn read_gzip(file_path: &str) -> Result<String, io::Error> {
+    let mut data = String::new();
+    flate2::read::GzDecoder::new(fs::File::open(file_path)?).read_to_string(&mut data)?;
+    Ok(data)
+}
+
+#[cfg(not(feature = "gzip"))]
+fn read_gzip(file_path: &str) -> Result<String, io::Error> {
+    Err(io::Error::new(io::ErrorKind::Unsupported,
+                       format!("{} is gzip compressed but the gzip feature is disabled", file_path)))
+}
+
+pub const CORPUS_EXTENSION: &str = "txt";
+
+pub fn get_data_dir(dir: &str, extension: &str) -> Result<String, io::Error> {
+    // Concatenate all files with the given extension, sorted by name for determinism
+    let extension = extension.trim_start_matches('.');
+    let mut paths = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == extension) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut contents = vec![];
+    for path in paths {
+        contents.push(fs::read_to_string(path)?);
+    }
+    Ok(contents.join("\n"))
+}
+
+/// Profile of an `observed:hidden` training corpus
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CorpusStats {
+    /// Lines containing at least one token
+    pub line_count: usize,
+    pub token_count: usize,
+    pub observed_words: usize,
+    pub hidden_tags: usize,
+    /// Tokens missing the `:` between observed word and hidden tag,
+    /// and lines with an unclosed quote
+    pub malformed_tokens: Vec<String>,
+}
+
+pub fn corpus_stats(data: &str) -> CorpusStats {
+    let mut stats = CorpusStats::default();
+    let mut observed_words = HashSet::new();
+    let mut hidden_tags = HashSet::new();
+    for line in data.lines().filter(|line| !line.trim().is_empty()) {
+        stats.line_count += 1;
+        let tokens = match split_tokens(line) {
+            Ok(tokens) => tokens,
+            Err(_) => {
+                stats.malformed_tokens.push(String::from(line.trim()));
+                continue
+            },
+        };
+        for token in tokens {
+            stats.token_count += 1;
+            match token.split_once(':') {
+                Some((observed, hidden)) => {
+                    observed_words.insert(observed.to_owned());
+                    hidden_tags.insert(hidden.to_owned());
+                },
+                None => stats.malformed_tokens.push(format_observed(&token, true)),
+            }
+        }
+    }
+    stats.observed_words = observed_words.len();
+    stats.hidden_tags = hidden_tags.len();
+    stats
+}
+
+/// Splits a line into whitespace separated tokens, keeping a quoted observed chunk
+/// such as `"New York":NNP` as one token with its words joined by `CHUNK_JOINER`
+///
+/// A quote opens a chunk at the start of a token and closes it right before the `:`;
+/// any other quote (e.g. in the token `":PUNCT`) is kept as part of the word.
+/// Fails if a chunk is never closed
+pub(crate) fn split_tokens(line: &str) -> Result<Vec<String>, ChmmError> {
+    let mut tokens = vec![];
+    let mut token = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        let next = chars.peek().copied();
+        if c == '"' && !in_quotes && token.is_empty() && next.is_some_and(|next| next != ':' && !next.is_whitespace()) {
+            in_quotes = true;
+        } else if c == '"' && in_quotes && next == Some(':') {
+            in_quotes = false;
+            while token.ends_with(CHUNK_JOINER) { token.pop(); }
+        } else if !c.is_whitespace() {
+            token.push(c);
+        } else if in_quotes {
+            if !token.is_empty() && !token.ends_with(CHUNK_JOINER) { token.push(CHUNK_JOINER) }
+        } else if !token.is_empty() {
+            tokens.push(std::mem::take(&mut token));
+        }
+    }
+    if in_quotes {
+        return Err(ChmmError::UnclosedQuote { token: tokens.len() + 1, text: line.trim().to_owned() })
+    }
+    if !token.is_empty() { tokens.push(token) }
+    Ok(tokens)
+}
+
+/// Formats an observed state for output, turning a multi-word chunk back into
+/// space separated words, quoted when it is followed by its hidden state
+pub(crate) fn format_observed(observed: &str, quoted: bool) -> String {
+    if !observed.contains(CHUNK_JOINER) { return observed.to_owned() }
+    let words = observed.replace(CHUNK_JOINER, " ");
+    if quoted { format!("\"{}\"", words) } else { words }
 }
 
 pub(crate) fn get_test_constraints() -> Vec<Box<dyn Constraint + Send>> {
@@ -24,18 +165,124 @@ pub(crate) fn get_test_constraints() -> Vec<Box<dyn Constraint + Send>> {
     return observed_constraints
 }
 
-pub(crate) fn print_sequences(sequences: Vec<String>) {
+pub fn print_sequences(sequences: Vec<String>) {
     for sequence in sequences {
         println!("{}", sequence);
     }
 }
 
-pub(crate) fn write_sequences(sequences: Vec<String>, output_file: String) {
+pub fn write_sequences(sequences: Vec<String>, output_file: String) {
     let mut file = fs::File::create(output_file).expect("Unable to create file");
     for seq in sequences {
         file.write_all(seq.as_ref()).expect("Unable to write");
-        file.write("\n".as_ref()).expect("Unable to write");
+        file.write_all("\n".as_ref()).expect("Unable to write");
+    }
+}
+
+/// Appends one sequence per line to `output_file`, creating it if needed
+pub(crate) fn append_sequences(sequences: &[String], output_file: &str) -> Result<(), io::Error> {
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(output_file)?;
+    for seq in sequences {
+        file.write_all(seq.as_ref())?;
+        file.write_all("\n".as_ref())?;
+    }
+    file.flush()
+}
+
+/// File next to `output_file` recording how many sequences a checkpointed run has written
+pub(crate) fn progress_file(output_file: &str) -> String {
+    format!("{}.progress", output_file)
+}
+
+/// Writes `total` sequences to `output_file` in chunks of `every`, drawn from
+/// `generate(count)`, recording the count written in the progress file after each chunk
+///
+/// With `resume` the run continues from the last recorded count (dropping any lines
+/// written after it), otherwise it starts over. The progress file is removed once all
+/// sequences are written, so resuming without one (e.g. after a completed run) fails
+/// and leaves `output_file` untouched. Returns the number of sequences generated by this run
+pub fn write_sequences_checkpointed<F: FnMut(usize) -> Vec<String>>(output_file: &str, total: usize, every: usize, resume: bool,
+                                                                          mut generate: F) -> Result<usize, io::Error> {
+    let progress_file = progress_file(output_file);
+    let mut written = 0;
+    if resume {
+        let recorded = fs::read_to_string(&progress_file).map_err(|e| io::Error::new(e.kind(),
+            format!("no progress to resume from in {} ({}); the previous run may have completed", progress_file, e)))?;
+        let recorded: usize = recorded.trim().parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidData,
+            format!("invalid progress count '{}' in {}", recorded.trim(), progress_file)))?;
+        written = truncate_lines(output_file, recorded)?;
+    } else {
+        fs::File::create(output_file)?;
+    }
+
+    let resumed_from = written;
+    while written < total {
+        let sequences = generate(every.max(1).min(total - written));
+        append_sequences(&sequences, output_file)?;
+        written += sequences.len();
+        fs::write(&progress_file, written.to_string())?;
+    }
+    if fs::metadata(&progress_file).is_ok() {
+        fs::remove_file(&progress_file)?;
+    }
+    Ok(written - resumed_from)
+}
+
+/// Cuts `file` after its first `count` complete lines without reading it into memory,
+/// returning the number of lines kept (fewer if the file is shorter)
+fn truncate_lines(file: &str, count: usize) -> Result<usize, io::Error> {
+    let mut reader = io::BufReader::new(fs::File::open(file)?);
+    let (mut kept, mut length) = (0, 0);
+    let mut line = vec![];
+    while kept < count {
+        line.clear();
+        let read = reader.read_until(b'\n', &mut line)?;
+        // A last line without a newline was cut off mid-write
+        if read == 0 || !line.ends_with(b"\n") { break }
+        kept += 1;
+        length += read as u64;
     }
+    fs::OpenOptions::new().write(true).open(file)?.set_len(length)?;
+    Ok(kept)
+}
+
+/// A sequence split into its observed and hidden tokens
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub(crate) struct JsonSequence {
+    pub observed: Vec<String>,
+    pub hidden: Vec<String>,
+}
+
+impl JsonSequence {
+    /// Multi-word chunks become one space separated observed entry; a sequence with
+    /// an unclosed quote is split on whitespace alone
+    pub fn parse(sequence: &str) -> JsonSequence {
+        let mut json_sequence = JsonSequence { observed: vec![], hidden: vec![] };
+        let tokens = split_tokens(sequence)
+            .unwrap_or_else(|_| sequence.split_whitespace().map(String::from).collect());
+        for token in tokens {
+            match token.split_once(':') {
+                Some((observed, hidden)) => {
+                    json_sequence.observed.push(format_observed(observed, false));
+                    json_sequence.hidden.push(String::from(hidden));
+                },
+                None => json_sequence.observed.push(format_observed(&token, false)),
+            }
+        }
+        json_sequence
+    }
+}
+
+pub fn print_sequences_json(sequences: Vec<String>) {
+    let json_sequences: Vec<JsonSequence> = sequences.iter().map(|seq| JsonSequence::parse(seq)).collect();
+    println!("{}", serde_json::to_string_pretty(&json_sequences).expect("Unable to serialize sequences"));
+}
+
+pub fn write_sequences_json(sequences: Vec<String>, output_file: String) -> Result<(), io::Error> {
+    let json_sequences: Vec<JsonSequence> = sequences.iter().map(|seq| JsonSequence::parse(seq)).collect();
+    let file = fs::File::create(output_file)?;
+    serde_json::to_writer_pretty(file, &json_sequences)?;
+    Ok(())
 }
 
 pub(crate) fn generate_unique_sequences(constrained_model: &ConstrainedHiddenMarkov, n: i32, out_of: i32) -> Vec<String>{
@@ -51,4 +298,207 @@ pub(crate) fn generate_unique_sequences(constrained_model: &ConstrainedHiddenMar
     unique.shuffle(&mut thread_rng());
     let count = if unique.len() < n as usize { unique.len() } else { n as usize };
     return unique[0..count].to_owned()
-}
\ No newline at end of file
+}
+
+pub(crate) fn sample_with_counts(model: &ConstrainedHiddenMarkov, n: usize) -> Vec<(String, usize)> {
+    // Count how often each distinct sequence is sampled, most frequent first
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for sequence in model.sample_n(n) {
+        *counts.entry(sequence).or_insert(0) += 1;
+    }
+    let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HiddenMarkov;
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn get_data_gzip_test() {
+        let plain = get_data("data/small_test.txt").unwrap();
+        let decompressed = get_data("data/small_test.txt.gz").unwrap();
+        assert_eq!(plain, decompressed);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn get_data_corrupt_gzip_test() {
+        let file_path = std::env::temp_dir().join(format!("chmm_corrupt_{}.gz", std::process::id()));
+        fs::write(&file_path, "Ted:NNP now:RB").unwrap();
+        let data = get_data(file_path.to_str().unwrap());
+        fs::remove_file(&file_path).unwrap();
+        assert!(data.is_err());
+    }
+
+    #[test]
+    fn write_sequences_json_test() {
+        let output_file = std::env::temp_dir().join(format!("chmm_sequences_{}.json", std::process::id()));
+        let sequences = vec![
+            String::from("Ted:NNP now:RB likes:VBZ red:NN"),
+            String::from("Fred:NNP sees:VBZ"),
+        ];
+        write_sequences_json(sequences, output_file.to_str().unwrap().to_string()).unwrap();
+
+        let written = fs::read_to_string(&output_file).unwrap();
+        fs::remove_file(&output_file).unwrap();
+        let parsed: Vec<JsonSequence> = serde_json::from_str(&written).unwrap();
+        assert_eq!(vec![
+            JsonSequence {
+                observed: vec![String::from("Ted"), String::from("now"), String::from("likes"), String::from("red")],
+                hidden: vec![String::from("NNP"), String::from("RB"), String::from("VBZ"), String::from("NN")],
+            },
+            JsonSequence {
+                observed: vec![String::from("Fred"), String::from("sees")],
+                hidden: vec![String::from("NNP"), String::from("VBZ")],
+            },
+        ], parsed);
+    }
+
+    #[test]
+    fn write_sequences_checkpointed_test() {
+        let temp_dir = std::env::temp_dir();
+        let single_file = temp_dir.join(format!("chmm_single_{}.txt", std::process::id()));
+        let chunked_file = temp_dir.join(format!("chmm_chunked_{}.txt", std::process::id()));
+        let single_file = single_file.to_str().unwrap();
+        let chunked_file = chunked_file.to_str().unwrap();
+        let sequences: Vec<String> = (0..10).map(|i| format!("Ted:NNP {}:CD", i)).collect();
+
+        write_sequences(sequences.clone(), single_file.to_string());
+        let mut next = 0;
+        let mut numbered = |count: usize| {
+            next += count;
+            sequences[next - count..next].to_vec()
+        };
+        assert_eq!(10, write_sequences_checkpointed(chunked_file, 10, 3, false, &mut numbered).unwrap());
+
+        let single = fs::read_to_string(single_file).unwrap();
+        let chunked = fs::read_to_string(chunked_file).unwrap();
+        fs::remove_file(single_file).unwrap();
+        fs::remove_file(chunked_file).unwrap();
+        assert_eq!(single, chunked);
+        assert!(fs::metadata(progress_file(chunked_file)).is_err());
+    }
+
+    #[test]
+    fn write_sequences_checkpointed_resume_test() {
+        let output_file = std::env::temp_dir().join(format!("chmm_resume_{}.txt", std::process::id()));
+        let output_file = output_file.to_str().unwrap();
+        // A crashed run checkpointed 2 sequences but wrote part of the next chunk
+        fs::write(output_file, "first\nsecond\npartial\n").unwrap();
+        fs::write(progress_file(output_file), "2").unwrap();
+
+        let generated = write_sequences_checkpointed(output_file, 4, 5, true, |count| vec![String::from("resumed"); count]).unwrap();
+        let written = fs::read_to_string(output_file).unwrap();
+        fs::remove_file(output_file).unwrap();
+        assert_eq!(2, generated);
+        assert_eq!("first\nsecond\nresumed\nresumed\n", written);
+
+        // Resuming after the run completed and removed its progress file leaves the output alone
+        fs::write(output_file, "first\nsecond\n").unwrap();
+        let result = write_sequences_checkpointed(output_file, 4, 5, true, |count| vec![String::from("resumed"); count]);
+        let written = fs::read_to_string(output_file).unwrap();
+        assert_eq!(io::ErrorKind::NotFound, result.unwrap_err().kind());
+        assert_eq!("first\nsecond\n", written);
+
+        // A progress count past the end of a shorter file keeps only the complete lines
+        fs::write(output_file, "first\nsecond\npart").unwrap();
+        fs::write(progress_file(output_file), "3").unwrap();
+        let generated = write_sequences_checkpointed(output_file, 3, 5, true, |count| vec![String::from("resumed"); count]).unwrap();
+        let written = fs::read_to_string(output_file).unwrap();
+        fs::remove_file(output_file).unwrap();
+        assert_eq!(1, generated);
+        assert_eq!("first\nsecond\nresumed\n", written);
+    }
+
+    #[test]
+    fn corpus_stats_test() {
+        let stats = corpus_stats(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB\n"
+        );
+        assert_eq!(CorpusStats {
+            line_count: 4,
+            token_count: 15,
+            observed_words: 10,
+            hidden_tags: 4,
+            malformed_tokens: vec![],
+        }, stats);
+    }
+
+    #[test]
+    fn corpus_stats_malformed_test() {
+        let stats = corpus_stats("Ted:NNP now likes:VBZ\n\ngreen");
+        assert_eq!(2, stats.line_count);
+        assert_eq!(4, stats.token_count);
+        assert_eq!(vec![String::from("now"), String::from("green")], stats.malformed_tokens);
+
+        let stats = corpus_stats("\"New York\":NNP is:VBZ\n\"New York:NNP is:VBZ");
+        assert_eq!(2, stats.token_count);
+        assert_eq!(vec![String::from("\"New York:NNP is:VBZ")], stats.malformed_tokens);
+    }
+
+    #[test]
+    fn split_tokens_test() {
+        assert_eq!(Ok(vec![String::from("Ted:NNP"), String::from("likes:VBZ")]), split_tokens("Ted:NNP  likes:VBZ"));
+        let tokens = split_tokens("\"New  York\":NNP is:VBZ").unwrap();
+        assert_eq!(vec![format!("New{}York:NNP", CHUNK_JOINER), String::from("is:VBZ")], tokens);
+        // Quotes that do not delimit a chunk are part of the word
+        assert_eq!(Ok(vec![String::from("\":PUNCT"), String::from("say\"s:VBZ")]), split_tokens("\":PUNCT say\"s:VBZ"));
+        assert_eq!(Err(ChmmError::UnclosedQuote { token: 2, text: String::from("is:VBZ \"New York:NNP big:JJ") }),
+                   split_tokens("is:VBZ \"New York:NNP big:JJ"));
+        assert_eq!("\"New York\"", format_observed("New\u{1f}York", true));
+        assert_eq!("New York", format_observed("New\u{1f}York", false));
+        assert_eq!("Boston", format_observed("Boston", true));
+    }
+
+    #[test]
+    fn parse_observed_only_json_sequence() {
+        let parsed = JsonSequence::parse("Ted now");
+        assert_eq!(vec![String::from("Ted"), String::from("now")], parsed.observed);
+        assert!(parsed.hidden.is_empty());
+        let parsed = JsonSequence::parse("\"New York\":NNP is:VBZ");
+        assert_eq!(vec![String::from("New York"), String::from("is")], parsed.observed);
+    }
+
+    #[test]
+    fn get_data_missing_file_test() {
+        let error = get_data("data/does_not_exist.txt").unwrap_err();
+        assert_eq!(io::ErrorKind::NotFound, error.kind());
+    }
+
+    #[test]
+    fn get_data_dir_test() {
+        let dir = std::env::temp_dir().join(format!("chmm_get_data_dir_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("b.txt"), "Mary:NNP likes:VBZ red:NN").unwrap();
+        fs::write(dir.join("a.txt"), "Ted:NNP now:RB likes:VBZ green:NN").unwrap();
+        fs::write(dir.join("notes.md"), "not:a corpus:file").unwrap();
+
+        let data = get_data_dir(dir.to_str().unwrap(), CORPUS_EXTENSION);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!("Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN", data.unwrap());
+    }
+
+    #[test]
+    fn get_data_missing_dir_test() {
+        assert!(get_data_dir("no/such/corpus/dir", CORPUS_EXTENSION).is_err());
+    }
+
+    #[test]
+    fn sample_with_counts_test() {
+        let data = String::from(
+            "Ted:NNP now:RB likes:VBZ green:NN\nMary:NNP likes:VBZ red:NN\nMary:NNP now:RB loves:VBZ red:NN\nFred:NNP sees:VBZ Mary:NNP sometimes:RB"
+        );
+        let model = HiddenMarkov::new(1, data);
+        let mut constrained_model = ConstrainedHiddenMarkov::new(model, 4, None, Some(get_test_constraints()));
+        constrained_model.train();
+
+        let counts = sample_with_counts(&constrained_model, 100);
+        assert_eq!(100, counts.iter().map(|(_, count)| count).sum::<usize>());
+        assert!(counts.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+        assert!(counts.iter().all(|(sequence, _)| sequence.ends_with("red:NN")));
+    }
+}